@@ -0,0 +1,158 @@
+use serde::Serialize;
+
+use crate::configuration::BatteryConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BatteryState {
+    #[default]
+    Normal,
+    /// Warn user to charge
+    Low,
+    /// Body is grounded and torque disabled to protect the battery
+    Critical,
+}
+
+/// Tracks battery state from voltage readings
+///
+/// Voltage sags while servos are under load so a threshold has to be
+/// crossed by several consecutive readings before the state changes.
+pub struct BatteryMonitor {
+    config: BatteryConfig,
+    state: BatteryState,
+    pending_state: BatteryState,
+    pending_readings: usize,
+}
+
+impl BatteryMonitor {
+    pub fn new(config: BatteryConfig) -> Self {
+        Self {
+            config,
+            state: BatteryState::Normal,
+            pending_state: BatteryState::Normal,
+            pending_readings: 0,
+        }
+    }
+
+    pub fn state(&self) -> BatteryState {
+        self.state
+    }
+
+    /// Charge between 0 and 1 estimated linearly between critical and full voltage
+    pub fn level(&self, voltage: f32) -> f32 {
+        ((voltage - self.config.critical_voltage)
+            / (self.config.full_voltage - self.config.critical_voltage))
+            .clamp(0.0, 1.0)
+    }
+
+    fn classify(&self, voltage: f32) -> BatteryState {
+        // state only improves once voltage rises above threshold with margin
+        let margin = |state| {
+            if self.state >= state {
+                self.config.hysteresis_voltage
+            } else {
+                0.0
+            }
+        };
+        if voltage <= self.config.critical_voltage + margin(BatteryState::Critical) {
+            BatteryState::Critical
+        } else if voltage <= self.config.low_voltage + margin(BatteryState::Low) {
+            BatteryState::Low
+        } else {
+            BatteryState::Normal
+        }
+    }
+
+    /// Process new voltage reading. Returns new state if it changed
+    pub fn update(&mut self, voltage: f32) -> Option<BatteryState> {
+        if !self.config.enabled {
+            return None;
+        }
+        let measured_state = self.classify(voltage);
+        if measured_state == self.pending_state {
+            self.pending_readings += 1;
+        } else {
+            self.pending_state = measured_state;
+            self.pending_readings = 1;
+        }
+        if self.pending_state != self.state
+            && self.pending_readings >= self.config.consecutive_readings
+        {
+            self.state = self.pending_state;
+            Some(self.state)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn monitor() -> BatteryMonitor {
+        BatteryMonitor::new(BatteryConfig {
+            consecutive_readings: 3,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn single_sag_is_ignored() {
+        let mut monitor = monitor();
+        assert_eq!(monitor.update(10.0), None);
+        assert_eq!(monitor.update(12.0), None);
+        assert_eq!(monitor.update(10.0), None);
+        assert_eq!(monitor.state(), BatteryState::Normal);
+    }
+
+    #[test]
+    fn consecutive_low_readings_change_state() {
+        let mut monitor = monitor();
+        assert_eq!(monitor.update(11.0), None);
+        assert_eq!(monitor.update(11.0), None);
+        assert_eq!(monitor.update(11.0), Some(BatteryState::Low));
+        assert_eq!(monitor.update(11.0), None);
+        for _ in 0..2 {
+            assert_eq!(monitor.update(10.4), None);
+        }
+        assert_eq!(monitor.update(10.4), Some(BatteryState::Critical));
+    }
+
+    #[test]
+    fn recovery_requires_hysteresis() {
+        let mut monitor = monitor();
+        for _ in 0..3 {
+            monitor.update(11.0);
+        }
+        assert_eq!(monitor.state(), BatteryState::Low);
+        // just above low threshold is still low
+        for _ in 0..3 {
+            assert_eq!(monitor.update(11.2), None);
+        }
+        for _ in 0..2 {
+            monitor.update(11.5);
+        }
+        assert_eq!(monitor.update(11.5), Some(BatteryState::Normal));
+    }
+
+    #[test]
+    fn disabled_monitor_never_changes_state() {
+        let mut monitor = BatteryMonitor::new(BatteryConfig {
+            enabled: false,
+            ..Default::default()
+        });
+        for _ in 0..10 {
+            assert_eq!(monitor.update(0.0), None);
+        }
+    }
+
+    #[test]
+    fn level_is_clamped() {
+        let monitor = monitor();
+        assert_relative_eq!(monitor.level(13.0), 1.0);
+        assert_relative_eq!(monitor.level(9.0), 0.0);
+        assert_relative_eq!(monitor.level(11.55), 0.5, epsilon = 0.001);
+    }
+}
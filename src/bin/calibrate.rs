@@ -0,0 +1,136 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use hopper_rust::{
+    body_controller::{AsyncBodyController, BodyController, SimulatedBodyController},
+    calibration::{calibrate_body, mean_motor_positions, parse_leg_names},
+    configuration::{HopperZenohConfig, SimulatedBodyConfig},
+    error::HopperError,
+    hopper_body_config::HopperConfig,
+    logging,
+    zenoh_remotes::topic_consts::HOPPER_MOTOR_RATE,
+};
+use std::{io::BufRead, path::PathBuf, time::Duration};
+use zenoh::prelude::r#async::*;
+
+/// Calibrate joint offsets of the body config
+///
+/// Stop the hopper service before running, the calibration needs the dynamixel port.
+#[derive(Parser)]
+#[command(author, version)]
+struct Args {
+    /// Body config to start from (.toml)
+    /// If unset uses default value.
+    #[arg(long)]
+    body_config: Option<PathBuf>,
+    /// Calibrated body config is written here (.toml)
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Legs to calibrate such as left_front or lrl_tripod. All legs if unset
+    #[arg(long)]
+    leg: Vec<String>,
+    #[arg(long, default_value = "/dev/dynamixel")]
+    dynamixel_port: String,
+    /// Use simulated motors instead of the dynamixel port
+    #[arg(long)]
+    simulated: bool,
+    /// Readings averaged to reduce noise
+    #[arg(long, default_value_t = 10)]
+    samples: usize,
+
+    /// Sets the level of verbosity
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
+
+fn wait_for_enter() -> Result<()> {
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line)?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Args = Args::parse();
+    logging::setup_tracing(args.verbose);
+    anyhow::ensure!(args.samples > 0, "At least one sample is needed");
+
+    let body_config = match &args.body_config {
+        Some(path) => HopperConfig::load(path)?,
+        None => HopperConfig::default(),
+    };
+
+    let legs = parse_leg_names(&args.leg)?;
+
+    let mut body_controller: Box<dyn BodyController> = if args.simulated {
+        Box::new(SimulatedBodyController::new(
+            &body_config,
+            &SimulatedBodyConfig::default(),
+        )?)
+    } else {
+        let zenoh_config = HopperZenohConfig {
+            connect: vec![],
+            listen: vec![],
+            config_path: None,
+        }
+        .get_zenoh_config()?;
+        let zenoh_session = zenoh::open(zenoh_config)
+            .res()
+            .await
+            .map_err(HopperError::ZenohError)?
+            .into_arc();
+        let motor_rate_publisher = zenoh_session
+            .declare_publisher(HOPPER_MOTOR_RATE)
+            .res()
+            .await
+            .map_err(HopperError::ZenohError)?;
+        Box::new(AsyncBodyController::new(
+            &args.dynamixel_port,
+            body_config.legs.clone(),
+            motor_rate_publisher,
+        )?)
+    };
+
+    body_controller.set_torque(false).await?;
+    println!("Torque disabled. Support the body so that legs hang free.");
+    println!("Place calibrated legs into the reference pose:");
+    println!("  coxa perpendicular to the body");
+    println!("  femur horizontal");
+    println!("  tibia vertical");
+    println!("Press enter when ready");
+    wait_for_enter()?;
+
+    let mut samples = Vec::with_capacity(args.samples);
+    for _ in 0..args.samples {
+        samples.push(body_controller.read_motor_positions().await?);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let measured = mean_motor_positions(&samples).context("No samples read")?;
+
+    let calibrated = calibrate_body(&body_config, &measured, legs);
+    for (old, new) in body_config
+        .legs
+        .as_legs()
+        .iter()
+        .zip(calibrated.legs.as_legs())
+    {
+        if old != &new {
+            println!(
+                "Leg at {}: angle offset {:.3} -> {:.3}, femur correction {:.3} -> {:.3}, tibia correction {:.3} -> {:.3}",
+                old.position,
+                old.angle_offset,
+                new.angle_offset,
+                old.femur_correction,
+                new.femur_correction,
+                old.tibia_correction,
+                new.tibia_correction
+            );
+        }
+    }
+
+    calibrated.save_as_toml(&args.output)?;
+    // make sure hopper can load what we wrote
+    HopperConfig::load(&args.output)?;
+    println!("Calibrated body config written to {:?}", args.output);
+    println!("Start hopper with --body-config {:?}", args.output);
+    Ok(())
+}
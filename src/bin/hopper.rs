@@ -3,27 +3,38 @@ use clap::Parser;
 use hopper_rust::{
     body_controller::{self, BodyController},
     camera::start_camera,
-    configuration::get_configuration,
+    configuration::{get_configuration, BodyControllerType},
     error::HopperError,
     high_five::HighFiveDetector,
-    hopper_body_config, ik_controller,
+    hopper_body_config,
+    ik_controller::{self, odometry::Odometry},
     ioc_container::IocContainer,
     lidar::start_lidar_driver,
     logging,
-    monitoring::start_monitoring_loop,
-    motion_controller,
+    monitoring::{
+        start_body_phase_publisher, start_monitoring_loop, start_servo_telemetry_publisher,
+    },
+    motion_controller::{
+        self, choreography::ChoreographyLibrary, hot_reload::start_body_config_reloader,
+    },
+    navigation::start_navigation_service,
+    obstacle_guard::ObstacleGuard,
     openai::start_openai_controller,
     speech::SpeechService,
     utilities::RateTracker,
     zenoh_remotes::{
+        estop_controller::start_estop_controller,
         face_controller::start_face_controller,
+        pose_publisher::ZenohPosePublisher,
         remote_controller::{simple_zenoh_controller, MoveService},
         speech_controller::start_speech_controller,
-        topic_consts::{HOPPER_CONTROL_LOOP_RATE, HOPPER_MOTOR_RATE, HOPPER_POSE_FRAMES},
+        state_queryable::start_state_queryable,
+        topic_consts::{HOPPER_CONTROL_LOOP_RATE, HOPPER_MOTOR_RATE},
     },
 };
 use std::{
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
     time::Duration,
 };
 use tracing::*;
@@ -61,7 +72,12 @@ async fn main() -> Result<()> {
         .map_err(HopperError::ZenohError)?
         .into_arc();
 
-    let face_controller = hopper_rust::face::FaceController::open(&app_config.base.face_port)?;
+    let face_controller = match app_config.base.body_controller {
+        BodyControllerType::Dynamixel => {
+            hopper_rust::face::FaceController::open(&app_config.base.face_port)?
+        }
+        BodyControllerType::Simulated => hopper_rust::face::FaceController::simulated(),
+    };
     face_controller.larson_scanner(hopper_rust::face::driver::PURPLE)?;
 
     let ioc_container = IocContainer::global_instance();
@@ -79,21 +95,29 @@ async fn main() -> Result<()> {
 
     ioc_container.register(high_five_service_controller);
 
-    let lidar_service_controller =
-        start_lidar_driver(zenoh_session.clone(), &app_config.lidar, high_five_detector).await?;
-
-    ioc_container.register(lidar_service_controller);
+    let obstacle_guard = ObstacleGuard::new(app_config.lidar.obstacle_guard.clone());
+    ioc_container.register(obstacle_guard.clone());
 
-    start_monitoring_loop(zenoh_session.clone()).await?;
+    // shared between the IK controller that integrates it and the lidar mapping
+    let odometry = Arc::new(Mutex::new(Odometry::new()));
 
-    let speech_service = SpeechService::new(
-        app_config.tts_service_config.azure_api_key,
-        app_config.tts_service_config.eleven_labs_api_key,
-        app_config.tts_service_config.cache_dir_path,
-        app_config.tts_service_config.audio_repository_path,
+    let lidar_service_controller = start_lidar_driver(
+        zenoh_session.clone(),
+        &app_config.lidar,
+        high_five_detector,
+        obstacle_guard,
+        odometry.clone(),
+        app_config.base.body_controller,
     )
     .await?;
 
+    ioc_container.register(lidar_service_controller);
+
+    let diagnostics_service = start_monitoring_loop(zenoh_session.clone()).await?;
+    ioc_container.register(diagnostics_service);
+
+    let speech_service = SpeechService::new(app_config.tts_service_config)?;
+
     ioc_container.register(speech_service);
 
     start_speech_controller(
@@ -104,6 +128,7 @@ async fn main() -> Result<()> {
 
     let hopper_body_config = args
         .body_config
+        .as_ref()
         .map(|path| hopper_body_config::HopperConfig::load(Path::new(&path)))
         .unwrap_or_else(|| Ok(hopper_body_config::HopperConfig::default()))?;
 
@@ -113,27 +138,34 @@ async fn main() -> Result<()> {
         .await
         .map_err(HopperError::ZenohError)?;
 
-    let body_controller = body_controller::AsyncBodyController::new(
-        &app_config.base.dynamixel_port,
-        hopper_body_config.legs.clone(),
-        motor_rate_publisher,
-    )?;
-
-    let pose_publisher = zenoh_session
-        .declare_publisher(HOPPER_POSE_FRAMES)
-        .res()
-        .await
-        .map_err(HopperError::ZenohError)?;
-
-    let mut ik_controller = ik_controller::IkController::new(
-        Box::new(body_controller),
-        hopper_body_config,
-        pose_publisher,
-    );
-
-    // TODO (David): Move to some settings system
-    // also maybe tune...
-    ik_controller.set_compliance_slope(64).await?;
+    let body_controller: Box<dyn BodyController> = match app_config.base.body_controller {
+        BodyControllerType::Dynamixel => Box::new(body_controller::AsyncBodyController::new(
+            &app_config.base.dynamixel_port,
+            hopper_body_config.legs.clone(),
+            motor_rate_publisher,
+        )?),
+        BodyControllerType::Simulated => {
+            warn!("Using simulated body controller");
+            Box::new(body_controller::SimulatedBodyController::new(
+                &hopper_body_config,
+                &app_config.base.simulated_body,
+            )?)
+        }
+    };
+
+    let navigation_service =
+        start_navigation_service(zenoh_session.clone(), odometry.clone()).await?;
+    ioc_container.register(navigation_service);
+
+    let pose_publisher = ZenohPosePublisher::new(zenoh_session.clone(), odometry).await?;
+
+    let mut ik_controller =
+        ik_controller::IkController::new(body_controller, hopper_body_config, pose_publisher);
+
+    // TODO (David): maybe tune...
+    ik_controller
+        .set_compliance_slope(app_config.base.servo_telemetry.default_compliance_slope)
+        .await?;
     ik_controller.set_motor_speed(1023).await?;
 
     let motion_controller_rate_publisher = zenoh_session
@@ -145,25 +177,47 @@ async fn main() -> Result<()> {
     let motion_controller_rate_reporter =
         RateTracker::new(Duration::from_secs(1), motion_controller_rate_publisher);
 
+    let choreography_library =
+        ChoreographyLibrary::load_directory(Path::new(&app_config.choreography.directory));
+    info!("Loaded choreographies: {:?}", choreography_library.names());
+    ioc_container.register(choreography_library);
+
     let mut motion_controller = motion_controller::MotionController::new(
         ik_controller,
         motion_controller_rate_reporter,
         high_five_receiver,
+        app_config.base.battery.clone(),
+        app_config.base.servo_telemetry.clone(),
+        app_config.base.terrain.clone(),
+        app_config.base.estop.clone(),
     )
     .await?;
 
-    let dance_service = motion_controller.create_dance_service();
-    ioc_container.register(dance_service);
+    start_estop_controller(zenoh_session.clone(), motion_controller.emergency_stop()).await?;
 
-    start_camera(zenoh_session.clone(), &app_config.camera).await?;
+    start_servo_telemetry_publisher(zenoh_session.clone(), motion_controller.subscribe_status())
+        .await?;
+    start_body_phase_publisher(zenoh_session.clone(), motion_controller.subscribe_status()).await?;
 
-    let open_ai_service = start_openai_controller(
-        &app_config.openai.api_key,
-        &app_config.openai.wakeword_topic_prefix,
+    start_body_config_reloader(
         zenoh_session.clone(),
+        motion_controller.create_dance_service(),
+        args.body_config.map(PathBuf::from),
+        app_config.base.hot_reload.clone(),
     )
     .await?;
 
+    let dance_service = motion_controller.create_dance_service();
+    ioc_container.register(dance_service);
+
+    start_state_queryable(zenoh_session.clone()).await?;
+
+    let camera_service = start_camera(zenoh_session.clone(), &app_config.camera).await?;
+    ioc_container.register(camera_service);
+
+    let open_ai_service =
+        start_openai_controller(&app_config.openai, zenoh_session.clone()).await?;
+
     ioc_container.register(open_ai_service);
 
     // audio is sent to us transcribed now
@@ -190,9 +244,15 @@ async fn main() -> Result<()> {
     let (move_service, receiver) = MoveService::new();
     ioc_container.register(move_service);
 
-    simple_zenoh_controller(&mut motion_controller, zenoh_session.clone(), receiver)
-        .await
-        .context("Controller reader failed")?;
+    simple_zenoh_controller(
+        &mut motion_controller,
+        zenoh_session.clone(),
+        receiver,
+        app_config.choreography.gamepad_buttons,
+        app_config.base.command_watchdog.clone(),
+    )
+    .await
+    .context("Controller reader failed")?;
     info!("Controller stopped");
 
     motion_controller.set_body_state(motion_controller::BodyState::Grounded);
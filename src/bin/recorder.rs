@@ -0,0 +1,88 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use hopper_rust::{
+    configuration::HopperZenohConfig,
+    error::HopperError,
+    logging,
+    recorder::{record, replay, RecorderConfig},
+};
+use std::path::PathBuf;
+use tracing::*;
+use zenoh::prelude::r#async::*;
+
+/// Record and replay Hopper telemetry
+#[derive(Parser)]
+#[command(author, version)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+
+    /// zenoh endpoints to connect to
+    #[arg(long)]
+    connect: Vec<zenoh_config::EndPoint>,
+
+    /// Sets the level of verbosity
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Record topics to MCAP file until ctrl-c
+    Record {
+        /// output .mcap file
+        #[arg(short, long)]
+        output: PathBuf,
+        /// recorded topics (.yaml)
+        /// If unset records all default topics.
+        #[arg(long)]
+        config: Option<PathBuf>,
+    },
+    /// Publish recorded MCAP file back onto zenoh
+    Replay {
+        /// input .mcap file
+        input: PathBuf,
+        /// playback speed multiplier
+        #[arg(long, default_value_t = 1.0)]
+        rate: f64,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Args = Args::parse();
+    logging::setup_tracing(args.verbose);
+    info!("Started recorder");
+
+    let zenoh_config = HopperZenohConfig {
+        connect: args.connect,
+        listen: vec![],
+        config_path: None,
+    }
+    .get_zenoh_config()?;
+    let zenoh_session = zenoh::open(zenoh_config)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?
+        .into_arc();
+
+    match args.command {
+        Command::Record { output, config } => {
+            let config = match config {
+                Some(path) => RecorderConfig::load(&path)?,
+                None => RecorderConfig::default(),
+            };
+            let stop = async {
+                if let Err(error) = tokio::signal::ctrl_c().await {
+                    error!("Failed to wait for ctrl-c: {}", error);
+                }
+            };
+            record(zenoh_session, &config, &output, stop).await?;
+        }
+        Command::Replay { input, rate } => {
+            anyhow::ensure!(rate > 0.0, "Replay rate must be positive");
+            replay(zenoh_session, &input, rate).await?;
+        }
+    }
+    Ok(())
+}
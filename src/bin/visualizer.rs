@@ -1,6 +1,7 @@
 use anyhow::Result;
 use clap::Parser;
 use gilrs::Gilrs;
+use hopper_rust::configuration::{BatteryConfig, EstopConfig, ServoTelemetryConfig, TerrainConfig};
 use hopper_rust::error::HopperError;
 use hopper_rust::utilities::RateTracker;
 use hopper_rust::zenoh_remotes::topic_consts::HOPPER_CONTROL_LOOP_RATE;
@@ -81,6 +82,17 @@ async fn main() -> Result<()> {
         Box::new(visualizer),
         motion_controller_rate_reporter,
         rx,
+        // visualizer has no battery
+        BatteryConfig {
+            enabled: false,
+            ..Default::default()
+        },
+        ServoTelemetryConfig {
+            enabled: false,
+            ..Default::default()
+        },
+        TerrainConfig::default(),
+        EstopConfig::default(),
     )
     .await?;
 
@@ -1,5 +1,9 @@
 pub mod motor_controller;
 pub mod motor_positions;
+pub mod motor_telemetry;
+pub mod simulated_controller;
 
 pub use motor_controller::{AsyncBodyController, BodyController};
 pub use motor_positions::BodyMotorPositions;
+pub use motor_telemetry::{BodyMotorTelemetry, MotorTelemetry};
+pub use simulated_controller::{SimulatedBodyController, SimulatedFaultInjector};
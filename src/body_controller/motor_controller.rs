@@ -1,8 +1,9 @@
 use super::motor_positions::*;
+use super::motor_telemetry::*;
 
 use crate::{
     error::{HopperError, HopperResult},
-    hexapod::{HexapodTypes, ToSyncCommand, TripodLegType},
+    hexapod::{HexapodTypes, LegFlags, ToSyncCommand, TripodLegType},
     hopper_body_config::{BodyConfig, LegConfig},
     utilities::RateTracker,
 };
@@ -13,6 +14,26 @@ use tracing::*;
 use zenoh::publication::Publisher;
 
 const RETRY_COUNT: u32 = 3;
+/// AX present temperature register in °C
+const AX_PRESENT_TEMPERATURE: u8 = 0x2B;
+/// AX present load register. See [`load_from_register`]
+const AX_PRESENT_LOAD: u8 = 0x28;
+
+/// Register value or the error byte the servo replied with instead
+///
+/// AX protocol 1.0 has no hardware error register. The error byte is part of every status
+/// reply and the driver returns replies that have it set as a status error.
+fn from_status_reply<T>(
+    result: Result<T, DynamixelDriverError>,
+) -> Result<Result<T, HardwareErrorFlags>, DynamixelDriverError> {
+    match result {
+        Ok(value) => Ok(Ok(value)),
+        Err(DynamixelDriverError::StatusError(error)) => {
+            Ok(Err(HardwareErrorFlags::from_bits_truncate(error)))
+        }
+        Err(error) => Err(error),
+    }
+}
 
 #[macro_export]
 macro_rules! retry_async {
@@ -54,7 +75,16 @@ pub trait BodyController: Send + Sync {
     async fn set_body_motor_speed(&mut self, speed: HexapodMotorSpeed) -> HopperResult<()>;
     async fn set_torque(&mut self, torque: bool) -> HopperResult<()>;
     async fn read_motor_positions(&mut self) -> HopperResult<BodyMotorPositions>;
+    /// Positions of selected legs in order of [`HexapodTypes::selected_legs`]
+    async fn read_selected_motor_positions(
+        &mut self,
+        legs: LegFlags,
+    ) -> HopperResult<Vec<LegMotorPositions>> {
+        let positions = self.read_motor_positions().await?;
+        Ok(positions.selected_legs(legs).into_iter().copied().collect())
+    }
     async fn read_mean_voltage(&mut self) -> HopperResult<f32>;
+    async fn read_motor_telemetry(&mut self) -> HopperResult<BodyMotorTelemetry>;
     async fn scan_motors(&mut self) -> HopperResult<()>;
     async fn clear_serial_io_buffers(&mut self) -> HopperResult<()>;
 }
@@ -92,6 +122,31 @@ impl AsyncBodyController {
     }
 }
 
+async fn read_leg_positions(
+    driver: &mut DynamixelDriver,
+    leg_config: &LegConfig,
+) -> HopperResult<LegMotorPositions> {
+    let coxa = retry_async!(
+        RETRY_COUNT,
+        driver.read_position_rad(leg_config.coxa_id),
+        driver.clear_io_buffers()
+    )
+    .map_err(|err| HopperError::DynamixelDriverError(leg_config.coxa_id, err))?;
+    let femur = retry_async!(
+        RETRY_COUNT,
+        driver.read_position_rad(leg_config.femur_id),
+        driver.clear_io_buffers()
+    )
+    .map_err(|err| HopperError::DynamixelDriverError(leg_config.femur_id, err))?;
+    let tibia = retry_async!(
+        RETRY_COUNT,
+        driver.read_position_rad(leg_config.tibia_id),
+        driver.clear_io_buffers()
+    )
+    .map_err(|err| HopperError::DynamixelDriverError(leg_config.tibia_id, err))?;
+    Ok(LegMotorPositions::new(coxa, femur, tibia))
+}
+
 #[async_trait]
 impl BodyController for AsyncBodyController {
     async fn move_motors_to(&mut self, positions: &BodyMotorPositions) -> HopperResult<()> {
@@ -224,30 +279,6 @@ impl BodyController for AsyncBodyController {
     }
 
     async fn read_motor_positions(&mut self) -> HopperResult<BodyMotorPositions> {
-        async fn read_leg_positions(
-            driver: &mut DynamixelDriver,
-            leg_config: &LegConfig,
-        ) -> HopperResult<LegMotorPositions> {
-            let coxa = retry_async!(
-                RETRY_COUNT,
-                driver.read_position_rad(leg_config.coxa_id),
-                driver.clear_io_buffers()
-            )
-            .map_err(|err| HopperError::DynamixelDriverError(leg_config.coxa_id, err))?;
-            let femur = retry_async!(
-                RETRY_COUNT,
-                driver.read_position_rad(leg_config.femur_id),
-                driver.clear_io_buffers()
-            )
-            .map_err(|err| HopperError::DynamixelDriverError(leg_config.femur_id, err))?;
-            let tibia = retry_async!(
-                RETRY_COUNT,
-                driver.read_position_rad(leg_config.tibia_id),
-                driver.clear_io_buffers()
-            )
-            .map_err(|err| HopperError::DynamixelDriverError(leg_config.tibia_id, err))?;
-            Ok(LegMotorPositions::new(coxa, femur, tibia))
-        }
         let left_front =
             read_leg_positions(&mut self.driver, self.body_config.left_front()).await?;
         let left_middle =
@@ -269,6 +300,72 @@ impl BodyController for AsyncBodyController {
         ))
     }
 
+    async fn read_selected_motor_positions(
+        &mut self,
+        legs: LegFlags,
+    ) -> HopperResult<Vec<LegMotorPositions>> {
+        let mut positions = Vec::with_capacity(6);
+        for leg_config in self.body_config.selected_legs(legs) {
+            positions.push(read_leg_positions(&mut self.driver, leg_config).await?);
+        }
+        Ok(positions)
+    }
+
+    async fn read_motor_telemetry(&mut self) -> HopperResult<BodyMotorTelemetry> {
+        async fn read_motor(driver: &mut DynamixelDriver, id: u8) -> HopperResult<MotorTelemetry> {
+            let temperature = retry_async!(
+                RETRY_COUNT,
+                async { from_status_reply(driver.read_u8(id, AX_PRESENT_TEMPERATURE).await) },
+                driver.clear_io_buffers()
+            )
+            .map_err(|err| HopperError::DynamixelDriverError(id, err))?;
+            let load = retry_async!(
+                RETRY_COUNT,
+                async { from_status_reply(driver.read_u16(id, AX_PRESENT_LOAD).await) },
+                driver.clear_io_buffers()
+            )
+            .map_err(|err| HopperError::DynamixelDriverError(id, err))?;
+            let mut hardware_error = HardwareErrorFlags::empty();
+            let temperature_c = match temperature {
+                Ok(temperature) => temperature as f32,
+                Err(flags) => {
+                    hardware_error |= flags;
+                    f32::NAN
+                }
+            };
+            let load = match load {
+                Ok(load) => load_from_register(load),
+                Err(flags) => {
+                    hardware_error |= flags;
+                    f32::NAN
+                }
+            };
+            Ok(MotorTelemetry {
+                temperature_c,
+                load,
+                hardware_error,
+            })
+        }
+        async fn read_leg(
+            driver: &mut DynamixelDriver,
+            leg_config: &LegConfig,
+        ) -> HopperResult<TripodLegType<MotorTelemetry>> {
+            Ok(TripodLegType::new(
+                read_motor(driver, leg_config.coxa_id).await?,
+                read_motor(driver, leg_config.femur_id).await?,
+                read_motor(driver, leg_config.tibia_id).await?,
+            ))
+        }
+        Ok(BodyMotorTelemetry::new(
+            read_leg(&mut self.driver, self.body_config.left_front()).await?,
+            read_leg(&mut self.driver, self.body_config.left_middle()).await?,
+            read_leg(&mut self.driver, self.body_config.left_rear()).await?,
+            read_leg(&mut self.driver, self.body_config.right_front()).await?,
+            read_leg(&mut self.driver, self.body_config.right_middle()).await?,
+            read_leg(&mut self.driver, self.body_config.right_rear()).await?,
+        ))
+    }
+
     async fn scan_motors(&mut self) -> HopperResult<()> {
         let ids = self.body_config.get_ids();
         for id in ids {
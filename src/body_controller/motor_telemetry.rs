@@ -0,0 +1,304 @@
+use bitflags::bitflags;
+use serde::Serialize;
+
+use crate::{
+    configuration::ServoTelemetryConfig,
+    hexapod::{HexapodTypes, TripodLegType},
+    hopper::{DiagnosticFloatValue, DiagnosticMessage, DiagnosticStringValue},
+};
+
+bitflags! {
+    /// Error byte of AX status packet
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct HardwareErrorFlags: u8 {
+        const INPUT_VOLTAGE = 0b0000_0001;
+        const ANGLE_LIMIT = 0b0000_0010;
+        const OVERHEATING = 0b0000_0100;
+        const RANGE = 0b0000_1000;
+        const CHECKSUM = 0b0001_0000;
+        const OVERLOAD = 0b0010_0000;
+        const INSTRUCTION = 0b0100_0000;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MotorTelemetry {
+    /// NaN if the servo replied with a hardware error instead
+    ///
+    /// Servo with unreadable temperature or load is treated as strained
+    pub temperature_c: f32,
+    /// Fraction of maximum torque. Positive in counter clockwise direction
+    ///
+    /// NaN if the servo replied with a hardware error instead
+    pub load: f32,
+    pub hardware_error: HardwareErrorFlags,
+}
+
+pub type BodyMotorTelemetry = HexapodTypes<TripodLegType<MotorTelemetry>>;
+
+/// AX present load register has magnitude in bits 0-9 and clockwise direction in bit 10
+pub fn load_from_register(raw: u16) -> f32 {
+    let magnitude = (raw & 0x3FF) as f32 / 1023.0;
+    if raw & 0x400 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+/// Leg names in the same order as `HexapodTypes::as_legs`
+const LEG_NAMES: [&str; 6] = [
+    "left_front",
+    "right_front",
+    "left_middle",
+    "right_middle",
+    "left_rear",
+    "right_rear",
+];
+
+/// Telemetry of every motor keyed by leg and joint such as `left_front/femur`
+pub fn named_motors(telemetry: &BodyMotorTelemetry) -> Vec<(String, MotorTelemetry)> {
+    LEG_NAMES
+        .iter()
+        .zip(telemetry.as_legs())
+        .flat_map(|(leg, motors)| {
+            [
+                ("coxa", motors.coxa()),
+                ("femur", motors.femur()),
+                ("tibia", motors.tibia()),
+            ]
+            .map(|(joint, motor)| (format!("{}/{}", leg, joint), motor))
+        })
+        .collect()
+}
+
+/// Diagnostic message without timestamp
+pub fn to_diagnostic_message(telemetry: &BodyMotorTelemetry) -> DiagnosticMessage {
+    let mut string_values = vec![];
+    let mut float_values = vec![];
+    for (name, motor) in named_motors(telemetry) {
+        float_values.push(DiagnosticFloatValue {
+            key: format!("{}/temperature", name),
+            value: motor.temperature_c,
+        });
+        float_values.push(DiagnosticFloatValue {
+            key: format!("{}/load", name),
+            value: motor.load,
+        });
+        if !motor.hardware_error.is_empty() {
+            string_values.push(DiagnosticStringValue {
+                key: format!("{}/hardware_error", name),
+                value: format!("{:?}", motor.hardware_error),
+            });
+        }
+    }
+    DiagnosticMessage {
+        timestamp: None,
+        string_values,
+        float_values,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ServoHealth {
+    #[default]
+    Normal,
+    /// Compliance is relaxed to lower holding torque
+    Strained,
+    /// Body is grounded and torque disabled to let servos cool down
+    Critical,
+}
+
+/// Decides servo health from telemetry of the whole body
+pub struct ServoHealthMonitor {
+    config: ServoTelemetryConfig,
+    health: ServoHealth,
+}
+
+impl ServoHealthMonitor {
+    pub fn new(config: ServoTelemetryConfig) -> Self {
+        Self {
+            config,
+            health: ServoHealth::Normal,
+        }
+    }
+
+    pub fn health(&self) -> ServoHealth {
+        self.health
+    }
+
+    fn classify_motor(&self, motor: &MotorTelemetry) -> ServoHealth {
+        // health only improves once servos cool down below threshold with margin
+        let margin = |health, hysteresis| {
+            if self.health >= health {
+                hysteresis
+            } else {
+                0.0
+            }
+        };
+        let temperature_margin = |health| margin(health, self.config.temperature_hysteresis_c);
+        let critical_errors = HardwareErrorFlags::OVERHEATING | HardwareErrorFlags::OVERLOAD;
+        // NaN fails every comparison so an unreadable hot servo would look normal
+        let unreadable = motor.temperature_c.is_nan() || motor.load.is_nan();
+        if motor.hardware_error.intersects(critical_errors)
+            || motor.temperature_c
+                >= self.config.critical_temperature_c - temperature_margin(ServoHealth::Critical)
+        {
+            ServoHealth::Critical
+        } else if motor.temperature_c
+            >= self.config.warning_temperature_c - temperature_margin(ServoHealth::Strained)
+            || motor.load.abs()
+                >= self.config.relief_load
+                    - margin(ServoHealth::Strained, self.config.load_hysteresis)
+            || unreadable
+        {
+            ServoHealth::Strained
+        } else {
+            ServoHealth::Normal
+        }
+    }
+
+    /// Process new telemetry. Returns new health if it changed
+    pub fn update(&mut self, telemetry: &BodyMotorTelemetry) -> Option<ServoHealth> {
+        if !self.config.enabled {
+            return None;
+        }
+        let health = named_motors(telemetry)
+            .iter()
+            .map(|(_, motor)| self.classify_motor(motor))
+            .max()
+            .unwrap_or_default();
+        if health != self.health {
+            self.health = health;
+            Some(health)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn body_telemetry(femur: MotorTelemetry) -> BodyMotorTelemetry {
+        let normal = MotorTelemetry {
+            temperature_c: 40.0,
+            ..Default::default()
+        };
+        let leg = TripodLegType::new(normal, normal, normal);
+        let hot_leg = TripodLegType::new(normal, femur, normal);
+        HexapodTypes::new(hot_leg, leg, leg, leg, leg, leg)
+    }
+
+    fn with_temperature(temperature_c: f32) -> BodyMotorTelemetry {
+        body_telemetry(MotorTelemetry {
+            temperature_c,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn load_register_direction() {
+        assert_relative_eq!(load_from_register(1023), 1.0);
+        assert_relative_eq!(load_from_register(1024 + 1023), -1.0);
+        assert_relative_eq!(load_from_register(0), 0.0);
+    }
+
+    #[test]
+    fn motors_are_named_by_leg_and_joint() {
+        let names: Vec<_> = named_motors(&with_temperature(40.0))
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names.len(), 18);
+        assert_eq!(names[1], "left_front/femur");
+        assert_eq!(names[17], "right_rear/tibia");
+    }
+
+    #[test]
+    fn hot_femur_relieves_then_sits_down() {
+        let mut monitor = ServoHealthMonitor::new(ServoTelemetryConfig::default());
+        assert_eq!(monitor.update(&with_temperature(40.0)), None);
+        assert_eq!(
+            monitor.update(&with_temperature(62.0)),
+            Some(ServoHealth::Strained)
+        );
+        assert_eq!(
+            monitor.update(&with_temperature(72.0)),
+            Some(ServoHealth::Critical)
+        );
+        // still too hot to stand up again
+        assert_eq!(monitor.update(&with_temperature(68.0)), None);
+        assert_eq!(
+            monitor.update(&with_temperature(60.0)),
+            Some(ServoHealth::Strained)
+        );
+        assert_eq!(
+            monitor.update(&with_temperature(50.0)),
+            Some(ServoHealth::Normal)
+        );
+    }
+
+    #[test]
+    fn load_relief_has_hysteresis() {
+        let config = ServoTelemetryConfig::default();
+        let with_load = |load| {
+            body_telemetry(MotorTelemetry {
+                temperature_c: 40.0,
+                load,
+                ..Default::default()
+            })
+        };
+        let mut monitor = ServoHealthMonitor::new(config.clone());
+        assert_eq!(
+            monitor.update(&with_load(config.relief_load)),
+            Some(ServoHealth::Strained)
+        );
+        // dipping just below the threshold doesn't restore compliance
+        let just_below = config.relief_load - config.load_hysteresis / 2.0;
+        assert_eq!(monitor.update(&with_load(just_below)), None);
+        assert_eq!(
+            monitor.update(&with_load(
+                config.relief_load - config.load_hysteresis * 2.0
+            )),
+            Some(ServoHealth::Normal)
+        );
+    }
+
+    #[test]
+    fn unreadable_temperature_is_strained() {
+        let mut monitor = ServoHealthMonitor::new(ServoTelemetryConfig::default());
+        let telemetry = body_telemetry(MotorTelemetry {
+            temperature_c: f32::NAN,
+            load: 0.0,
+            hardware_error: HardwareErrorFlags::INPUT_VOLTAGE,
+        });
+        assert_eq!(monitor.update(&telemetry), Some(ServoHealth::Strained));
+        let telemetry = body_telemetry(MotorTelemetry {
+            temperature_c: 40.0,
+            load: f32::NAN,
+            hardware_error: HardwareErrorFlags::INPUT_VOLTAGE,
+        });
+        assert_eq!(monitor.update(&telemetry), None);
+    }
+
+    #[test]
+    fn overload_error_is_critical() {
+        let mut monitor = ServoHealthMonitor::new(ServoTelemetryConfig::default());
+        let telemetry = body_telemetry(MotorTelemetry {
+            temperature_c: 40.0,
+            load: 0.0,
+            hardware_error: HardwareErrorFlags::OVERLOAD,
+        });
+        assert_eq!(monitor.update(&telemetry), Some(ServoHealth::Critical));
+        let message = to_diagnostic_message(&telemetry);
+        assert_eq!(message.string_values.len(), 1);
+        assert_eq!(
+            message.string_values[0].key,
+            "left_front/femur/hardware_error"
+        );
+    }
+}
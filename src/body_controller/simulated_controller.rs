@@ -0,0 +1,495 @@
+use super::motor_controller::{BodyController, HexapodCompliance, HexapodMotorSpeed};
+use super::motor_positions::*;
+use super::motor_telemetry::*;
+
+use crate::{
+    configuration::SimulatedBodyConfig,
+    error::{HopperError, HopperResult},
+    hexapod::{HexapodTypes, TripodLegType},
+    hopper_body_config::{BodyConfig, HopperConfig, LegConfig},
+    ik_controller::calculate_ik,
+    motion_controller::stance,
+};
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tracing::*;
+
+const RETRY_COUNT: u32 = 3;
+
+/// AX-12 moving speed unit is about 0.111 rpm
+const AX_SPEED_UNIT_RAD_PER_SEC: f32 = 0.111 * std::f32::consts::TAU / 60.0;
+/// Speed value 0 means maximum speed without speed control
+const AX_MAX_SPEED_VALUE: u16 = 1023;
+/// AX-12 position unit is about 0.29 degrees
+const AX_POSITION_UNIT_RAD: f32 = 0.29 * std::f32::consts::PI / 180.0;
+/// Servos can move from 0 to 300 degrees
+const AX_MAX_POSITION_RAD: f32 = 300.0 * std::f32::consts::PI / 180.0;
+/// Even inside the compliance slope the motor keeps some of its speed
+const MIN_COMPLIANCE_SPEED_RATIO: f32 = 0.2;
+const ROOM_TEMPERATURE_C: f32 = 25.0;
+
+#[derive(Debug, Clone)]
+struct SimulatedMotor {
+    present_position: f32,
+    goal_position: f32,
+    moving_speed: u16,
+    compliance_slope: u8,
+    torque_enabled: bool,
+    temperature_c: f32,
+    hardware_error: HardwareErrorFlags,
+}
+
+impl SimulatedMotor {
+    fn new(position: f32) -> Self {
+        Self {
+            present_position: position,
+            goal_position: position,
+            moving_speed: AX_MAX_SPEED_VALUE,
+            compliance_slope: 32,
+            torque_enabled: false,
+            temperature_c: ROOM_TEMPERATURE_C,
+            hardware_error: HardwareErrorFlags::empty(),
+        }
+    }
+
+    /// Load grows with position error up to full torque at the edge of the compliance slope
+    fn telemetry(&self) -> MotorTelemetry {
+        let load = if self.torque_enabled {
+            let slope_rad = (self.compliance_slope as f32 * AX_POSITION_UNIT_RAD).max(f32::EPSILON);
+            ((self.goal_position - self.present_position) / slope_rad).clamp(-1.0, 1.0)
+        } else {
+            0.0
+        };
+        MotorTelemetry {
+            temperature_c: self.temperature_c,
+            load,
+            hardware_error: self.hardware_error,
+        }
+    }
+
+    fn max_speed_rad_per_sec(&self) -> f32 {
+        let speed = if self.moving_speed == 0 {
+            AX_MAX_SPEED_VALUE
+        } else {
+            self.moving_speed.min(AX_MAX_SPEED_VALUE)
+        };
+        speed as f32 * AX_SPEED_UNIT_RAD_PER_SEC
+    }
+
+    /// Advance motor towards goal position
+    ///
+    /// Within the compliance slope the motor loses torque the closer it gets to goal
+    /// so it slows down in a similar way the real servo does.
+    fn step(&mut self, elapsed_secs: f32) {
+        if !self.torque_enabled {
+            return;
+        }
+        let error = self.goal_position - self.present_position;
+        let slope_rad = self.compliance_slope as f32 * AX_POSITION_UNIT_RAD;
+        let speed_ratio = if slope_rad > 0.0 {
+            (error.abs() / slope_rad).clamp(MIN_COMPLIANCE_SPEED_RATIO, 1.0)
+        } else {
+            1.0
+        };
+        let max_step = self.max_speed_rad_per_sec() * speed_ratio * elapsed_secs;
+        if error.abs() <= max_step {
+            self.present_position = self.goal_position;
+        } else {
+            self.present_position += max_step.copysign(error);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SimulatedBus {
+    motors: HashMap<u8, SimulatedMotor>,
+    last_update: Instant,
+    voltage: f32,
+    missing_motor_ids: Vec<u8>,
+    timeout_probability: f32,
+}
+
+impl SimulatedBus {
+    fn update(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_update).as_secs_f32();
+        self.last_update = now;
+        for motor in self.motors.values_mut() {
+            motor.step(elapsed);
+        }
+    }
+
+    fn is_present(&self, id: u8) -> bool {
+        !self.missing_motor_ids.contains(&id)
+    }
+
+    /// Sync writes don't get a status response so missing motors are silently ignored
+    fn write<F: FnMut(&mut SimulatedMotor)>(&mut self, id: u8, mut write: F) {
+        if !self.is_present(id) {
+            return;
+        }
+        if let Some(motor) = self.motors.get_mut(&id) {
+            write(motor);
+        }
+    }
+
+    fn read<T, F: Fn(&SimulatedMotor) -> T>(&self, id: u8, read: F) -> HopperResult<T> {
+        let motor = self
+            .motors
+            .get(&id)
+            .filter(|_| self.is_present(id))
+            .ok_or(HopperError::SimulatedMotorMissing(id))?;
+        let mut error_count = 0;
+        while rand::random::<f32>() < self.timeout_probability {
+            error!("Simulated motor {} timed out count {}", id, error_count);
+            error_count += 1;
+            if error_count > RETRY_COUNT {
+                return Err(HopperError::SimulatedMotorTimeout(id));
+            }
+        }
+        Ok(read(motor))
+    }
+
+    fn read_leg_telemetry(
+        &self,
+        leg_config: &LegConfig,
+    ) -> HopperResult<TripodLegType<MotorTelemetry>> {
+        Ok(TripodLegType::new(
+            self.read(leg_config.coxa_id, SimulatedMotor::telemetry)?,
+            self.read(leg_config.femur_id, SimulatedMotor::telemetry)?,
+            self.read(leg_config.tibia_id, SimulatedMotor::telemetry)?,
+        ))
+    }
+
+    fn read_leg(&self, leg_config: &LegConfig) -> HopperResult<LegMotorPositions> {
+        Ok(LegMotorPositions::new(
+            self.read(leg_config.coxa_id, |motor| motor.present_position)?,
+            self.read(leg_config.femur_id, |motor| motor.present_position)?,
+            self.read(leg_config.tibia_id, |motor| motor.present_position)?,
+        ))
+    }
+}
+
+/// Handle for changing faults of a running simulated body
+#[derive(Clone)]
+pub struct SimulatedFaultInjector {
+    bus: Arc<Mutex<SimulatedBus>>,
+}
+
+impl SimulatedFaultInjector {
+    pub fn set_voltage(&self, voltage: f32) {
+        self.bus.lock().unwrap().voltage = voltage;
+    }
+
+    pub fn set_motor_missing(&self, id: u8, missing: bool) {
+        let mut bus = self.bus.lock().unwrap();
+        bus.missing_motor_ids.retain(|missing_id| *missing_id != id);
+        if missing {
+            bus.missing_motor_ids.push(id);
+        }
+    }
+
+    pub fn set_temperature(&self, id: u8, temperature_c: f32) {
+        if let Some(motor) = self.bus.lock().unwrap().motors.get_mut(&id) {
+            motor.temperature_c = temperature_c;
+        }
+    }
+
+    pub fn set_hardware_error(&self, id: u8, hardware_error: HardwareErrorFlags) {
+        if let Some(motor) = self.bus.lock().unwrap().motors.get_mut(&id) {
+            motor.hardware_error = hardware_error;
+        }
+    }
+
+    /// Probability that a single read times out
+    pub fn set_timeout_probability(&self, probability: f32) {
+        self.bus.lock().unwrap().timeout_probability = probability.clamp(0.0, 1.0);
+    }
+}
+
+/// Simulated dynamixel bus with 18 AX motors
+///
+/// Motors start in grounded stance with torque disabled, the same way hopper usually boots.
+pub struct SimulatedBodyController {
+    bus: Arc<Mutex<SimulatedBus>>,
+    body_config: BodyConfig,
+}
+
+impl SimulatedBodyController {
+    pub fn new(
+        hopper_config: &HopperConfig,
+        simulation_config: &SimulatedBodyConfig,
+    ) -> HopperResult<Self> {
+        let starting_positions = calculate_ik(stance::grounded_stance(), hopper_config);
+        let body_config = hopper_config.legs.clone();
+        let mut motors = HashMap::new();
+        for (positions, leg_config) in starting_positions
+            .as_legs()
+            .iter()
+            .zip(body_config.as_legs().iter())
+        {
+            for (id, position) in positions.pair_with_id(leg_config) {
+                motors.insert(id, SimulatedMotor::new(position));
+            }
+        }
+        let bus = SimulatedBus {
+            motors,
+            last_update: Instant::now(),
+            voltage: simulation_config.voltage,
+            missing_motor_ids: simulation_config.missing_motor_ids.clone(),
+            timeout_probability: simulation_config.timeout_probability,
+        };
+        Ok(Self {
+            bus: Arc::new(Mutex::new(bus)),
+            body_config,
+        })
+    }
+
+    pub fn fault_injector(&self) -> SimulatedFaultInjector {
+        SimulatedFaultInjector {
+            bus: self.bus.clone(),
+        }
+    }
+
+    fn write_body<T, F>(&self, values: &HexapodTypes<TripodLegType<T>>, mut write: F)
+    where
+        T: Clone,
+        F: FnMut(&mut SimulatedMotor, T),
+    {
+        let mut bus = self.bus.lock().unwrap();
+        bus.update();
+        for (leg_values, leg_config) in values
+            .as_legs()
+            .iter()
+            .zip(self.body_config.as_legs().iter())
+        {
+            for (id, value) in leg_values.pair_with_id(leg_config) {
+                bus.write(id, |motor| write(motor, value.clone()));
+            }
+        }
+    }
+
+    fn write_all<F: FnMut(&mut SimulatedMotor)>(&self, mut write: F) {
+        let mut bus = self.bus.lock().unwrap();
+        bus.update();
+        for id in self.body_config.get_ids() {
+            bus.write(id, &mut write);
+        }
+    }
+}
+
+#[async_trait]
+impl BodyController for SimulatedBodyController {
+    async fn move_motors_to(&mut self, positions: &BodyMotorPositions) -> HopperResult<()> {
+        self.write_body(positions, |motor, position| {
+            motor.goal_position = position.clamp(0.0, AX_MAX_POSITION_RAD)
+        });
+        Ok(())
+    }
+
+    async fn move_optional_motors_to(
+        &mut self,
+        positions: &OptionalBodyMotorPositions,
+    ) -> HopperResult<()> {
+        self.write_body(positions, |motor, position| {
+            if let Some(position) = position {
+                motor.goal_position = position.clamp(0.0, AX_MAX_POSITION_RAD)
+            }
+        });
+        Ok(())
+    }
+
+    async fn set_compliance_slope(&mut self, compliance: u8) -> HopperResult<()> {
+        self.write_all(|motor| motor.compliance_slope = compliance);
+        Ok(())
+    }
+
+    async fn set_body_compliance_slope(
+        &mut self,
+        compliance: HexapodCompliance,
+    ) -> HopperResult<()> {
+        self.write_body(&compliance, |motor, compliance| {
+            motor.compliance_slope = compliance
+        });
+        Ok(())
+    }
+
+    async fn set_motor_speed(&mut self, speed: u16) -> HopperResult<()> {
+        self.write_all(|motor| motor.moving_speed = speed);
+        Ok(())
+    }
+
+    async fn set_body_motor_speed(&mut self, speed: HexapodMotorSpeed) -> HopperResult<()> {
+        self.write_body(&speed, |motor, speed| motor.moving_speed = speed);
+        Ok(())
+    }
+
+    async fn set_torque(&mut self, torque: bool) -> HopperResult<()> {
+        self.write_all(|motor| {
+            motor.torque_enabled = torque;
+            // servo doesn't jump to old goal when torque is enabled again
+            motor.goal_position = motor.present_position;
+        });
+        Ok(())
+    }
+
+    async fn read_motor_positions(&mut self) -> HopperResult<BodyMotorPositions> {
+        let mut bus = self.bus.lock().unwrap();
+        bus.update();
+        Ok(BodyMotorPositions::new(
+            bus.read_leg(self.body_config.left_front())?,
+            bus.read_leg(self.body_config.left_middle())?,
+            bus.read_leg(self.body_config.left_rear())?,
+            bus.read_leg(self.body_config.right_front())?,
+            bus.read_leg(self.body_config.right_middle())?,
+            bus.read_leg(self.body_config.right_rear())?,
+        ))
+    }
+
+    async fn read_mean_voltage(&mut self) -> HopperResult<f32> {
+        let bus = self.bus.lock().unwrap();
+        let ids = self.body_config.get_ids();
+        let mut sum = 0.0;
+        for id in ids {
+            sum += bus.read(id, |_| bus.voltage)?;
+        }
+        Ok(sum / ids.len() as f32)
+    }
+
+    async fn read_motor_telemetry(&mut self) -> HopperResult<BodyMotorTelemetry> {
+        let mut bus = self.bus.lock().unwrap();
+        bus.update();
+        Ok(BodyMotorTelemetry::new(
+            bus.read_leg_telemetry(self.body_config.left_front())?,
+            bus.read_leg_telemetry(self.body_config.left_middle())?,
+            bus.read_leg_telemetry(self.body_config.left_rear())?,
+            bus.read_leg_telemetry(self.body_config.right_front())?,
+            bus.read_leg_telemetry(self.body_config.right_middle())?,
+            bus.read_leg_telemetry(self.body_config.right_rear())?,
+        ))
+    }
+
+    async fn scan_motors(&mut self) -> HopperResult<()> {
+        let bus = self.bus.lock().unwrap();
+        for id in self.body_config.get_ids() {
+            match bus.read(id, |_| ()) {
+                Ok(()) => info!("Motor {} works", id),
+                Err(err) => error!("Motor {} failing with {:?}", id, err),
+            }
+        }
+        Ok(())
+    }
+
+    async fn clear_serial_io_buffers(&mut self) -> HopperResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn motor_moves_at_configured_speed() {
+        let mut motor = SimulatedMotor::new(1.0);
+        motor.torque_enabled = true;
+        motor.compliance_slope = 0;
+        motor.moving_speed = 100;
+        motor.goal_position = 2.0;
+        motor.step(0.5);
+        let expected = 1.0 + 100.0 * AX_SPEED_UNIT_RAD_PER_SEC * 0.5;
+        assert_relative_eq!(motor.present_position, expected);
+        motor.step(10.0);
+        assert_relative_eq!(motor.present_position, 2.0);
+    }
+
+    #[test]
+    fn motor_without_torque_does_not_move() {
+        let mut motor = SimulatedMotor::new(1.0);
+        motor.goal_position = 2.0;
+        motor.step(10.0);
+        assert_relative_eq!(motor.present_position, 1.0);
+    }
+
+    #[test]
+    fn compliance_slope_slows_motor_near_goal() {
+        let mut stiff = SimulatedMotor::new(1.0);
+        stiff.torque_enabled = true;
+        stiff.compliance_slope = 0;
+        stiff.goal_position = 1.05;
+        let mut compliant = stiff.clone();
+        compliant.compliance_slope = 128;
+        stiff.step(0.001);
+        compliant.step(0.001);
+        assert!(compliant.present_position < stiff.present_position);
+    }
+
+    #[tokio::test]
+    async fn starts_in_grounded_stance() {
+        let config = HopperConfig::default();
+        let mut controller =
+            SimulatedBodyController::new(&config, &SimulatedBodyConfig::default()).unwrap();
+        let positions = controller.read_motor_positions().await.unwrap();
+        let expected = calculate_ik(stance::grounded_stance(), &config);
+        assert_eq!(positions, expected);
+    }
+
+    #[tokio::test]
+    async fn missing_motor_fails_reads() {
+        let config = HopperConfig::default();
+        let mut controller =
+            SimulatedBodyController::new(&config, &SimulatedBodyConfig::default()).unwrap();
+        let missing_id = config.legs.left_front().femur_id;
+        controller
+            .fault_injector()
+            .set_motor_missing(missing_id, true);
+        let error = controller.read_motor_positions().await.unwrap_err();
+        assert!(matches!(error, HopperError::SimulatedMotorMissing(id) if id == missing_id));
+        assert!(controller.read_mean_voltage().await.is_err());
+        controller
+            .fault_injector()
+            .set_motor_missing(missing_id, false);
+        assert!(controller.read_motor_positions().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn timeouts_are_recoverable_errors() {
+        let config = HopperConfig::default();
+        let mut controller =
+            SimulatedBodyController::new(&config, &SimulatedBodyConfig::default()).unwrap();
+        controller.fault_injector().set_timeout_probability(1.0);
+        let error = controller.read_motor_positions().await.unwrap_err();
+        assert!(error.is_recoverable_driver_error());
+    }
+
+    #[tokio::test]
+    async fn reports_configured_voltage() {
+        let config = HopperConfig::default();
+        let mut controller =
+            SimulatedBodyController::new(&config, &SimulatedBodyConfig::default()).unwrap();
+        controller.fault_injector().set_voltage(10.5);
+        assert_relative_eq!(controller.read_mean_voltage().await.unwrap(), 10.5);
+    }
+
+    #[tokio::test]
+    async fn reports_injected_temperature() {
+        let config = HopperConfig::default();
+        let mut controller =
+            SimulatedBodyController::new(&config, &SimulatedBodyConfig::default()).unwrap();
+        let femur_id = config.legs.left_front().femur_id;
+        controller.fault_injector().set_temperature(femur_id, 65.0);
+        let telemetry = controller.read_motor_telemetry().await.unwrap();
+        assert_relative_eq!(telemetry.left_front().femur().temperature_c, 65.0);
+        assert_relative_eq!(
+            telemetry.left_front().coxa().temperature_c,
+            ROOM_TEMPERATURE_C
+        );
+        // torque is disabled on boot
+        assert_relative_eq!(telemetry.left_front().femur().load, 0.0);
+    }
+}
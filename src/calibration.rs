@@ -0,0 +1,196 @@
+//! Joint offset calibration
+//!
+//! Legs are placed by hand into the reference pose with torque disabled.
+//! In the reference pose coxa is perpendicular to the body, femur is horizontal
+//! and tibia is vertical. Offsets are chosen so that the motor positions read in
+//! this pose map exactly onto it.
+
+use nalgebra::{Point3, Vector3};
+
+use crate::{
+    body_controller::{motor_positions::LegMotorPositions, BodyMotorPositions},
+    hexapod::{HexapodTypes, LegFlags, LEGS_IN_ORDER},
+    hopper_body_config::{HopperConfig, LegConfig},
+};
+
+/// Femur and tibia are both at right angles in the reference pose
+const REFERENCE_JOINT_ANGLE: f32 = std::f32::consts::FRAC_PI_2;
+/// Coxa servo position when the leg points straight out
+const COXA_CENTER: f32 = 150.0 * std::f32::consts::PI / 180.0;
+
+/// Direction of the leg pointing straight out of the body
+fn outward_angle(leg_config: &LegConfig) -> f32 {
+    REFERENCE_JOINT_ANGLE.copysign(leg_config.position.y)
+}
+
+/// Foot position of a leg in the reference pose
+pub fn reference_foot_position(body_config: &HopperConfig, leg_config: &LegConfig) -> Point3<f32> {
+    let angle = outward_angle(leg_config);
+    let horizontal = Vector3::new(angle.cos(), angle.sin(), 0.0)
+        * (body_config.coxa_length + body_config.femur_length);
+    leg_config.position + horizontal - Vector3::z() * body_config.tibia_length
+}
+
+/// Leg config with offsets that map measured motor positions onto the reference pose
+pub fn calibrate_leg(
+    body_config: &HopperConfig,
+    leg_config: &LegConfig,
+    measured: &LegMotorPositions,
+) -> LegConfig {
+    // IK mirrors servos with negative correction so keep the side of the old correction
+    let femur_sign = (leg_config.femur_correction + body_config.femur_offset).signum();
+    let tibia_sign = (leg_config.tibia_correction + body_config.tibia_offset).signum();
+    LegConfig {
+        angle_offset: measured.coxa() - COXA_CENTER - outward_angle(leg_config),
+        femur_correction: femur_sign * measured.femur()
+            - body_config.femur_offset
+            - REFERENCE_JOINT_ANGLE,
+        tibia_correction: tibia_sign * measured.tibia()
+            - body_config.tibia_offset
+            - REFERENCE_JOINT_ANGLE,
+        ..leg_config.clone()
+    }
+}
+
+/// Body config with selected legs calibrated
+pub fn calibrate_body(
+    body_config: &HopperConfig,
+    measured: &BodyMotorPositions,
+    legs: LegFlags,
+) -> HopperConfig {
+    let calibrated: Vec<LegConfig> = LEGS_IN_ORDER
+        .iter()
+        .zip(body_config.legs.as_legs())
+        .zip(measured.as_legs())
+        .map(|((leg, leg_config), measured)| {
+            if legs.contains(*leg) {
+                calibrate_leg(body_config, leg_config, measured)
+            } else {
+                leg_config.clone()
+            }
+        })
+        .collect();
+    HopperConfig {
+        legs: HexapodTypes::from_legs([
+            &calibrated[0],
+            &calibrated[1],
+            &calibrated[2],
+            &calibrated[3],
+            &calibrated[4],
+            &calibrated[5],
+        ]),
+        ..body_config.clone()
+    }
+}
+
+/// Leg names such as `left_front` or `lrl_tripod`. All legs if empty
+pub fn parse_leg_names(names: &[String]) -> anyhow::Result<LegFlags> {
+    if names.is_empty() {
+        return Ok(LegFlags::ALL);
+    }
+    Ok(LegFlags::parse_names(names)?)
+}
+
+/// Average of several readings to reduce servo position noise
+pub fn mean_motor_positions(samples: &[BodyMotorPositions]) -> Option<BodyMotorPositions> {
+    if samples.is_empty() {
+        return None;
+    }
+    let count = samples.len() as f32;
+    let legs: Vec<LegMotorPositions> = (0..6)
+        .map(|index| {
+            let (coxa, femur, tibia) = samples.iter().map(|sample| sample.as_legs()[index]).fold(
+                (0.0, 0.0, 0.0),
+                |(coxa, femur, tibia), leg| {
+                    (coxa + leg.coxa(), femur + leg.femur(), tibia + leg.tibia())
+                },
+            );
+            LegMotorPositions::new(coxa / count, femur / count, tibia / count)
+        })
+        .collect();
+    Some(HexapodTypes::from_legs([
+        &legs[0], &legs[1], &legs[2], &legs[3], &legs[4], &legs[5],
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ik_controller::{calculate_fk, calculate_ik, leg_positions::LegPositions};
+    use approx::assert_relative_eq;
+
+    fn reference_pose(body_config: &HopperConfig) -> LegPositions {
+        let feet: Vec<_> = body_config
+            .legs
+            .as_legs()
+            .iter()
+            .map(|leg_config| reference_foot_position(body_config, leg_config))
+            .collect();
+        LegPositions::from_legs([&feet[0], &feet[1], &feet[2], &feet[3], &feet[4], &feet[5]])
+    }
+
+    fn assert_legs_equal(a: &LegConfig, b: &LegConfig) {
+        assert_relative_eq!(a.angle_offset, b.angle_offset, epsilon = 0.001);
+        assert_relative_eq!(a.femur_correction, b.femur_correction, epsilon = 0.001);
+        assert_relative_eq!(a.tibia_correction, b.tibia_correction, epsilon = 0.001);
+    }
+
+    #[test]
+    fn calibrated_config_is_unchanged_when_motors_match() {
+        let config = HopperConfig::default();
+        let measured = calculate_ik(&reference_pose(&config), &config);
+        let calibrated = calibrate_body(&config, &measured, LegFlags::ALL);
+        for (calibrated, original) in calibrated.legs.as_legs().iter().zip(config.legs.as_legs()) {
+            assert_legs_equal(calibrated, original);
+        }
+    }
+
+    #[test]
+    fn swapped_servo_offset_is_recovered() {
+        let config = HopperConfig::default();
+        let reference = reference_pose(&config);
+        let measured = calculate_ik(&reference, &config);
+        // new femur servo sits 0.1 rad off
+        let left_front = measured.left_front();
+        let measured = measured.updated_left_front(LegMotorPositions::new(
+            left_front.coxa(),
+            left_front.femur() + 0.1,
+            left_front.tibia(),
+        ));
+
+        let calibrated = calibrate_body(&config, &measured, LegFlags::LEFT_FRONT);
+        let feet = calculate_fk(&measured, &calibrated);
+        assert_relative_eq!(
+            feet.left_front().coords,
+            reference.left_front().coords,
+            epsilon = 0.001
+        );
+        assert_legs_equal(calibrated.legs.right_front(), config.legs.right_front());
+    }
+
+    #[test]
+    fn calibrated_config_roundtrips_through_toml() {
+        let config = HopperConfig::default();
+        let measured = calculate_ik(&reference_pose(&config), &config);
+        let calibrated = calibrate_body(&config, &measured, LegFlags::ALL);
+        let dir = tempdir::TempDir::new("calibration").unwrap();
+        let path = dir.path().join("hopper.toml");
+        calibrated.save_as_toml(&path).unwrap();
+        assert_eq!(HopperConfig::load(&path).unwrap(), calibrated);
+    }
+
+    #[test]
+    fn no_leg_names_select_all_legs() {
+        assert_eq!(parse_leg_names(&[]).unwrap(), LegFlags::ALL);
+        assert!(parse_leg_names(&["tail".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn mean_of_samples() {
+        let config = HopperConfig::default();
+        let measured = calculate_ik(&reference_pose(&config), &config);
+        let mean = mean_motor_positions(&[measured, measured]).unwrap();
+        assert_relative_eq!(mean.left_rear().femur(), measured.left_rear().femur());
+        assert!(mean_motor_positions(&[]).is_none());
+    }
+}
@@ -1,4 +1,5 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use prost::Message;
 use prost_types::Timestamp;
@@ -43,10 +44,35 @@ pub fn scan_camera() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Latest frame captured by the camera
+#[derive(Clone, Default)]
+pub struct CameraService {
+    latest_frame: Arc<Mutex<Option<(Instant, CompressedImage)>>>,
+}
+
+impl CameraService {
+    /// Latest frame if it's newer than max age
+    pub fn latest_frame(&self, max_age: Duration) -> Option<CompressedImage> {
+        self.latest_frame
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|(capture_time, _)| capture_time.elapsed() <= max_age)
+            .map(|(_, frame)| frame.clone())
+    }
+
+    pub fn update_frame(&self, frame: CompressedImage) {
+        self.latest_frame
+            .lock()
+            .unwrap()
+            .replace((Instant::now(), frame));
+    }
+}
+
 pub async fn start_camera(
     zenoh_session: Arc<Session>,
     config: &CameraConfig,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<CameraService> {
     let dev = Device::new(config.id).expect("Failed to open device");
 
     let image_publisher = zenoh_session
@@ -72,21 +98,27 @@ pub async fn start_camera(
         timestamp: Some(proto_timestamp_now()),
     };
 
-    tokio::spawn(async move {
-        loop {
-            let (buf, meta) = stream.next().unwrap();
-
-            compressed_image.data = buf.to_vec();
-            compressed_image.timestamp = Some(proto_timestamp_from_v4l(meta.timestamp));
-            image_publisher
-                .put(compressed_image.encode_to_vec())
-                .res()
-                .await
-                .map_err(HopperError::ZenohError)
-                .unwrap();
+    let camera_service = CameraService::default();
+
+    tokio::spawn({
+        let camera_service = camera_service.clone();
+        async move {
+            loop {
+                let (buf, meta) = stream.next().unwrap();
+
+                compressed_image.data = buf.to_vec();
+                compressed_image.timestamp = Some(proto_timestamp_from_v4l(meta.timestamp));
+                image_publisher
+                    .put(compressed_image.encode_to_vec())
+                    .res()
+                    .await
+                    .map_err(HopperError::ZenohError)
+                    .unwrap();
+                camera_service.update_frame(compressed_image.clone());
+            }
         }
     });
-    Ok(())
+    Ok(camera_service)
 }
 
 fn proto_timestamp_from_v4l(ts: v4l::Timestamp) -> Timestamp {
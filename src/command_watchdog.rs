@@ -0,0 +1,187 @@
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::{configuration::CommandWatchdogConfig, motion_controller::walking::MoveCommand};
+
+/// Input that move commands come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandSource {
+    /// Gamepad published on zenoh
+    RemoteGamepad,
+    /// Gamepad connected to Hopper
+    LocalGamepad,
+    /// Navigation and other in process clients
+    MoveService,
+    /// hopper.command.v1 move commands
+    TypedCommand,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum WatchdogState {
+    /// Watchdog is disabled in config or for the source of current command
+    Disabled,
+    /// No command yet or current command doesn't move
+    Idle,
+    Fresh,
+    /// Source went silent. Walking slows down
+    Decaying {
+        speed_ratio: f32,
+    },
+    /// Source went silent and walking stopped
+    Stopped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CommandWatchdogStatus {
+    /// Source of current move command
+    pub source: Option<CommandSource>,
+    #[serde(flatten)]
+    pub state: WatchdogState,
+}
+
+/// Stops walking when the source of the current move command goes silent
+///
+/// Only the source that sent the latest command is watched.
+pub struct CommandWatchdog {
+    config: CommandWatchdogConfig,
+    source: Option<CommandSource>,
+    last_heard: Instant,
+}
+
+impl CommandWatchdog {
+    pub fn new(config: CommandWatchdogConfig) -> Self {
+        Self {
+            config,
+            source: None,
+            last_heard: Instant::now(),
+        }
+    }
+
+    /// Source sent a new move command
+    pub fn command_received(&mut self, source: CommandSource, now: Instant) {
+        self.source = Some(source);
+        self.last_heard = now;
+    }
+
+    /// Source is still there even if its command didn't change
+    pub fn feed(&mut self, source: CommandSource, now: Instant) {
+        if self.source == Some(source) {
+            self.last_heard = now;
+        }
+    }
+
+    /// Decay command towards zero once its source went silent
+    pub fn limit(
+        &self,
+        command: MoveCommand,
+        now: Instant,
+    ) -> (MoveCommand, CommandWatchdogStatus) {
+        let status = |state| CommandWatchdogStatus {
+            source: self.source,
+            state,
+        };
+        let source = match self.source {
+            Some(source) => source,
+            None => return (command, status(WatchdogState::Idle)),
+        };
+        let timeout = match self.timeout(source) {
+            Some(timeout) => timeout,
+            None => return (command, status(WatchdogState::Disabled)),
+        };
+        if !command.should_move() {
+            return (command, status(WatchdogState::Idle));
+        }
+        let silence = now.saturating_duration_since(self.last_heard);
+        if silence <= timeout {
+            return (command, status(WatchdogState::Fresh));
+        }
+        let decay = Duration::from_millis(self.config.decay_ms).as_secs_f32();
+        let speed_ratio = if decay > 0.0 {
+            (1.0 - (silence - timeout).as_secs_f32() / decay).max(0.0)
+        } else {
+            0.0
+        };
+        let limited = command
+            .with_direction(command.direction() * speed_ratio)
+            .with_rotation(command.rotation() * speed_ratio);
+        let state = if speed_ratio > 0.0 {
+            WatchdogState::Decaying { speed_ratio }
+        } else {
+            WatchdogState::Stopped
+        };
+        (limited, status(state))
+    }
+
+    fn timeout(&self, source: CommandSource) -> Option<Duration> {
+        if !self.config.enabled {
+            return None;
+        }
+        let timeout_ms = match source {
+            CommandSource::RemoteGamepad => self.config.remote_gamepad_timeout_ms,
+            CommandSource::LocalGamepad => self.config.local_gamepad_timeout_ms,
+            CommandSource::MoveService => self.config.move_service_timeout_ms,
+            CommandSource::TypedCommand => self.config.typed_command_timeout_ms,
+        };
+        // zero disables the watchdog for this source
+        (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use nalgebra::Vector2;
+
+    fn walking() -> MoveCommand {
+        MoveCommand::new(Vector2::new(0.02, 0.0), 0.1)
+    }
+
+    #[test]
+    fn fresh_command_passes_through() {
+        let mut watchdog = CommandWatchdog::new(CommandWatchdogConfig::default());
+        let start = Instant::now();
+        watchdog.command_received(CommandSource::RemoteGamepad, start);
+        let (limited, status) = watchdog.limit(walking(), start + Duration::from_millis(100));
+        assert_eq!(limited, walking());
+        assert_eq!(status.state, WatchdogState::Fresh);
+    }
+
+    #[test]
+    fn silent_source_decays_to_stop() {
+        let config = CommandWatchdogConfig::default();
+        let mut watchdog = CommandWatchdog::new(config.clone());
+        let start = Instant::now();
+        watchdog.command_received(CommandSource::RemoteGamepad, start);
+
+        let half_decay =
+            Duration::from_millis(config.remote_gamepad_timeout_ms + config.decay_ms / 2);
+        let (limited, status) = watchdog.limit(walking(), start + half_decay);
+        assert_relative_eq!(limited.direction().x, 0.01, epsilon = 0.0001);
+        assert_relative_eq!(limited.rotation(), 0.05, epsilon = 0.0001);
+        assert!(matches!(status.state, WatchdogState::Decaying { .. }));
+
+        let after_decay = Duration::from_millis(config.remote_gamepad_timeout_ms + config.decay_ms);
+        let (limited, status) = watchdog.limit(walking(), start + after_decay);
+        assert!(!limited.should_move());
+        assert_eq!(status.state, WatchdogState::Stopped);
+    }
+
+    #[test]
+    fn only_current_source_feeds_watchdog() {
+        let config = CommandWatchdogConfig::default();
+        let mut watchdog = CommandWatchdog::new(config.clone());
+        let start = Instant::now();
+        watchdog.command_received(CommandSource::RemoteGamepad, start);
+        let late = start + Duration::from_millis(config.remote_gamepad_timeout_ms * 2);
+        watchdog.feed(CommandSource::LocalGamepad, late);
+        let (_, status) = watchdog.limit(walking(), late);
+        assert_ne!(status.state, WatchdogState::Fresh);
+        watchdog.feed(CommandSource::RemoteGamepad, late);
+        let (_, status) = watchdog.limit(walking(), late);
+        assert_eq!(status.state, WatchdogState::Fresh);
+    }
+}
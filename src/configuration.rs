@@ -5,6 +5,7 @@ use tracing::*;
 use zenoh::config::Config as ZenohConfig;
 
 use crate::error::HopperError;
+use crate::input::{Button, ESTOP_BUTTONS, ESTOP_CLEAR_BUTTONS};
 
 /// Use default config if no path is provided
 pub fn get_configuration(config: &Option<PathBuf>) -> Result<HopperConfig, anyhow::Error> {
@@ -27,7 +28,9 @@ pub fn get_configuration(config: &Option<PathBuf>) -> Result<HopperConfig, anyho
             .build()?
     };
 
-    Ok(settings.try_deserialize()?)
+    let config: HopperConfig = settings.try_deserialize()?;
+    config.validate()?;
+    Ok(config)
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -38,20 +41,315 @@ pub struct HopperConfig {
     pub zenoh: HopperZenohConfig,
     pub camera: CameraConfig,
     pub openai: HopperOpenAiConfig,
+    #[serde(default)]
+    pub choreography: ChoreographyConfig,
+}
+
+impl HopperConfig {
+    /// Catch values that deserialize fine but can't be used
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.lidar.occupancy_grid.validate()?;
+        self.base.battery.validate()?;
+        self.choreography.validate()
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct BaseConfig {
     pub dynamixel_port: String,
     pub face_port: String,
+    #[serde(default)]
+    pub body_controller: BodyControllerType,
+    #[serde(default)]
+    pub simulated_body: SimulatedBodyConfig,
+    #[serde(default)]
+    pub battery: BatteryConfig,
+    #[serde(default)]
+    pub servo_telemetry: ServoTelemetryConfig,
+    #[serde(default)]
+    pub hot_reload: HotReloadConfig,
+    #[serde(default)]
+    pub terrain: TerrainConfig,
+    #[serde(default)]
+    pub estop: EstopConfig,
+    #[serde(default)]
+    pub command_watchdog: CommandWatchdogConfig,
+}
+
+/// Walking stops when the source of the current move command goes silent
+///
+/// Timeout of zero disables the watchdog for that source
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CommandWatchdogConfig {
+    pub enabled: bool,
+    /// Gamepad messages on `remote-control/gamepad`
+    pub remote_gamepad_timeout_ms: u64,
+    /// Gamepad connected to Hopper
+    pub local_gamepad_timeout_ms: u64,
+    /// Navigation and other in process clients
+    pub move_service_timeout_ms: u64,
+    /// Move commands on `hopper/command/v1`
+    pub typed_command_timeout_ms: u64,
+    /// Walking slows down to a stop over this time after a timeout
+    pub decay_ms: u64,
+}
+
+impl Default for CommandWatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            remote_gamepad_timeout_ms: 500,
+            local_gamepad_timeout_ms: 500,
+            move_service_timeout_ms: 1000,
+            typed_command_timeout_ms: 1000,
+            decay_ms: 500,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct EstopConfig {
+    pub action: EstopAction,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EstopAction {
+    /// Disable torque right away. Legs go limp and the body drops
+    #[default]
+    Compliant,
+    /// Sit down if standing before disabling torque
+    SitDown,
+}
+
+/// Terrain adaptive foot placement
+///
+/// Disabled by default because leg positions have to be read every tick while walking
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TerrainConfig {
+    pub enabled: bool,
+    /// Swinging foot touched the ground once it's held this far above its commanded height
+    pub contact_height_error: f32,
+    /// Swinging feet descend at most this far below the planned touchdown height
+    pub max_step_down: f32,
+    /// Highest ground a foot can be placed on above the planned touchdown height
+    pub max_step_up: f32,
+    /// Body moves towards mean ground height at this speed in m/s
+    pub leveling_rate: f32,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            contact_height_error: 0.008,
+            max_step_down: 0.03,
+            max_step_up: 0.03,
+            leveling_rate: 0.02,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HotReloadConfig {
+    pub enabled: bool,
+    /// Stance files such as `relaxed.toml` are watched here
+    pub stance_directory: String,
+    /// Watched files are checked for changes this often
+    pub poll_period_ms: u64,
+}
+
+impl Default for HotReloadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            stance_directory: "/etc/hopper/stance".to_string(),
+            poll_period_ms: 1000,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ServoTelemetryConfig {
+    pub enabled: bool,
+    /// Telemetry of all motors is read this often while not walking
+    pub read_period_ms: u64,
+    /// Compliance slope set on boot. Restored after relief unless compliance was changed since
+    pub default_compliance_slope: u8,
+    /// Softer compliance slope used to relieve hot or strained servos
+    pub relief_compliance_slope: u8,
+    /// Relax compliance above this temperature
+    pub warning_temperature_c: f32,
+    /// Sit down and disable torque above this temperature
+    pub critical_temperature_c: f32,
+    /// Servos have to cool down this much below a threshold before health improves
+    pub temperature_hysteresis_c: f32,
+    /// Relax compliance when any servo exceeds this fraction of maximum torque
+    pub relief_load: f32,
+    /// Load has to drop this much below `relief_load` before health improves
+    pub load_hysteresis: f32,
+}
+
+impl Default for ServoTelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            read_period_ms: 5000,
+            default_compliance_slope: 64,
+            relief_compliance_slope: 128,
+            warning_temperature_c: 60.0,
+            critical_temperature_c: 70.0,
+            temperature_hysteresis_c: 5.0,
+            relief_load: 0.9,
+            load_hysteresis: 0.1,
+        }
+    }
+}
+
+/// Defaults are for a 3 cell LiPo
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BatteryConfig {
+    pub enabled: bool,
+    /// Voltage of a fully charged battery. Used to estimate battery level
+    pub full_voltage: f32,
+    /// Warn by voice and face below this voltage
+    pub low_voltage: f32,
+    /// Sit down and disable torque below this voltage
+    pub critical_voltage: f32,
+    /// Voltage has to rise this much above a threshold before the state improves
+    pub hysteresis_voltage: f32,
+    /// Readings needed to change state. Voltage sags while servos are under load
+    pub consecutive_readings: usize,
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            full_voltage: 12.6,
+            low_voltage: 11.1,
+            critical_voltage: 10.5,
+            hysteresis_voltage: 0.2,
+            consecutive_readings: 5,
+        }
+    }
+}
+
+impl BatteryConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        // battery level is interpolated between critical and full voltage
+        if !(self.critical_voltage < self.low_voltage && self.low_voltage < self.full_voltage) {
+            anyhow::bail!(
+                "Battery voltages have to be ordered critical < low < full, got {} < {} < {}",
+                self.critical_voltage,
+                self.low_voltage,
+                self.full_voltage
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BodyControllerType {
+    /// Real motors on the dynamixel port
+    #[default]
+    Dynamixel,
+    /// Simulated motors for running without hardware
+    Simulated,
+}
+
+fn default_simulated_voltage() -> f32 {
+    12.0
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SimulatedBodyConfig {
+    #[serde(default = "default_simulated_voltage")]
+    pub voltage: f32,
+    /// Motors that don't respond on the bus
+    #[serde(default)]
+    pub missing_motor_ids: Vec<u8>,
+    /// Probability that a single read times out
+    #[serde(default)]
+    pub timeout_probability: f32,
+}
+
+impl Default for SimulatedBodyConfig {
+    fn default() -> Self {
+        Self {
+            voltage: default_simulated_voltage(),
+            missing_motor_ids: vec![],
+            timeout_probability: 0.0,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct TtsServiceConfig {
+    /// Empty key disables Azure
+    #[serde(default)]
     pub azure_api_key: String,
+    /// Empty key disables ElevenLabs
+    #[serde(default)]
     pub eleven_labs_api_key: String,
+    #[serde(default = "default_eleven_labs_voice")]
+    pub eleven_labs_voice: String,
     pub cache_dir_path: Option<String>,
     pub audio_repository_path: Option<String>,
+    /// Backends tried in order until one succeeds
+    #[serde(default = "default_tts_backends")]
+    pub backends: Vec<TtsBackendKind>,
+    #[serde(default)]
+    pub local: LocalTtsConfig,
+}
+
+fn default_eleven_labs_voice() -> String {
+    "Natasha".to_string()
+}
+
+fn default_tts_backends() -> Vec<TtsBackendKind> {
+    vec![
+        TtsBackendKind::ElevenLabs,
+        TtsBackendKind::Azure,
+        TtsBackendKind::Local,
+    ]
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TtsBackendKind {
+    Azure,
+    ElevenLabs,
+    /// Offline engine run as a subprocess
+    Local,
+}
+
+/// Command that reads text on stdin and writes a wav file to stdout
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LocalTtsConfig {
+    pub command: String,
+    pub args: Vec<String>,
+    /// Engine is killed if it doesn't finish in time
+    pub timeout_ms: u64,
+}
+
+impl Default for LocalTtsConfig {
+    fn default() -> Self {
+        Self {
+            command: "espeak-ng".to_string(),
+            args: vec!["--stdout".to_string(), "--stdin".to_string()],
+            timeout_ms: 10000,
+        }
+    }
 }
 
 fn default_wakeword_topic_prefix() -> String {
@@ -64,6 +362,44 @@ pub struct HopperOpenAiConfig {
     pub api_key: String,
     #[serde(default = "default_wakeword_topic_prefix")]
     pub wakeword_topic_prefix: String,
+    #[serde(default = "default_openai_model")]
+    pub model: String,
+    /// OpenAI compatible server such as llama.cpp or vLLM. Uses OpenAI if unset
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// Replaces the built in personality
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+    /// Uses server default if unset
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    /// Model used to describe camera images. Uses `model` if unset
+    #[serde(default)]
+    pub vision_model: Option<String>,
+    #[serde(default)]
+    pub memory: ConversationMemoryConfig,
+}
+
+fn default_openai_model() -> String {
+    "gpt-4o-2024-05-13".to_string()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ConversationMemoryConfig {
+    /// File the conversation is persisted to. Memory is lost on restart if unset
+    pub path: Option<String>,
+    /// Estimated token count above which older turns are summarized
+    pub max_tokens: usize,
+}
+
+impl Default for ConversationMemoryConfig {
+    fn default() -> Self {
+        Self {
+            path: Some("/var/lib/hopper/conversation_memory.json".to_string()),
+            max_tokens: 4000,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -72,6 +408,79 @@ pub struct LidarConfig {
     pub state_topic: String,
     pub point_cloud_topic: String,
     pub start_state_on: bool,
+    #[serde(default)]
+    pub obstacle_guard: ObstacleGuardConfig,
+    #[serde(default)]
+    pub occupancy_grid: OccupancyGridConfig,
+}
+
+/// Distances are measured from the center of the body
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ObstacleGuardConfig {
+    pub enabled: bool,
+    /// Walking towards obstacles closer than this is stopped
+    pub stop_distance_m: f32,
+    /// Walking towards obstacles closer than this is slowed down
+    pub slow_distance_m: f32,
+    /// Half width of the corridor in direction of travel that is checked for obstacles
+    pub corridor_half_width_m: f32,
+    /// Scans older than this are ignored
+    pub max_scan_age_ms: u64,
+}
+
+impl Default for ObstacleGuardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            stop_distance_m: 0.25,
+            slow_distance_m: 0.5,
+            corridor_half_width_m: 0.15,
+            max_scan_age_ms: 1000,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct OccupancyGridConfig {
+    pub enabled: bool,
+    pub topic: String,
+    /// Size of a single cell
+    pub resolution_m: f32,
+    /// Number of cells along each side. Grid is kept centered on the robot
+    pub size_cells: usize,
+    /// Rate at which evidence in cells fades
+    pub decay_per_second: f32,
+    pub publish_period_ms: u64,
+}
+
+impl Default for OccupancyGridConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            topic: "hopper/lidar/occupancy_grid".to_string(),
+            resolution_m: 0.05,
+            size_cells: 160,
+            decay_per_second: 0.05,
+            publish_period_ms: 1000,
+        }
+    }
+}
+
+impl OccupancyGridConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        if !(self.resolution_m.is_finite() && self.resolution_m > 0.0) {
+            anyhow::bail!(
+                "Occupancy grid resolution has to be positive, got {}",
+                self.resolution_m
+            );
+        }
+        if self.size_cells == 0 {
+            anyhow::bail!("Occupancy grid needs at least one cell");
+        }
+        Ok(())
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -106,6 +515,48 @@ pub struct CameraConfig {
     pub image_height: u32,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ChoreographyConfig {
+    /// Directory with choreography files (.toml or .yaml)
+    pub directory: String,
+    pub gamepad_buttons: Vec<ChoreographyButton>,
+}
+
+impl Default for ChoreographyConfig {
+    fn default() -> Self {
+        Self {
+            directory: "/etc/hopper/choreography".to_string(),
+            gamepad_buttons: vec![],
+        }
+    }
+}
+
+impl ChoreographyConfig {
+    /// Emergency stop buttons can't start dances
+    fn validate(&self) -> anyhow::Result<()> {
+        for choreography_button in &self.gamepad_buttons {
+            if ESTOP_BUTTONS.contains(&choreography_button.button)
+                || ESTOP_CLEAR_BUTTONS.contains(&choreography_button.button)
+            {
+                anyhow::bail!(
+                    "Choreography {} can't be mapped to {:?} which is part of the emergency stop combo",
+                    choreography_button.choreography,
+                    choreography_button.button
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Gamepad button that starts a choreography by name
+#[derive(Deserialize, Debug, Clone)]
+pub struct ChoreographyButton {
+    pub button: Button,
+    pub choreography: String,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -121,6 +572,59 @@ mod tests {
             ))
             .build()
             .unwrap();
-        builder.try_deserialize::<HopperConfig>().unwrap();
+        let config = builder.try_deserialize::<HopperConfig>().unwrap();
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn choreography_on_estop_button_is_rejected() {
+        let config = ChoreographyConfig {
+            directory: String::new(),
+            gamepad_buttons: vec![ChoreographyButton {
+                button: Button::Mode,
+                choreography: String::from("bow"),
+            }],
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn occupancy_grid_without_size_is_rejected() {
+        let config = OccupancyGridConfig {
+            resolution_m: 0.0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        let config = OccupancyGridConfig {
+            size_cells: 0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        assert!(OccupancyGridConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn unordered_battery_voltages_are_rejected() {
+        let config = BatteryConfig {
+            critical_voltage: 12.6,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        let config = BatteryConfig {
+            low_voltage: 10.0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        assert!(BatteryConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn tts_config_without_keys_uses_defaults() {
+        let config: TtsServiceConfig = serde_yaml::from_str("cache_dir_path: null").unwrap();
+        assert!(config.azure_api_key.is_empty());
+        assert_eq!(config.eleven_labs_voice, "Natasha");
+        assert_eq!(config.backends, default_tts_backends());
+        assert_eq!(config.local.command, "espeak-ng");
+        assert_eq!(config.local.timeout_ms, 10000);
     }
 }
@@ -10,6 +10,8 @@ pub type HopperResult<T> = Result<T, HopperError>;
 pub enum HopperError {
     #[error("Generic IK error")]
     GenericIkError,
+    #[error("{0}")]
+    IkError(#[from] crate::ik_controller::validation::IkError),
     #[error("Dynamixel sync write error {0:?}")]
     DynamixelSyncWriteError(#[source] DynamixelDriverError),
     #[error("Dynamixel driver error ID ({0}) {1:?}")]
@@ -47,6 +49,16 @@ pub enum HopperError {
     WrongNumberOfLogs(usize, usize),
     #[error("Gilrs error {0:?}")]
     GilrsError(String),
+    #[error("Simulated motor ID ({0}) timed out")]
+    SimulatedMotorTimeout(u8),
+    #[error("Simulated motor ID ({0}) is missing")]
+    SimulatedMotorMissing(u8),
+    #[error("Body config can't change motor ids at runtime")]
+    MotorIdsChanged,
+    #[error("Emergency stop latched")]
+    EmergencyStop,
+    #[error("Unknown leg name {0}")]
+    UnknownLegName(String),
 }
 
 impl HopperError {
@@ -54,6 +66,7 @@ impl HopperError {
         match self {
             HopperError::DynamixelSyncWriteError(error) => error.is_recoverable(),
             HopperError::DynamixelDriverError(_id, error) => error.is_recoverable(),
+            HopperError::SimulatedMotorTimeout(_id) => true,
             _ => false,
         }
     }
@@ -57,6 +57,35 @@ impl Animation {
             Animation::Off => Box::new(SolidColor::off()),
         }
     }
+
+    /// Name as accepted on the face animation topic
+    pub fn name(&self) -> &'static str {
+        match self {
+            Animation::LarsonScanner(_) => "larson_scanner",
+            Animation::RunAnimation(_) => "run_animation",
+            Animation::CycleAllColors => "cycle_all_colors",
+            Animation::CycleBrightColors => "cycle_bright_colors",
+            Animation::CycleNormalColors => "cycle_normal_colors",
+            Animation::CountDownBasic => "count_down_basic",
+            Animation::CountDown(_) => "count_down",
+            Animation::Breathing(_) => "breathing",
+            Animation::SolidColor(_) => "solid_color",
+            Animation::SpeakingRandom(_) | Animation::Speaking(_, _) => "speaking",
+            Animation::Off => "off",
+        }
+    }
+
+    pub fn color(&self) -> Option<RGB> {
+        match self {
+            Animation::LarsonScanner(color)
+            | Animation::RunAnimation(color)
+            | Animation::Breathing(color)
+            | Animation::SolidColor(color)
+            | Animation::SpeakingRandom(color)
+            | Animation::Speaking(color, _) => Some(*color),
+            _ => None,
+        }
+    }
 }
 
 pub struct SolidColor {
@@ -1,4 +1,5 @@
 use cobs_rs::stuff;
+use serde::Serialize;
 use serialport::TTYPort;
 use std::{convert::TryInto, io::Write};
 use thiserror::Error;
@@ -83,7 +84,7 @@ fn corrected_pixel_index(index: i32) -> usize {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
 pub struct RGB {
     red: u8,
     green: u8,
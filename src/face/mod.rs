@@ -76,6 +76,23 @@ impl FaceController {
         })
     }
 
+    /// Face without LEDs that accepts and drops all animations
+    pub fn simulated() -> Self {
+        let (sender, rx) = sync_channel(5);
+        let join_handle = spawn(move || {
+            while let Ok(message) = rx.recv() {
+                if let ColorCommand::Exit = message {
+                    break;
+                }
+            }
+        });
+        FaceController {
+            sender,
+            thread_handle: Some(join_handle),
+            last_animation: Mutex::new(None),
+        }
+    }
+
     pub fn larson_scanner(&self, color: RGB) -> Result<()> {
         self.set_animation(Animation::LarsonScanner(color))
     }
@@ -232,6 +232,28 @@ bitflags! {
     }
 }
 
+impl LegFlags {
+    /// Combine legs or leg groups named like `left_front` or `lrl_tripod`. Case insensitive
+    pub fn parse_names<S: AsRef<str>>(names: &[S]) -> HopperResult<Self> {
+        names.iter().try_fold(LegFlags::empty(), |legs, name| {
+            let name = name.as_ref();
+            LegFlags::from_name(&name.to_uppercase())
+                .map(|leg| legs | leg)
+                .ok_or_else(|| HopperError::UnknownLegName(name.to_owned()))
+        })
+    }
+}
+
+/// Legs in the same order as `HexapodTypes::as_legs`
+pub(crate) const LEGS_IN_ORDER: [LegFlags; 6] = [
+    LegFlags::LEFT_FRONT,
+    LegFlags::RIGHT_FRONT,
+    LegFlags::LEFT_MIDDLE,
+    LegFlags::RIGHT_MIDDLE,
+    LegFlags::LEFT_REAR,
+    LegFlags::RIGHT_REAR,
+];
+
 impl<T: Clone + Copy> Copy for HexapodTypes<T> {}
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
@@ -301,6 +323,20 @@ mod tests {
 
     type TestingHexapodType = HexapodTypes<usize>;
 
+    #[test]
+    fn leg_names_are_parsed() {
+        assert_eq!(
+            LegFlags::parse_names(&["left_front", "Right_Rear"]).unwrap(),
+            LegFlags::LEFT_FRONT | LegFlags::RIGHT_REAR
+        );
+        assert_eq!(
+            LegFlags::parse_names(&["lrl_tripod"]).unwrap(),
+            LegFlags::LRL_TRIPOD
+        );
+        assert!(LegFlags::parse_names::<&str>(&[]).unwrap().is_empty());
+        assert!(LegFlags::parse_names(&["tail"]).is_err());
+    }
+
     #[test]
     fn from_legs_and_as_legs_are_in_the_same_order() {
         let testing_hexapod_type = TestingHexapodType::new(0, 1, 2, 3, 4, 5);
@@ -43,6 +43,61 @@ impl BodyConfig {
     }
 }
 
+/// Allowed servo angle range in radians
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+pub struct JointLimits {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl JointLimits {
+    pub fn new(min: f32, max: f32) -> Self {
+        Self { min, max }
+    }
+
+    pub fn contains(&self, angle: f32) -> bool {
+        (self.min..=self.max).contains(&angle)
+    }
+}
+
+/// What to do with targets that are outside of limits
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IkLimitPolicy {
+    /// Move as close to the target as the limits allow
+    #[default]
+    Clamp,
+    /// Fail the whole move
+    Reject,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct IkLimits {
+    #[serde(default)]
+    pub policy: IkLimitPolicy,
+    pub coxa: JointLimits,
+    pub femur: JointLimits,
+    pub tibia: JointLimits,
+    /// Minimal horizontal distance between foot and coxa joint
+    /// Feet closer than this would hit the chassis
+    pub min_foot_distance: f32,
+    /// Minimal distance between any two feet
+    pub min_foot_separation: f32,
+}
+
+impl Default for IkLimits {
+    fn default() -> Self {
+        Self {
+            policy: IkLimitPolicy::default(),
+            coxa: JointLimits::new(60_f32.to_radians(), 240_f32.to_radians()),
+            femur: JointLimits::new(0.0, 300_f32.to_radians()),
+            tibia: JointLimits::new(0.0, 300_f32.to_radians()),
+            min_foot_distance: 0.04,
+            min_foot_separation: 0.04,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct HopperConfig {
     pub coxa_length: f32,
@@ -51,6 +106,8 @@ pub struct HopperConfig {
     pub femur_offset: f32,
     pub tibia_offset: f32,
     pub legs: BodyConfig,
+    #[serde(default)]
+    pub ik_limits: IkLimits,
 }
 
 impl HopperConfig {
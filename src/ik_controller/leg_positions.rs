@@ -5,6 +5,8 @@ use std::error::Error;
 
 pub type LegPositions = HexapodTypes<Point3<f32>>;
 
+pub const BODY_FRAME_ID: &str = "hopper_body";
+
 impl LegPositions {
     pub fn transform(
         &self,
@@ -54,49 +56,49 @@ impl LegPositions {
         let now = proto_timestamp_now();
         let left_front = crate::foxglove::FrameTransform {
             timestamp: Some(now.clone()),
-            parent_frame_id: "body".to_string(),
+            parent_frame_id: BODY_FRAME_ID.to_string(),
             child_frame_id: "left_front".to_string(),
             translation: Some(to_foxglove_vector3(self.left_front())),
             rotation: None,
         };
         let left_middle = crate::foxglove::FrameTransform {
             timestamp: Some(now.clone()),
-            parent_frame_id: "body".to_string(),
+            parent_frame_id: BODY_FRAME_ID.to_string(),
             child_frame_id: "left_middle".to_string(),
             translation: Some(to_foxglove_vector3(self.left_middle())),
             rotation: None,
         };
         let left_rear = crate::foxglove::FrameTransform {
             timestamp: Some(now.clone()),
-            parent_frame_id: "body".to_string(),
+            parent_frame_id: BODY_FRAME_ID.to_string(),
             child_frame_id: "left_rear".to_string(),
             translation: Some(to_foxglove_vector3(self.left_rear())),
             rotation: None,
         };
         let right_front = crate::foxglove::FrameTransform {
             timestamp: Some(now.clone()),
-            parent_frame_id: "body".to_string(),
+            parent_frame_id: BODY_FRAME_ID.to_string(),
             child_frame_id: "right_front".to_string(),
             translation: Some(to_foxglove_vector3(self.right_front())),
             rotation: None,
         };
         let right_middle = crate::foxglove::FrameTransform {
             timestamp: Some(now.clone()),
-            parent_frame_id: "body".to_string(),
+            parent_frame_id: BODY_FRAME_ID.to_string(),
             child_frame_id: "right_middle".to_string(),
             translation: Some(to_foxglove_vector3(self.right_middle())),
             rotation: None,
         };
         let right_rear = crate::foxglove::FrameTransform {
             timestamp: Some(now.clone()),
-            parent_frame_id: "body".to_string(),
+            parent_frame_id: BODY_FRAME_ID.to_string(),
             child_frame_id: "right_rear".to_string(),
             translation: Some(to_foxglove_vector3(self.right_rear())),
             rotation: None,
         };
         let lidar = crate::foxglove::FrameTransform {
             timestamp: Some(now.clone()),
-            parent_frame_id: "body".to_string(),
+            parent_frame_id: BODY_FRAME_ID.to_string(),
             child_frame_id: "hopper_lidar".to_string(),
             translation: Some(crate::foxglove::Vector3 {
                 x: 0.035,
@@ -108,7 +110,7 @@ impl LegPositions {
 
         let camera = crate::foxglove::FrameTransform {
             timestamp: Some(now.clone()),
-            parent_frame_id: "body".to_string(),
+            parent_frame_id: BODY_FRAME_ID.to_string(),
             child_frame_id: "hopper_camera".to_string(),
             translation: Some(crate::foxglove::Vector3 {
                 x: 0.050,
@@ -133,7 +135,7 @@ impl LegPositions {
     }
 }
 
-fn proto_timestamp_now() -> Timestamp {
+pub(crate) fn proto_timestamp_now() -> Timestamp {
     let now = std::time::SystemTime::now();
     let duration = now.duration_since(std::time::UNIX_EPOCH).unwrap();
     Timestamp {
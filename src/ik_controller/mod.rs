@@ -1,28 +1,46 @@
 pub mod leg_positions;
+pub mod odometry;
+pub mod validation;
 
 use crate::{
     body_controller::{
         motor_controller::{HexapodCompliance, HexapodMotorSpeed},
         motor_positions::{BodyMotorPositions, LegMotorPositions, OptionalBodyMotorPositions},
-        BodyController,
+        BodyController, BodyMotorTelemetry,
     },
-    error::HopperError,
     zenoh_remotes::pose_publisher::ZenohPosePublisher,
 };
 use crate::{
-    error::HopperResult,
+    error::{HopperError, HopperResult},
+    hexapod::LegFlags,
     hopper_body_config::{HopperConfig, LegConfig},
 };
 use async_trait::async_trait;
 use leg_positions::*;
 use nalgebra::{Point3, Vector3};
-use tracing::*;
+use std::f32::consts::PI;
+use validation::{calculate_validated_ik, check_motor_limits, check_optional_motor_limits};
 
 #[async_trait]
 pub trait IkControllable: BodyController {
     async fn move_to_positions(&mut self, positions: &LegPositions) -> HopperResult<()>;
     async fn read_leg_positions(&mut self) -> HopperResult<LegPositions>;
+    /// Measure only selected legs. Other legs are kept from `last`
+    async fn read_selected_leg_positions(
+        &mut self,
+        legs: LegFlags,
+        last: &LegPositions,
+    ) -> HopperResult<LegPositions> {
+        let measured = self.read_leg_positions().await?;
+        Ok(last.merge_with(&measured, legs))
+    }
     async fn disable_motors(&mut self) -> HopperResult<()>;
+    /// Pose was read back after a discontinuity. Odometry restarts from next commanded pose
+    fn restart_odometry(&mut self) {}
+    /// Replace leg geometry. Motor ids can't change at runtime
+    fn set_body_configuration(&mut self, body_configuration: HopperConfig) -> HopperResult<()>;
+    /// Fails if positions can't be reached with current leg geometry
+    fn check_reachable(&self, positions: &LegPositions) -> HopperResult<()>;
 }
 
 pub struct IkController {
@@ -35,10 +53,8 @@ impl IkController {
     pub fn new(
         body_controller: Box<dyn BodyController>,
         body_configuration: HopperConfig,
-        pose_publisher: zenoh::publication::Publisher<'static>,
+        pose_publisher: ZenohPosePublisher,
     ) -> Box<Self> {
-        let pose_publisher = ZenohPosePublisher::new(pose_publisher);
-
         Box::new(IkController {
             body_controller,
             body_configuration,
@@ -50,15 +66,17 @@ impl IkController {
 #[async_trait]
 impl BodyController for IkController {
     async fn move_motors_to(&mut self, positions: &BodyMotorPositions) -> HopperResult<()> {
-        self.body_controller.move_motors_to(positions).await
+        let positions = check_motor_limits(positions, &self.body_configuration)?;
+        self.body_controller.move_motors_to(&positions).await
     }
 
     async fn move_optional_motors_to(
         &mut self,
         positions: &OptionalBodyMotorPositions,
     ) -> HopperResult<()> {
+        let positions = check_optional_motor_limits(positions, &self.body_configuration)?;
         self.body_controller
-            .move_optional_motors_to(positions)
+            .move_optional_motors_to(&positions)
             .await
     }
 
@@ -91,10 +109,23 @@ impl BodyController for IkController {
         self.body_controller.read_motor_positions().await
     }
 
+    async fn read_selected_motor_positions(
+        &mut self,
+        legs: LegFlags,
+    ) -> HopperResult<Vec<LegMotorPositions>> {
+        self.body_controller
+            .read_selected_motor_positions(legs)
+            .await
+    }
+
     async fn read_mean_voltage(&mut self) -> HopperResult<f32> {
         self.body_controller.read_mean_voltage().await
     }
 
+    async fn read_motor_telemetry(&mut self) -> HopperResult<BodyMotorTelemetry> {
+        self.body_controller.read_motor_telemetry().await
+    }
+
     async fn scan_motors(&mut self) -> HopperResult<()> {
         self.body_controller.scan_motors().await
     }
@@ -107,11 +138,12 @@ impl BodyController for IkController {
 #[async_trait]
 impl IkControllable for IkController {
     async fn move_to_positions(&mut self, positions: &LegPositions) -> HopperResult<()> {
-        let motor_positions = calculate_ik(positions, &self.body_configuration)?;
+        let (positions, motor_positions) =
+            calculate_validated_ik(positions, &self.body_configuration)?;
         self.body_controller
             .move_motors_to(&motor_positions)
             .await?;
-        self.pose_publisher.set_pose(*positions);
+        self.pose_publisher.set_pose(positions);
         Ok(())
     }
 
@@ -121,54 +153,93 @@ impl IkControllable for IkController {
         Ok(leg_positions)
     }
 
+    async fn read_selected_leg_positions(
+        &mut self,
+        legs: LegFlags,
+        last: &LegPositions,
+    ) -> HopperResult<LegPositions> {
+        let motor_positions = self
+            .body_controller
+            .read_selected_motor_positions(legs)
+            .await?;
+        let measured: Vec<_> = motor_positions
+            .iter()
+            .zip(self.body_configuration.legs.selected_legs(legs))
+            .map(|(motor_positions, leg_config)| {
+                calculate_fk_for_leg(motor_positions, &self.body_configuration, leg_config)
+            })
+            .collect();
+        let mut positions = *last;
+        positions.updated_from_selected_legs(&measured, legs)?;
+        Ok(positions)
+    }
+
+    fn restart_odometry(&mut self) {
+        self.pose_publisher.restart_odometry();
+    }
+
     async fn disable_motors(&mut self) -> HopperResult<()> {
         self.body_controller.set_torque(false).await?;
         Ok(())
     }
+
+    fn set_body_configuration(&mut self, body_configuration: HopperConfig) -> HopperResult<()> {
+        if body_configuration.legs.get_ids() != self.body_configuration.legs.get_ids() {
+            return Err(HopperError::MotorIdsChanged);
+        }
+        self.body_configuration = body_configuration;
+        Ok(())
+    }
+
+    fn check_reachable(&self, positions: &LegPositions) -> HopperResult<()> {
+        calculate_validated_ik(positions, &self.body_configuration)?;
+        Ok(())
+    }
 }
 
+/// Raw IK without any limits. Use [`calculate_validated_ik`] to move legs
 pub(crate) fn calculate_ik(
     positions: &LegPositions,
     body_config: &HopperConfig,
-) -> HopperResult<BodyMotorPositions> {
+) -> BodyMotorPositions {
     let left_front = calculate_ik_for_leg(
         positions.left_front(),
         body_config,
         body_config.legs.left_front(),
-    )?;
+    );
     let right_front = calculate_ik_for_leg(
         positions.right_front(),
         body_config,
         body_config.legs.right_front(),
-    )?;
+    );
     let left_middle = calculate_ik_for_leg(
         positions.left_middle(),
         body_config,
         body_config.legs.left_middle(),
-    )?;
+    );
     let right_middle = calculate_ik_for_leg(
         positions.right_middle(),
         body_config,
         body_config.legs.right_middle(),
-    )?;
+    );
     let left_rear = calculate_ik_for_leg(
         positions.left_rear(),
         body_config,
         body_config.legs.left_rear(),
-    )?;
+    );
     let right_rear = calculate_ik_for_leg(
         positions.right_rear(),
         body_config,
         body_config.legs.right_rear(),
-    )?;
-    Ok(BodyMotorPositions::new(
+    );
+    BodyMotorPositions::new(
         left_front,
         left_middle,
         left_rear,
         right_front,
         right_middle,
         right_rear,
-    ))
+    )
 }
 
 pub(crate) fn calculate_fk(
@@ -219,10 +290,16 @@ fn calculate_ik_for_leg(
     target: &Point3<f32>,
     body_config: &HopperConfig,
     leg_config: &LegConfig,
-) -> HopperResult<LegMotorPositions> {
+) -> LegMotorPositions {
     let coxa_position = leg_config.position;
     let relative_vector: Vector3<f32> = target - coxa_position;
-    let target_angle = relative_vector.y.atan2(relative_vector.x) + leg_config.angle_offset;
+    let mut target_angle = relative_vector.y.atan2(relative_vector.x) + leg_config.angle_offset;
+    // keep targets behind the leg on the side they are on
+    if target_angle > PI {
+        target_angle -= 2.0 * PI;
+    } else if target_angle < -PI {
+        target_angle += 2.0 * PI;
+    }
     let horizontal_distance =
         (relative_vector.x.powi(2) + relative_vector.y.powi(2)).sqrt() - body_config.coxa_length;
     let distance = (horizontal_distance.powi(2) + relative_vector.z.powi(2)).sqrt();
@@ -240,24 +317,28 @@ fn calculate_ik_for_leg(
     );
     // we have angles of the SSS triangle. now we need angle for the servos
     let ground_target_angle = horizontal_distance.atan2(-relative_vector.z);
-    if target_angle >= 90_f32.to_radians() || target_angle <= (-90_f32).to_radians() {
-        warn!(
-            "Failed IK for leg: {} target: {}",
-            leg_config.position, &target
-        );
-        return Err(HopperError::GenericIkError);
-    }
     let femur_angle = angle_by_femur + ground_target_angle;
-    let corrected_femur =
-        (leg_config.femur_correction + body_config.femur_offset + femur_angle).abs();
-    let corrected_tibia =
-        (leg_config.tibia_correction + body_config.tibia_offset + angle_by_tibia).abs();
+    let corrected_femur = mirrored_servo_angle(
+        leg_config.femur_correction + body_config.femur_offset,
+        femur_angle,
+    );
+    let corrected_tibia = mirrored_servo_angle(
+        leg_config.tibia_correction + body_config.tibia_offset,
+        angle_by_tibia,
+    );
     let corrected_coxa = 150_f32.to_radians() + target_angle;
-    Ok(LegMotorPositions::new(
-        corrected_coxa,
-        corrected_femur,
-        corrected_tibia,
-    ))
+    LegMotorPositions::new(corrected_coxa, corrected_femur, corrected_tibia)
+}
+
+/// Servos mounted mirrored have a negative correction and turn the other way
+///
+/// Angles past the servo range come out negative so that joint limits catch them.
+fn mirrored_servo_angle(correction: f32, angle: f32) -> f32 {
+    if correction < 0.0 {
+        -(correction + angle)
+    } else {
+        correction + angle
+    }
 }
 
 fn calculate_fk_for_leg(
@@ -328,8 +409,7 @@ mod tests {
             &target,
             &hopper_body_config,
             hopper_body_config.legs.left_front(),
-        )
-        .unwrap();
+        );
         assert_relative_eq!(motor_positions.coxa().to_degrees(), 113.28124);
         assert_relative_eq!(motor_positions.femur().to_degrees(), 112.15929);
         assert_relative_eq!(motor_positions.tibia().to_degrees(), 196.29994);
@@ -343,8 +423,7 @@ mod tests {
             &target,
             &hopper_body_config,
             hopper_body_config.legs.right_front(),
-        )
-        .unwrap();
+        );
         assert_relative_eq!(motor_positions.coxa().to_degrees(), 186.71875);
         assert_relative_eq!(motor_positions.femur().to_degrees(), 188.0706);
         assert_relative_eq!(motor_positions.tibia().to_degrees(), 103.929955);
@@ -364,7 +443,7 @@ mod tests {
             Point3::new(0.0, -0.22, -0.09),
             Point3::new(-0.18, -0.15, -0.09),
         );
-        let motor_positions = calculate_ik(&pose, &hopper_body_config).unwrap();
+        let motor_positions = calculate_ik(&pose, &hopper_body_config);
         let expected_motor_positions = BodyMotorPositions::new(
             LegMotorPositions::new(1.9771307, 1.9575489, 3.4260802),
             LegMotorPositions::new(2.6187901, 1.9678993, 3.3529518),
@@ -384,8 +463,7 @@ mod tests {
             &target,
             &hopper_body_config,
             hopper_body_config.legs.left_front(),
-        )
-        .unwrap();
+        );
         let fk_calculated = calculate_fk_for_leg(
             &motor_positions,
             &hopper_body_config,
@@ -402,8 +480,7 @@ mod tests {
             &target,
             &hopper_body_config,
             hopper_body_config.legs.right_front(),
-        )
-        .unwrap();
+        );
         let fk_calculated = calculate_fk_for_leg(
             &motor_positions,
             &hopper_body_config,
@@ -423,7 +500,7 @@ mod tests {
             Point3::new(0.0, -0.22, -0.09),
             Point3::new(-0.18, -0.15, -0.09),
         );
-        let motor_positions = calculate_ik(&origin, &hopper_body_config).unwrap();
+        let motor_positions = calculate_ik(&origin, &hopper_body_config);
         let result = calculate_fk(&motor_positions, &hopper_body_config);
         assert_relative_eq!(origin.left_front(), result.left_front());
         assert_relative_eq!(origin.left_middle(), result.left_middle());
@@ -436,6 +513,6 @@ mod tests {
     #[test]
     fn test_ik_for_grounded() {
         let hopper_body_config = HopperConfig::default();
-        let _calculated = calculate_ik(stance::grounded_stance(), &hopper_body_config).unwrap();
+        let _calculated = calculate_ik(stance::grounded_stance(), &hopper_body_config);
     }
 }
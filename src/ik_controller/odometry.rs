@@ -0,0 +1,222 @@
+use super::leg_positions::{proto_timestamp_now, LegPositions, BODY_FRAME_ID};
+use crate::foxglove;
+use nalgebra::{Isometry2, Point2, UnitComplex, Vector2};
+
+pub const ODOMETRY_FRAME_ID: &str = "odom";
+
+/// Feet within this distance of the lowest foot are considered on the ground
+const GROUNDED_TOLERANCE: f32 = 0.005;
+/// Minimal number of feet on the ground needed to estimate body motion
+const MIN_GROUNDED_LEGS: usize = 3;
+
+/// Dead reckoning odometry based on positions of feet on the ground
+///
+/// Feet that stay on the ground between two poses are assumed not to slip.
+/// Their motion relative to the body is therefore the inverse of the body motion.
+#[derive(Debug, Clone)]
+pub struct Odometry {
+    pose: Isometry2<f32>,
+    last_positions: Option<LegPositions>,
+}
+
+impl Default for Odometry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Odometry {
+    pub fn new() -> Self {
+        Self {
+            pose: Isometry2::identity(),
+            last_positions: None,
+        }
+    }
+
+    /// Current body pose in odometry frame
+    pub fn pose(&self) -> Isometry2<f32> {
+        self.pose
+    }
+
+    /// Reset estimate back to origin
+    pub fn reset(&mut self) {
+        self.pose = Isometry2::identity();
+        self.last_positions = None;
+    }
+
+    /// Drop reference after motors were read back
+    ///
+    /// Read positions don't match commanded ones exactly so motion is only
+    /// integrated again from the next commanded positions
+    pub fn clear_reference(&mut self) {
+        self.last_positions = None;
+    }
+
+    /// Integrate body motion between last and new commanded positions
+    pub fn update(&mut self, positions: LegPositions) {
+        if let Some(last_positions) = self.last_positions.replace(positions) {
+            if let Some(body_motion) = estimate_body_motion(&last_positions, &positions) {
+                self.pose *= body_motion;
+            }
+        }
+    }
+
+    pub fn to_foxglove_pose_in_frame(&self) -> foxglove::PoseInFrame {
+        foxglove::PoseInFrame {
+            timestamp: Some(proto_timestamp_now()),
+            frame_id: ODOMETRY_FRAME_ID.to_string(),
+            pose: Some(foxglove::Pose {
+                position: Some(self.foxglove_translation()),
+                orientation: Some(self.foxglove_rotation()),
+            }),
+        }
+    }
+
+    pub fn to_foxglove_frame_transform(&self) -> foxglove::FrameTransform {
+        foxglove::FrameTransform {
+            timestamp: Some(proto_timestamp_now()),
+            parent_frame_id: ODOMETRY_FRAME_ID.to_string(),
+            child_frame_id: BODY_FRAME_ID.to_string(),
+            translation: Some(self.foxglove_translation()),
+            rotation: Some(self.foxglove_rotation()),
+        }
+    }
+
+    fn foxglove_translation(&self) -> foxglove::Vector3 {
+        foxglove::Vector3 {
+            x: self.pose.translation.x as f64,
+            y: self.pose.translation.y as f64,
+            z: 0.0,
+        }
+    }
+
+    fn foxglove_rotation(&self) -> foxglove::Quaternion {
+        let half_angle = self.pose.rotation.angle() as f64 / 2.0;
+        foxglove::Quaternion {
+            x: 0.0,
+            y: 0.0,
+            z: half_angle.sin(),
+            w: half_angle.cos(),
+        }
+    }
+}
+
+fn grounded_feet(positions: &LegPositions) -> [bool; 6] {
+    let legs = positions.as_legs();
+    let lowest = legs.iter().map(|leg| leg.z).fold(f32::INFINITY, f32::min);
+    legs.map(|leg| leg.z - lowest < GROUNDED_TOLERANCE)
+}
+
+/// Estimate motion of the body between two poses in the old body frame
+///
+/// Fits rigid 2D transformation mapping new foot positions onto old ones.
+fn estimate_body_motion(last: &LegPositions, new: &LegPositions) -> Option<Isometry2<f32>> {
+    let last_grounded = grounded_feet(last);
+    let new_grounded = grounded_feet(new);
+    let pairs: Vec<(Point2<f32>, Point2<f32>)> = last
+        .as_legs()
+        .into_iter()
+        .zip(new.as_legs())
+        .zip(last_grounded.into_iter().zip(new_grounded))
+        .filter(|(_, (last_grounded, new_grounded))| *last_grounded && *new_grounded)
+        .map(|((last, new), _)| (last.xy(), new.xy()))
+        .collect();
+    if pairs.len() < MIN_GROUNDED_LEGS {
+        return None;
+    }
+
+    let count = pairs.len() as f32;
+    let last_centroid = pairs
+        .iter()
+        .fold(Vector2::zeros(), |sum, (last, _)| sum + last.coords)
+        / count;
+    let new_centroid = pairs
+        .iter()
+        .fold(Vector2::zeros(), |sum, (_, new)| sum + new.coords)
+        / count;
+
+    let (cross, dot) = pairs.iter().fold((0.0, 0.0), |(cross, dot), (last, new)| {
+        let last = last.coords - last_centroid;
+        let new = new.coords - new_centroid;
+        (cross + new.perp(&last), dot + new.dot(&last))
+    });
+    let rotation = UnitComplex::new(cross.atan2(dot));
+    let translation = last_centroid - rotation * new_centroid;
+    Some(Isometry2::from_parts(translation.into(), rotation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::motion_controller::stance;
+    use approx::assert_relative_eq;
+    use nalgebra::{UnitQuaternion, Vector3};
+
+    #[test]
+    fn standing_still_does_not_move() {
+        let mut odometry = Odometry::new();
+        odometry.update(*stance::relaxed_stance());
+        odometry.update(*stance::relaxed_stance());
+        assert_relative_eq!(odometry.pose(), Isometry2::identity());
+    }
+
+    #[test]
+    fn feet_moving_back_moves_body_forward() {
+        let mut odometry = Odometry::new();
+        let relaxed = *stance::relaxed_stance();
+        odometry.update(relaxed);
+        odometry
+            .update(relaxed.transform(Vector3::new(-0.02, 0.0, 0.0), UnitQuaternion::identity()));
+        assert_relative_eq!(odometry.pose().translation.x, 0.02, epsilon = 0.0001);
+        assert_relative_eq!(odometry.pose().translation.y, 0.0, epsilon = 0.0001);
+    }
+
+    #[test]
+    fn feet_rotating_rotates_body_in_opposite_direction() {
+        let mut odometry = Odometry::new();
+        let relaxed = *stance::relaxed_stance();
+        odometry.update(relaxed);
+        odometry.update(relaxed.transform(
+            Vector3::zeros(),
+            UnitQuaternion::from_euler_angles(0.0, 0.0, -0.1),
+        ));
+        assert_relative_eq!(odometry.pose().rotation.angle(), 0.1, epsilon = 0.0001);
+    }
+
+    #[test]
+    fn lifted_feet_are_ignored() {
+        let mut odometry = Odometry::new();
+        let relaxed = *stance::relaxed_stance();
+        odometry.update(relaxed);
+        let lifted = relaxed.transform_selected_legs(
+            Vector3::new(0.03, 0.0, 0.02),
+            UnitQuaternion::identity(),
+            crate::hexapod::LegFlags::LRL_TRIPOD,
+        );
+        odometry.update(lifted);
+        assert_relative_eq!(odometry.pose(), Isometry2::identity());
+    }
+
+    #[test]
+    fn reference_is_not_integrated() {
+        let mut odometry = Odometry::new();
+        let relaxed = *stance::relaxed_stance();
+        odometry.update(relaxed);
+        odometry.clear_reference();
+        assert_relative_eq!(odometry.pose(), Isometry2::identity());
+    }
+
+    #[test]
+    fn first_command_after_clearing_reference_does_not_jump() {
+        let mut odometry = Odometry::new();
+        let relaxed = *stance::relaxed_stance();
+        odometry.update(relaxed);
+        odometry.clear_reference();
+        let shifted = relaxed.transform(Vector3::new(-0.05, 0.0, 0.0), UnitQuaternion::identity());
+        odometry.update(shifted);
+        assert_relative_eq!(odometry.pose(), Isometry2::identity());
+        odometry
+            .update(shifted.transform(Vector3::new(-0.02, 0.0, 0.0), UnitQuaternion::identity()));
+        assert_relative_eq!(odometry.pose().translation.x, 0.02, epsilon = 0.0001);
+    }
+}
@@ -0,0 +1,452 @@
+use super::{calculate_fk, calculate_ik, leg_positions::LegPositions};
+use crate::{
+    body_controller::motor_positions::{
+        BodyMotorPositions, LegMotorPositions, OptionalBodyMotorPositions,
+        OptionalLegMotorPositions,
+    },
+    error::HopperResult,
+    hexapod::{LegFlags, LEGS_IN_ORDER},
+    hopper_body_config::{HopperConfig, IkLimitPolicy, JointLimits, LegConfig},
+};
+use nalgebra::{Point3, Vector2};
+use thiserror::Error;
+use tracing::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Joint {
+    Coxa,
+    Femur,
+    Tibia,
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum IkErrorReason {
+    #[error("{joint:?} angle {angle} outside of limits [{min}, {max}]")]
+    JointOutOfRange {
+        joint: Joint,
+        angle: f32,
+        min: f32,
+        max: f32,
+    },
+    #[error("target at distance {distance} outside of reach [{min_reach}, {max_reach}]")]
+    OutOfReach {
+        distance: f32,
+        min_reach: f32,
+        max_reach: f32,
+    },
+    #[error("foot {distance} from coxa would hit the chassis. Minimum is {min_distance}")]
+    BodyCollision { distance: f32, min_distance: f32 },
+    #[error("foot {distance} from {other:?} foot. Minimum is {min_distance}")]
+    LegCollision {
+        other: LegFlags,
+        distance: f32,
+        min_distance: f32,
+    },
+}
+
+/// IK failure for a specific leg
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("IK error for {leg:?}: {reason}")]
+pub struct IkError {
+    pub leg: LegFlags,
+    pub reason: IkErrorReason,
+}
+
+impl IkError {
+    pub fn new(leg: LegFlags, reason: IkErrorReason) -> Self {
+        Self { leg, reason }
+    }
+}
+
+/// Calculate IK while enforcing limits from body config
+///
+/// Returns positions the legs will actually move to together with motor positions.
+/// Depending on the policy targets outside of limits are either clamped or rejected.
+/// Feet colliding with each other are always rejected.
+pub(crate) fn calculate_validated_ik(
+    positions: &LegPositions,
+    body_config: &HopperConfig,
+) -> HopperResult<(LegPositions, BodyMotorPositions)> {
+    check_foot_separation(positions, body_config)?;
+
+    let mut targets = [Point3::origin(); 6];
+    for (index, (leg, (target, leg_config))) in LEGS_IN_ORDER
+        .iter()
+        .zip(
+            positions
+                .as_legs()
+                .into_iter()
+                .zip(body_config.legs.as_legs()),
+        )
+        .enumerate()
+    {
+        targets[index] = validate_leg_target(*leg, target, leg_config, body_config)?;
+    }
+    let targets = LegPositions::from_legs([
+        &targets[0],
+        &targets[1],
+        &targets[2],
+        &targets[3],
+        &targets[4],
+        &targets[5],
+    ]);
+
+    let motor_positions = calculate_ik(&targets, body_config);
+    let (motor_positions, clamped) = validate_joint_limits(&motor_positions, body_config)?;
+    if clamped {
+        Ok((calculate_fk(&motor_positions, body_config), motor_positions))
+    } else {
+        Ok((targets, motor_positions))
+    }
+}
+
+fn check_foot_separation(positions: &LegPositions, body_config: &HopperConfig) -> HopperResult<()> {
+    let min_distance = body_config.ik_limits.min_foot_separation;
+    let legs = positions.as_legs();
+    for (index, (leg, position)) in LEGS_IN_ORDER.iter().zip(legs).enumerate() {
+        for (other, other_position) in LEGS_IN_ORDER.iter().zip(legs).skip(index + 1) {
+            let distance = nalgebra::distance(position, other_position);
+            if distance < min_distance {
+                let reason = IkErrorReason::LegCollision {
+                    other: *other,
+                    distance,
+                    min_distance,
+                };
+                return Err(IkError::new(*leg, reason).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Either returns clamped value or fails based on policy
+fn apply_policy<T>(
+    policy: IkLimitPolicy,
+    leg: LegFlags,
+    reason: IkErrorReason,
+    clamped: T,
+) -> HopperResult<T> {
+    match policy {
+        IkLimitPolicy::Clamp => {
+            warn!("Clamping IK target for {:?}: {}", leg, reason);
+            Ok(clamped)
+        }
+        IkLimitPolicy::Reject => Err(IkError::new(leg, reason).into()),
+    }
+}
+
+fn validate_leg_target(
+    leg: LegFlags,
+    target: &Point3<f32>,
+    leg_config: &LegConfig,
+    body_config: &HopperConfig,
+) -> HopperResult<Point3<f32>> {
+    let limits = &body_config.ik_limits;
+    let mut target = *target;
+
+    let relative = target.xy() - leg_config.position.xy();
+    let horizontal_distance = relative.norm();
+    let direction = if horizontal_distance > f32::EPSILON {
+        relative / horizontal_distance
+    } else {
+        // straight out from coxa
+        Vector2::new(
+            (-leg_config.angle_offset).cos(),
+            (-leg_config.angle_offset).sin(),
+        )
+    };
+
+    if horizontal_distance < limits.min_foot_distance {
+        let reason = IkErrorReason::BodyCollision {
+            distance: horizontal_distance,
+            min_distance: limits.min_foot_distance,
+        };
+        let clamped = leg_config.position.xy() + direction * limits.min_foot_distance;
+        target = apply_policy(
+            limits.policy,
+            leg,
+            reason,
+            Point3::new(clamped.x, clamped.y, target.z),
+        )?;
+    }
+
+    let femur_joint_xy = leg_config.position.xy() + direction * body_config.coxa_length;
+    let femur_joint = Point3::new(femur_joint_xy.x, femur_joint_xy.y, leg_config.position.z);
+    let reach = target - femur_joint;
+    let distance = reach.norm();
+    let max_reach = body_config.femur_length + body_config.tibia_length;
+    let min_reach = (body_config.femur_length - body_config.tibia_length).abs();
+    if distance > max_reach || distance < min_reach {
+        let reason = IkErrorReason::OutOfReach {
+            distance,
+            min_reach,
+            max_reach,
+        };
+        let clamped_distance = distance.clamp(min_reach, max_reach);
+        let clamped = if distance > f32::EPSILON {
+            femur_joint + reach / distance * clamped_distance
+        } else {
+            femur_joint - nalgebra::Vector3::z() * clamped_distance
+        };
+        target = apply_policy(limits.policy, leg, reason, clamped)?;
+    }
+    Ok(target)
+}
+
+fn validate_joint(
+    leg: LegFlags,
+    joint: Joint,
+    angle: f32,
+    limits: &JointLimits,
+    policy: IkLimitPolicy,
+) -> HopperResult<f32> {
+    if limits.contains(angle) {
+        return Ok(angle);
+    }
+    let reason = IkErrorReason::JointOutOfRange {
+        joint,
+        angle,
+        min: limits.min,
+        max: limits.max,
+    };
+    apply_policy(policy, leg, reason, angle.clamp(limits.min, limits.max))
+}
+
+/// Returns motor positions within limits and whether any of them were clamped
+fn validate_joint_limits(
+    motor_positions: &BodyMotorPositions,
+    body_config: &HopperConfig,
+) -> HopperResult<(BodyMotorPositions, bool)> {
+    let limits = &body_config.ik_limits;
+    let mut legs = Vec::with_capacity(6);
+    for (leg, motors) in LEGS_IN_ORDER.iter().zip(motor_positions.as_legs()) {
+        legs.push(LegMotorPositions::new(
+            validate_joint(
+                *leg,
+                Joint::Coxa,
+                motors.coxa(),
+                &limits.coxa,
+                limits.policy,
+            )?,
+            validate_joint(
+                *leg,
+                Joint::Femur,
+                motors.femur(),
+                &limits.femur,
+                limits.policy,
+            )?,
+            validate_joint(
+                *leg,
+                Joint::Tibia,
+                motors.tibia(),
+                &limits.tibia,
+                limits.policy,
+            )?,
+        ));
+    }
+    let validated =
+        BodyMotorPositions::from_legs([&legs[0], &legs[1], &legs[2], &legs[3], &legs[4], &legs[5]]);
+    let clamped = &validated != motor_positions;
+    Ok((validated, clamped))
+}
+
+/// Enforce joint limits on motor positions that don't come from IK
+pub(crate) fn check_motor_limits(
+    motor_positions: &BodyMotorPositions,
+    body_config: &HopperConfig,
+) -> HopperResult<BodyMotorPositions> {
+    let (motor_positions, _) = validate_joint_limits(motor_positions, body_config)?;
+    Ok(motor_positions)
+}
+
+/// Enforce joint limits on motors that are set. Others are left alone
+pub(crate) fn check_optional_motor_limits(
+    motor_positions: &OptionalBodyMotorPositions,
+    body_config: &HopperConfig,
+) -> HopperResult<OptionalBodyMotorPositions> {
+    let limits = &body_config.ik_limits;
+    let validate = |leg, joint, angle: Option<f32>, joint_limits| {
+        angle
+            .map(|angle| validate_joint(leg, joint, angle, joint_limits, limits.policy))
+            .transpose()
+    };
+    let mut legs = Vec::with_capacity(6);
+    for (leg, motors) in LEGS_IN_ORDER.iter().zip(motor_positions.as_legs()) {
+        legs.push(OptionalLegMotorPositions::new(
+            validate(*leg, Joint::Coxa, motors.coxa(), &limits.coxa)?,
+            validate(*leg, Joint::Femur, motors.femur(), &limits.femur)?,
+            validate(*leg, Joint::Tibia, motors.tibia(), &limits.tibia)?,
+        ));
+    }
+    Ok(OptionalBodyMotorPositions::from_legs([
+        &legs[0], &legs[1], &legs[2], &legs[3], &legs[4], &legs[5],
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::HopperError;
+    use crate::motion_controller::stance;
+    use approx::assert_relative_eq;
+
+    fn ik_error(error: HopperError) -> IkError {
+        match error {
+            HopperError::IkError(error) => error,
+            error => panic!("Unexpected error {:?}", error),
+        }
+    }
+
+    #[test]
+    fn stances_are_within_limits() {
+        let config = HopperConfig::default();
+        for pose in [
+            stance::relaxed_stance(),
+            stance::grounded_stance(),
+            stance::relaxed_wide_stance(),
+        ] {
+            let (positions, motors) = calculate_validated_ik(pose, &config).unwrap();
+            assert_eq!(&positions, pose);
+            assert_eq!(motors, calculate_ik(pose, &config));
+        }
+    }
+
+    #[test]
+    fn rejects_foot_under_chassis() {
+        let mut config = HopperConfig::default();
+        config.ik_limits.policy = IkLimitPolicy::Reject;
+        let coxa = config.legs.left_middle().position;
+        let pose =
+            stance::relaxed_stance().updated_left_middle(Point3::new(coxa.x, coxa.y + 0.01, -0.1));
+        let error = ik_error(calculate_validated_ik(&pose, &config).unwrap_err());
+        assert_eq!(error.leg, LegFlags::LEFT_MIDDLE);
+        assert!(matches!(error.reason, IkErrorReason::BodyCollision { .. }));
+    }
+
+    #[test]
+    fn clamps_foot_under_chassis() {
+        let config = HopperConfig::default();
+        let coxa = config.legs.left_middle().position;
+        let pose =
+            stance::relaxed_stance().updated_left_middle(Point3::new(coxa.x, coxa.y + 0.01, -0.1));
+        let (positions, _) = calculate_validated_ik(&pose, &config).unwrap();
+        let distance = (positions.left_middle().xy() - coxa.xy()).norm();
+        assert_relative_eq!(
+            distance,
+            config.ik_limits.min_foot_distance,
+            epsilon = 0.001
+        );
+    }
+
+    #[test]
+    fn clamps_unreachable_target() {
+        let config = HopperConfig::default();
+        let pose = stance::relaxed_stance().updated_left_middle(Point3::new(0.0, 0.6, -0.1));
+        let (positions, _) = calculate_validated_ik(&pose, &config).unwrap();
+        let max_reach = config.coxa_length + config.femur_length + config.tibia_length;
+        let reach =
+            nalgebra::distance(positions.left_middle(), &config.legs.left_middle().position);
+        assert!(reach <= max_reach + 0.001);
+    }
+
+    #[test]
+    fn rejects_joint_out_of_range() {
+        let mut config = HopperConfig::default();
+        config.ik_limits.policy = IkLimitPolicy::Reject;
+        config.ik_limits.tibia = JointLimits::new(0.0, 1.0);
+        let error =
+            ik_error(calculate_validated_ik(stance::relaxed_stance(), &config).unwrap_err());
+        assert!(matches!(
+            error.reason,
+            IkErrorReason::JointOutOfRange {
+                joint: Joint::Tibia,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn rejects_coxa_outside_configured_limits() {
+        let mut config = HopperConfig::default();
+        config.ik_limits.policy = IkLimitPolicy::Reject;
+        config.ik_limits.coxa = JointLimits::new(140_f32.to_radians(), 160_f32.to_radians());
+        let error =
+            ik_error(calculate_validated_ik(stance::relaxed_stance(), &config).unwrap_err());
+        assert!(matches!(
+            error.reason,
+            IkErrorReason::JointOutOfRange {
+                joint: Joint::Coxa,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn clamps_coxa_to_configured_limits() {
+        let mut config = HopperConfig::default();
+        config.ik_limits.coxa = JointLimits::new(140_f32.to_radians(), 160_f32.to_radians());
+        let (_, motors) = calculate_validated_ik(stance::relaxed_stance(), &config).unwrap();
+        for leg in motors.as_legs() {
+            assert!(config.ik_limits.coxa.contains(leg.coxa()));
+        }
+    }
+
+    #[test]
+    fn femur_past_servo_range_is_not_mirrored_back() {
+        let config = HopperConfig::default();
+        // foot raised above the coxa turns the mirrored femur servo past zero
+        let leg = config.legs.left_middle();
+        let pose = stance::relaxed_stance()
+            .updated_left_middle(leg.position + nalgebra::Vector3::new(0.0, 0.08, 0.09));
+        let motors = calculate_ik(&pose, &config);
+        assert!(motors.left_middle().femur() < 0.0);
+        assert!(!config
+            .ik_limits
+            .femur
+            .contains(motors.left_middle().femur()));
+    }
+
+    #[test]
+    fn raw_motor_positions_are_limited() {
+        let mut config = HopperConfig::default();
+        let relaxed = calculate_ik(stance::relaxed_stance(), &config);
+        let mut legs = relaxed.as_legs();
+        let out_of_range = LegMotorPositions::new(0.1, 2.0, 2.0);
+        legs[0] = &out_of_range;
+        let motors = BodyMotorPositions::from_legs(legs);
+        let limited = check_motor_limits(&motors, &config).unwrap();
+        assert_relative_eq!(limited.left_front().coxa(), config.ik_limits.coxa.min);
+
+        config.ik_limits.policy = IkLimitPolicy::Reject;
+        assert!(check_motor_limits(&motors, &config).is_err());
+        let none = OptionalLegMotorPositions::default();
+        let optional = OptionalBodyMotorPositions::from_legs([
+            &OptionalLegMotorPositions::new(Some(0.1), None, None),
+            &none,
+            &none,
+            &none,
+            &none,
+            &none,
+        ]);
+        assert!(check_optional_motor_limits(&optional, &config).is_err());
+        assert!(
+            check_optional_motor_limits(&OptionalBodyMotorPositions::default(), &config).is_ok()
+        );
+    }
+
+    #[test]
+    fn always_rejects_colliding_feet() {
+        let config = HopperConfig::default();
+        let relaxed = stance::relaxed_stance();
+        let pose = relaxed.updated_left_front(*relaxed.left_middle());
+        let error = ik_error(calculate_validated_ik(&pose, &config).unwrap_err());
+        assert_eq!(error.leg, LegFlags::LEFT_FRONT);
+        assert!(matches!(
+            error.reason,
+            IkErrorReason::LegCollision {
+                other: LegFlags::LEFT_MIDDLE,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,88 @@
+//! Gamepad buttons shared by remote control and configuration
+
+use serde::{Deserialize, Serialize};
+
+/// Held together to latch the emergency stop
+pub const ESTOP_BUTTONS: [Button; 3] = [Button::LeftTrigger, Button::RightTrigger, Button::Mode];
+/// Held together to clear the emergency stop
+pub const ESTOP_CLEAR_BUTTONS: [Button; 3] =
+    [Button::LeftTrigger, Button::RightTrigger, Button::Start];
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub enum Button {
+    South,
+    East,
+    North,
+    West,
+    C,
+    Z,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    Mode,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    Unknown,
+    LeftPaddle,
+    RightPaddle,
+}
+
+impl Button {
+    pub fn all_gilrs_buttons() -> &'static [gilrs::ev::Button] {
+        &[
+            gilrs::ev::Button::South,
+            gilrs::ev::Button::East,
+            gilrs::ev::Button::North,
+            gilrs::ev::Button::West,
+            gilrs::ev::Button::C,
+            gilrs::ev::Button::Z,
+            gilrs::ev::Button::LeftTrigger,
+            gilrs::ev::Button::LeftTrigger2,
+            gilrs::ev::Button::RightTrigger,
+            gilrs::ev::Button::RightTrigger2,
+            gilrs::ev::Button::Select,
+            gilrs::ev::Button::Start,
+            gilrs::ev::Button::Mode,
+            gilrs::ev::Button::LeftThumb,
+            gilrs::ev::Button::RightThumb,
+            gilrs::ev::Button::DPadUp,
+            gilrs::ev::Button::DPadDown,
+            gilrs::ev::Button::DPadLeft,
+            gilrs::ev::Button::DPadRight,
+        ]
+    }
+}
+
+impl From<gilrs::ev::Button> for Button {
+    fn from(value: gilrs::ev::Button) -> Self {
+        match value {
+            gilrs::ev::Button::South => Button::South,
+            gilrs::ev::Button::East => Button::East,
+            gilrs::ev::Button::North => Button::North,
+            gilrs::ev::Button::West => Button::West,
+            gilrs::ev::Button::C => Button::C,
+            gilrs::ev::Button::Z => Button::Z,
+            gilrs::ev::Button::LeftTrigger => Button::LeftTrigger,
+            gilrs::ev::Button::LeftTrigger2 => Button::LeftTrigger2,
+            gilrs::ev::Button::RightTrigger => Button::RightTrigger,
+            gilrs::ev::Button::RightTrigger2 => Button::RightTrigger2,
+            gilrs::ev::Button::Select => Button::Select,
+            gilrs::ev::Button::Start => Button::Start,
+            gilrs::ev::Button::Mode => Button::Mode,
+            gilrs::ev::Button::LeftThumb => Button::LeftThumb,
+            gilrs::ev::Button::RightThumb => Button::RightThumb,
+            gilrs::ev::Button::DPadUp => Button::DPadUp,
+            gilrs::ev::Button::DPadDown => Button::DPadDown,
+            gilrs::ev::Button::DPadLeft => Button::DPadLeft,
+            gilrs::ev::Button::DPadRight => Button::DPadRight,
+            gilrs::ev::Button::Unknown => Button::Unknown,
+        }
+    }
+}
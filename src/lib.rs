@@ -1,8 +1,11 @@
 #![doc = include_str!("../README.md")]
 
 pub mod audio_transcribe;
+pub mod battery;
 pub mod body_controller;
+pub mod calibration;
 pub mod camera;
+pub mod command_watchdog;
 pub mod configuration;
 pub mod error;
 pub mod face;
@@ -10,12 +13,17 @@ mod hexapod;
 pub mod high_five;
 pub mod hopper_body_config;
 pub mod ik_controller;
+pub mod input;
 pub mod ioc_container;
 pub mod lidar;
 pub mod logging;
 pub mod monitoring;
 pub mod motion_controller;
+pub mod navigation;
+pub mod obstacle_guard;
+pub mod occupancy_grid;
 pub mod openai;
+pub mod recorder;
 pub mod speech;
 pub mod udp_remote;
 pub mod utilities;
@@ -40,4 +48,10 @@ pub mod foxglove {
 pub mod hopper {
     #![allow(non_snake_case)]
     include!(concat!(env!("OUT_DIR"), "/hopper.rs"));
+
+    pub mod command {
+        pub mod v1 {
+            include!(concat!(env!("OUT_DIR"), "/hopper.command.v1.rs"));
+        }
+    }
 }
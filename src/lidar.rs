@@ -1,35 +1,69 @@
 use crate::foxglove;
 use crate::high_five::HighFiveDetector;
-use crate::{configuration::LidarConfig, error::HopperError};
+use crate::ik_controller::odometry::Odometry;
+use crate::obstacle_guard::ObstacleGuard;
+use crate::occupancy_grid::OccupancyGrid;
+use crate::{
+    configuration::{BodyControllerType, LidarConfig},
+    error::HopperError,
+};
+use nalgebra::Point2;
 use prost::Message;
 use prost_types::Timestamp;
 use rplidar_driver::{utils::sort_scan, RplidarDevice, RposError, ScanOptions, ScanPoint};
 use std::{
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Mutex,
     },
     thread,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tracing::{error, info, log::warn};
 use zenoh::prelude::r#async::*;
 
+/// Position of the lidar on the x axis of the body frame
+pub const LIDAR_OFFSET_X: f32 = 0.035;
+
+/// Rate of the empty scans from the simulated lidar
+const SIMULATED_SCAN_PERIOD: Duration = Duration::from_millis(100);
+
+/// Project valid scan points into body frame
+pub fn scan_to_body_points(scan: &[ScanPoint]) -> Vec<Point2<f32>> {
+    scan.iter()
+        .filter(|scan_point| scan_point.is_valid())
+        .map(|scan_point| {
+            let x = scan_point.distance() * (-scan_point.angle()).cos();
+            let y = scan_point.distance() * (-scan_point.angle()).sin();
+            Point2::new(x + LIDAR_OFFSET_X, y)
+        })
+        .collect()
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct LidarServiceController {
     active: Arc<AtomicBool>,
+    /// Obstacle guard stops walking without scans so lidar can't be turned off
+    required: bool,
 }
 
 impl LidarServiceController {
-    fn new(initial_state: bool) -> Self {
+    fn new(initial_state: bool, required: bool) -> Self {
         Self {
-            active: Arc::new(AtomicBool::new(initial_state)),
+            active: Arc::new(AtomicBool::new(initial_state || required)),
+            required,
         }
     }
 
-    pub fn set_active(&self, active: bool) {
+    /// Returns false if lidar has to stay on for obstacle guard
+    pub fn set_active(&self, active: bool) -> bool {
+        if !active && self.required {
+            warn!("Lidar stays on while obstacle guard is enabled");
+            return false;
+        }
         self.active.store(active, Ordering::Relaxed);
+        true
     }
 
     pub fn is_active(&self) -> bool {
@@ -41,9 +75,21 @@ pub async fn start_lidar_driver(
     zenoh_session: Arc<Session>,
     config: &LidarConfig,
     high_give_detector: HighFiveDetector,
+    obstacle_guard: ObstacleGuard,
+    odometry: Arc<Mutex<Odometry>>,
+    backend: BodyControllerType,
 ) -> anyhow::Result<LidarServiceController> {
-    let (mut scan_receiver, lidar_service_controller) =
-        start_lidar_driver_internal(&config.serial_port, config.start_state_on)?;
+    if config.obstacle_guard.enabled && !config.start_state_on {
+        warn!("Starting lidar although start_state_on is off because obstacle guard is enabled");
+    }
+    let lidar_service_controller =
+        LidarServiceController::new(config.start_state_on, config.obstacle_guard.enabled);
+    let mut scan_receiver = match backend {
+        BodyControllerType::Dynamixel => {
+            start_lidar_driver_internal(&config.serial_port, lidar_service_controller.clone())?
+        }
+        BodyControllerType::Simulated => start_simulated_lidar(lidar_service_controller.clone()),
+    };
 
     let subscriber = zenoh_session
         .declare_subscriber(&config.state_topic)
@@ -57,6 +103,15 @@ pub async fn start_lidar_driver(
         .await
         .map_err(HopperError::ZenohError)?;
 
+    let occupancy_grid_publisher = zenoh_session
+        .declare_publisher(config.occupancy_grid.topic.to_owned())
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+    let occupancy_grid_config = config.occupancy_grid.clone();
+    let occupancy_grid_publish_period =
+        Duration::from_millis(occupancy_grid_config.publish_period_ms);
+
     let pose = foxglove::Pose {
         position: Some(foxglove::Vector3 {
             x: 0.0,
@@ -128,6 +183,9 @@ pub async fn start_lidar_driver(
     tokio::spawn(async move {
         let mut scan_counter = 0;
         let mut high_give_detector = high_give_detector;
+        let occupancy_grid_enabled = occupancy_grid_config.enabled;
+        let mut occupancy_grid = OccupancyGrid::new(occupancy_grid_config);
+        let mut last_occupancy_grid_publish = Instant::now();
         while let Some(mut scan) = scan_receiver.recv().await {
             let capture_time = SystemTime::now();
             scan_counter += 1;
@@ -139,6 +197,22 @@ pub async fn start_lidar_driver(
             sort_scan(&mut scan).unwrap();
 
             high_give_detector.process_scan(&scan).await;
+            obstacle_guard.process_scan(&scan);
+
+            if occupancy_grid_enabled {
+                let body_pose = odometry.lock().unwrap().pose();
+                occupancy_grid.integrate_scan(&scan_to_body_points(&scan), &body_pose);
+                if last_occupancy_grid_publish.elapsed() > occupancy_grid_publish_period {
+                    last_occupancy_grid_publish = Instant::now();
+                    if let Err(error) = occupancy_grid_publisher
+                        .put(occupancy_grid.to_foxglove_grid().encode_to_vec())
+                        .res()
+                        .await
+                    {
+                        error!("Failed to publish occupancy grid: {}", error);
+                    }
+                }
+            }
 
             // point cloud
             let projected_scan = scan
@@ -195,13 +269,11 @@ fn system_time_to_proto_time(time: &SystemTime) -> Timestamp {
 
 fn start_lidar_driver_internal(
     port: &str,
-    start_with_lidar_running: bool,
-) -> anyhow::Result<(Receiver<Vec<ScanPoint>>, LidarServiceController)> {
+    lidar_service_controller: LidarServiceController,
+) -> anyhow::Result<Receiver<Vec<ScanPoint>>> {
     let (scan_sender, scan_receiver) = channel(10);
-    let lidar_service_controller = LidarServiceController::new(start_with_lidar_running);
     thread::spawn({
         let port = port.to_owned();
-        let lidar_service_controller = lidar_service_controller.clone();
         move || loop {
             if let Err(err) =
                 lidar_loop(&port, scan_sender.clone(), lidar_service_controller.clone())
@@ -212,7 +284,22 @@ fn start_lidar_driver_internal(
         }
     });
 
-    Ok((scan_receiver, lidar_service_controller))
+    Ok(scan_receiver)
+}
+
+/// Lidar in an empty room. Publishes scans without any points while active
+fn start_simulated_lidar(
+    lidar_service_controller: LidarServiceController,
+) -> Receiver<Vec<ScanPoint>> {
+    let (scan_sender, scan_receiver) = channel(10);
+    thread::spawn(move || loop {
+        thread::sleep(SIMULATED_SCAN_PERIOD);
+        if lidar_service_controller.is_active() && scan_sender.blocking_send(vec![]).is_err() {
+            break;
+        }
+    });
+
+    scan_receiver
 }
 
 fn lidar_loop(
@@ -256,3 +343,22 @@ fn lidar_loop(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lidar_stays_on_for_obstacle_guard() {
+        let controller = LidarServiceController::new(false, true);
+        assert!(controller.is_active());
+        assert!(!controller.set_active(false));
+        assert!(controller.is_active());
+
+        let controller = LidarServiceController::new(false, false);
+        assert!(!controller.is_active());
+        assert!(controller.set_active(true));
+        assert!(controller.set_active(false));
+        assert!(!controller.is_active());
+    }
+}
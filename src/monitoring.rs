@@ -1,18 +1,49 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use prost::Message;
 use prost_reflect::ReflectMessage;
 use prost_types::Timestamp;
 use serde::Serialize;
-use tokio::process::Command;
+use tokio::{process::Command, sync::watch};
 use zenoh::prelude::r#async::*;
 use zenoh::publication::Publisher;
 use zenoh::Session;
 
+use crate::body_controller::motor_telemetry;
 use crate::error::HopperError;
-use crate::zenoh_remotes::topic_consts::{DIAGNOSTIC_METRICS, DIAGNOSTIC_METRICS_JSON};
+use crate::hopper::DiagnosticMessage;
+use crate::ioc_container::IocContainer;
+use crate::motion_controller::{
+    body_state::BodyPhaseChange, MotionControllerService, MotionControllerStatus,
+};
+use crate::zenoh_remotes::topic_consts::{
+    DIAGNOSTIC_METRICS, DIAGNOSTIC_METRICS_JSON, HOPPER_BODY_PHASE_STATUS, SERVO_TELEMETRY,
+    SERVO_TELEMETRY_JSON,
+};
 
-pub async fn start_monitoring_loop(zenoh_session: Arc<Session>) -> anyhow::Result<()> {
+/// Latest diagnostics measured by the monitoring loop
+#[derive(Clone, Default)]
+pub struct DiagnosticsService {
+    latest: Arc<Mutex<Option<DiagnosticMessage>>>,
+}
+
+impl DiagnosticsService {
+    pub fn latest(&self) -> Option<DiagnosticMessage> {
+        self.latest.lock().unwrap().clone()
+    }
+
+    /// Latest diagnostics in the same format as the json topic
+    pub fn latest_json(&self) -> anyhow::Result<Option<serde_json::Value>> {
+        match self.latest() {
+            Some(message) => Ok(Some(serde_json::to_value(message.transcode_to_dynamic())?)),
+            None => Ok(None),
+        }
+    }
+}
+
+pub async fn start_monitoring_loop(
+    zenoh_session: Arc<Session>,
+) -> anyhow::Result<DiagnosticsService> {
     let publisher = zenoh_session
         .declare_publisher(DIAGNOSTIC_METRICS)
         .res()
@@ -25,29 +56,100 @@ pub async fn start_monitoring_loop(zenoh_session: Arc<Session>) -> anyhow::Resul
         .await
         .map_err(HopperError::ZenohError)?;
 
+    let diagnostics_service = DiagnosticsService::default();
+
+    tokio::spawn({
+        let diagnostics_service = diagnostics_service.clone();
+        async move {
+            loop {
+                tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+                if let Err(err) = measure(&publisher, &json_publisher, &diagnostics_service).await {
+                    tracing::error!("Failed to measure metrics: {:?}", err);
+                }
+            }
+        }
+    });
+    Ok(diagnostics_service)
+}
+
+/// Publish servo telemetry every time the motion controller reads it
+pub async fn start_servo_telemetry_publisher(
+    zenoh_session: Arc<Session>,
+    mut status_receiver: watch::Receiver<MotionControllerStatus>,
+) -> anyhow::Result<()> {
+    let publisher = zenoh_session
+        .declare_publisher(SERVO_TELEMETRY)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+
+    let json_publisher = zenoh_session
+        .declare_publisher(SERVO_TELEMETRY_JSON)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+
     tokio::spawn(async move {
-        loop {
-            tokio::time::sleep(std::time::Duration::from_secs(2)).await;
-            if let Err(err) = measure(&publisher, &json_publisher).await {
-                tracing::error!("Failed to measure metrics: {:?}", err);
+        let mut last_telemetry = None;
+        while status_receiver.changed().await.is_ok() {
+            let telemetry = status_receiver.borrow().servo_telemetry;
+            if telemetry == last_telemetry {
+                continue;
+            }
+            last_telemetry = telemetry;
+            if let Some(telemetry) = telemetry {
+                let mut message = motor_telemetry::to_diagnostic_message(&telemetry);
+                message.timestamp = Some(proto_timestamp_now());
+                if let Err(err) = publish(&publisher, &json_publisher, &message).await {
+                    tracing::error!("Failed to publish servo telemetry: {:?}", err);
+                }
             }
         }
     });
     Ok(())
 }
 
-async fn measure(
-    proto_publisher: &Publisher<'_>,
-    json_publisher: &Publisher<'_>,
+/// Publish body phase changes as json
+pub async fn start_body_phase_publisher(
+    zenoh_session: Arc<Session>,
+    mut status_receiver: watch::Receiver<MotionControllerStatus>,
 ) -> anyhow::Result<()> {
+    let publisher = zenoh_session
+        .declare_publisher(HOPPER_BODY_PHASE_STATUS)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+
+    tokio::spawn(async move {
+        let mut last_phase = status_receiver.borrow().body_phase;
+        while status_receiver.changed().await.is_ok() {
+            let phase = status_receiver.borrow().body_phase;
+            if phase == last_phase {
+                continue;
+            }
+            let change = BodyPhaseChange {
+                from: last_phase,
+                to: phase,
+            };
+            last_phase = phase;
+            let message = serde_json::to_string(&change).unwrap();
+            if let Err(err) = publisher.put(message).res().await {
+                tracing::error!("Failed to publish body phase: {:?}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// CPU temperature as reported by vcgencmd
+async fn read_cpu_temperature() -> anyhow::Result<String> {
     let output = Command::new("vcgencmd")
         .arg("measure_temp")
         .kill_on_drop(true)
         .output()
         .await?;
     if !output.status.success() {
-        tracing::error!("Failed reading temperature: {:?}", output);
-        return Ok(());
+        anyhow::bail!("Failed reading temperature: {:?}", output);
     }
     let text = String::from_utf8(output.stdout)?;
     let temp = text
@@ -59,21 +161,84 @@ async fn measure(
         .strip_suffix("'C")
         .ok_or(HopperError::FailedParsingCommandOutput(text.to_owned()))?
         .to_owned();
-    tracing::debug!("CPU temperature: {}", temp);
-    let temperature_float = temp.parse::<f32>().unwrap_or_default();
+    Ok(temp)
+}
+
+async fn measure(
+    proto_publisher: &Publisher<'_>,
+    json_publisher: &Publisher<'_>,
+    diagnostics_service: &DiagnosticsService,
+) -> anyhow::Result<()> {
+    let mut string_values = vec![];
+    let mut float_values = vec![];
 
-    let diagnostic_data = crate::hopper::DiagnosticMessage {
+    match read_cpu_temperature().await {
+        Ok(temp) => {
+            tracing::debug!("CPU temperature: {}", temp);
+            float_values.push(crate::hopper::DiagnosticFloatValue {
+                key: String::from("cpu_temperature"),
+                value: temp.parse::<f32>().unwrap_or_default(),
+            });
+            string_values.push(crate::hopper::DiagnosticStringValue {
+                key: String::from("cpu_temperature"),
+                value: temp,
+            });
+        }
+        Err(error) => tracing::error!("Failed to measure CPU temperature: {:?}", error),
+    }
+
+    // motion controller may not be running yet
+    if let Ok(motion_controller) =
+        IocContainer::global_instance().service::<MotionControllerService>()
+    {
+        let status = motion_controller.status();
+        if let Some(voltage) = status.voltage {
+            float_values.push(crate::hopper::DiagnosticFloatValue {
+                key: String::from("battery_voltage"),
+                value: voltage,
+            });
+        }
+        if let Some(battery_level) = status.battery_level {
+            float_values.push(crate::hopper::DiagnosticFloatValue {
+                key: String::from("battery_level"),
+                value: battery_level,
+            });
+        }
+        string_values.push(crate::hopper::DiagnosticStringValue {
+            key: String::from("battery_state"),
+            value: serde_json::to_value(status.battery_state)?
+                .as_str()
+                .unwrap_or_default()
+                .to_owned(),
+        });
+    }
+
+    if string_values.is_empty() && float_values.is_empty() {
+        return Ok(());
+    }
+
+    let diagnostic_data = DiagnosticMessage {
         timestamp: Some(proto_timestamp_now()),
-        string_values: vec![crate::hopper::DiagnosticStringValue {
-            key: String::from("cpu_temperature"),
-            value: temp,
-        }],
-        float_values: vec![crate::hopper::DiagnosticFloatValue {
-            key: String::from("cpu_temperature"),
-            value: temperature_float,
-        }],
+        string_values,
+        float_values,
     };
 
+    publish(proto_publisher, json_publisher, &diagnostic_data).await?;
+
+    diagnostics_service
+        .latest
+        .lock()
+        .unwrap()
+        .replace(diagnostic_data);
+
+    Ok(())
+}
+
+async fn publish(
+    proto_publisher: &Publisher<'_>,
+    json_publisher: &Publisher<'_>,
+    diagnostic_data: &DiagnosticMessage,
+) -> anyhow::Result<()> {
     proto_publisher
         .put(diagnostic_data.encode_to_vec())
         .res()
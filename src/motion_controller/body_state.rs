@@ -0,0 +1,141 @@
+//! Body state machine
+//!
+//! [`BodyState`] only covers the states the body can rest in.
+//! [`BodyPhase`] also covers what the body is doing between them.
+
+use serde::Serialize;
+
+use super::BodyState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BodyPhase {
+    Standing,
+    Grounded,
+    Folded,
+    StandingUp,
+    SittingDown,
+    Folding,
+    Unfolding,
+    Dancing,
+    /// Motion controller is restarting after an error
+    Recovering,
+    /// Latched until the emergency stop is cleared
+    EmergencyStopped,
+}
+
+impl BodyPhase {
+    /// Resting state. None while moving between states
+    pub fn body_state(self) -> Option<BodyState> {
+        match self {
+            BodyPhase::Standing => Some(BodyState::Standing),
+            BodyPhase::Grounded => Some(BodyState::Grounded),
+            BodyPhase::Folded => Some(BodyState::Folded),
+            _ => None,
+        }
+    }
+}
+
+impl From<BodyState> for BodyPhase {
+    fn from(body_state: BodyState) -> Self {
+        match body_state {
+            BodyState::Standing => BodyPhase::Standing,
+            BodyState::Grounded => BodyPhase::Grounded,
+            BodyState::Folded => BodyPhase::Folded,
+        }
+    }
+}
+
+/// Published on every phase change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BodyPhaseChange {
+    pub from: BodyPhase,
+    pub to: BodyPhase,
+}
+
+/// Result of a body state request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum BodyStateOutcome {
+    Completed { body_state: BodyState },
+    Refused { reason: String },
+    Failed { error: String },
+}
+
+impl BodyStateOutcome {
+    pub fn is_completed(&self) -> bool {
+        matches!(self, BodyStateOutcome::Completed { .. })
+    }
+}
+
+/// Phases passed through on the way between two resting states
+pub fn transition_path(from: BodyState, to: BodyState) -> &'static [BodyPhase] {
+    match (from, to) {
+        (BodyState::Folded, BodyState::Grounded) => &[BodyPhase::Unfolding],
+        (BodyState::Folded, BodyState::Standing) => &[BodyPhase::Unfolding, BodyPhase::StandingUp],
+        (BodyState::Grounded, BodyState::Standing) => &[BodyPhase::StandingUp],
+        (BodyState::Standing, BodyState::Grounded) => &[BodyPhase::SittingDown],
+        (BodyState::Standing, BodyState::Folded) => &[BodyPhase::SittingDown, BodyPhase::Folding],
+        (BodyState::Grounded, BodyState::Folded) => &[BodyPhase::Folding],
+        (BodyState::Standing, BodyState::Standing)
+        | (BodyState::Grounded, BodyState::Grounded)
+        | (BodyState::Folded, BodyState::Folded) => &[],
+    }
+}
+
+/// Reason why no transition can start in this phase
+pub fn transition_blocker(phase: BodyPhase) -> Option<&'static str> {
+    match phase {
+        BodyPhase::Standing | BodyPhase::Grounded | BodyPhase::Folded => None,
+        BodyPhase::Dancing => Some("a dance is in progress"),
+        BodyPhase::Recovering => Some("motion controller is recovering"),
+        BodyPhase::EmergencyStopped => Some("emergency stop is latched"),
+        BodyPhase::StandingUp
+        | BodyPhase::SittingDown
+        | BodyPhase::Folding
+        | BodyPhase::Unfolding => Some("another transition is in progress"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_STATES: [BodyState; 3] =
+        [BodyState::Standing, BodyState::Grounded, BodyState::Folded];
+
+    #[test]
+    fn paths_only_pass_transitional_phases() {
+        for from in ALL_STATES {
+            for to in ALL_STATES {
+                let path = transition_path(from, to);
+                assert_eq!(path.is_empty(), from == to);
+                assert!(path.iter().all(|phase| phase.body_state().is_none()));
+            }
+        }
+        assert_eq!(
+            transition_path(BodyState::Folded, BodyState::Standing),
+            &[BodyPhase::Unfolding, BodyPhase::StandingUp]
+        );
+    }
+
+    #[test]
+    fn transitions_only_start_at_rest() {
+        for state in ALL_STATES {
+            assert_eq!(transition_blocker(state.into()), None);
+        }
+        assert!(transition_blocker(BodyPhase::Dancing).is_some());
+        assert!(transition_blocker(BodyPhase::Unfolding).is_some());
+    }
+
+    #[test]
+    fn outcome_is_tagged() {
+        let outcome = BodyStateOutcome::Refused {
+            reason: String::from("a dance is in progress"),
+        };
+        assert_eq!(
+            serde_json::to_value(&outcome).unwrap(),
+            serde_json::json!({"outcome": "refused", "reason": "a dance is in progress"})
+        );
+    }
+}
@@ -3,10 +3,12 @@ use rand::{seq::SliceRandom, Rng};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
-use tracing::info;
+use tracing::{error, info};
 
+use super::choreography::{interpolate_poses, Choreography, Easing, Keyframe};
 use crate::{
     error::HopperResult,
+    face::FaceController,
     hexapod::LegFlags,
     ik_controller::{
         leg_positions::{LegPositions, MoveTowards},
@@ -14,6 +16,7 @@ use crate::{
     },
     ioc_container::IocContainer,
     speech::SpeechService,
+    zenoh_remotes::face_controller::{color_from_name, set_animation},
 };
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
@@ -118,6 +121,62 @@ impl<'a> Choreographer<'a> {
         Ok(())
     }
 
+    pub async fn execute_choreography(&mut self, choreography: &Choreography) -> HopperResult<()> {
+        let mut interval = tokio::time::interval(TICK_DURATION);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        let mut current_pose = self.starting_pose;
+        for keyframe in &choreography.keyframes {
+            self.start_keyframe_effects(keyframe).await?;
+            let target = keyframe.target_pose(&self.starting_pose);
+            for step in interpolate_poses(
+                &current_pose,
+                &target,
+                keyframe.duration(),
+                TICK_DURATION,
+                keyframe.easing,
+            ) {
+                self.ik_controller.move_to_positions(&step).await?;
+                interval.tick().await;
+            }
+            current_pose = target;
+        }
+
+        for step in interpolate_poses(
+            &current_pose,
+            &self.starting_pose,
+            choreography.return_duration(),
+            TICK_DURATION,
+            Easing::EaseInOut,
+        ) {
+            self.ik_controller.move_to_positions(&step).await?;
+            interval.tick().await;
+        }
+        Ok(())
+    }
+
+    async fn start_keyframe_effects(&mut self, keyframe: &Keyframe) -> HopperResult<()> {
+        if let Some(animation) = &keyframe.face_animation {
+            let color = keyframe
+                .face_color
+                .as_deref()
+                .and_then(color_from_name)
+                .unwrap_or(crate::face::driver::PURPLE);
+            let face_controller = IocContainer::global_instance().service::<FaceController>()?;
+            // face is cosmetic so don't interrupt the dance
+            if let Err(error) = set_animation(&face_controller, animation, color) {
+                error!("Failed to set choreography face animation: {}", error);
+            }
+        }
+        if let Some(sound) = &keyframe.sound {
+            IocContainer::global_instance()
+                .service::<SpeechService>()?
+                .play_sound(sound)
+                .await?;
+        }
+        Ok(())
+    }
+
     async fn wave_hi(&mut self, play_audio: bool) -> HopperResult<()> {
         let mut interval = tokio::time::interval(TICK_DURATION);
         interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
@@ -0,0 +1,321 @@
+use nalgebra::{Isometry3, Point3, Translation3, UnitQuaternion, Vector3};
+use serde::{Deserialize, Deserializer};
+use std::{collections::BTreeMap, path::Path, sync::Arc, time::Duration};
+use tracing::*;
+
+use crate::{hexapod::LegFlags, ik_controller::leg_positions::LegPositions};
+
+/// Choreography loaded from a keyframe file
+///
+/// Keyframes are absolute offsets from the pose the choreography started in.
+/// The body returns to the starting pose after the last keyframe.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Choreography {
+    /// Short description used when offering the choreography to OpenAI
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "default_return_duration_ms")]
+    pub return_duration_ms: u64,
+    pub keyframes: Vec<Keyframe>,
+}
+
+fn default_return_duration_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Keyframe {
+    /// Time to reach this keyframe from the previous one
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub easing: Easing,
+    /// Body translation in meters. Positive z lifts the body
+    #[serde(default)]
+    pub body_translation: [f32; 3],
+    /// Body rotation as roll, pitch and yaw in radians
+    #[serde(default)]
+    pub body_rotation: [f32; 3],
+    /// Offsets of selected feet applied after the body motion
+    #[serde(default)]
+    pub legs: Vec<LegKeyframe>,
+    /// Sound from the audio repository played when the keyframe starts
+    #[serde(default)]
+    pub sound: Option<String>,
+    /// Face animation set when the keyframe starts
+    #[serde(default)]
+    pub face_animation: Option<String>,
+    #[serde(default)]
+    pub face_color: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LegKeyframe {
+    /// Leg names such as `left_front`, `middle` or `lrl_tripod`
+    #[serde(deserialize_with = "deserialize_leg_flags")]
+    pub legs: LegFlags,
+    /// Foot translation in body frame in meters
+    pub translation: [f32; 3],
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Map linear progress in range 0 to 1 onto eased progress
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+fn deserialize_leg_flags<'de, D>(deserializer: D) -> Result<LegFlags, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let names = Vec::<String>::deserialize(deserializer)?;
+    LegFlags::parse_names(&names).map_err(serde::de::Error::custom)
+}
+
+impl Keyframe {
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms)
+    }
+
+    /// Foot positions of this keyframe for given starting pose
+    pub fn target_pose(&self, starting_pose: &LegPositions) -> LegPositions {
+        let [roll, pitch, yaw] = self.body_rotation;
+        let body = Isometry3::from_parts(
+            Translation3::from(Vector3::from(self.body_translation)),
+            UnitQuaternion::from_euler_angles(roll, pitch, yaw),
+        );
+        // feet stay in place so in body frame they move opposite to the body
+        let body_inverse = body.inverse();
+        let mut pose =
+            starting_pose.transform(body_inverse.translation.vector, body_inverse.rotation);
+        for leg_keyframe in &self.legs {
+            pose = pose.transform_selected_legs(
+                Vector3::from(leg_keyframe.translation),
+                UnitQuaternion::identity(),
+                leg_keyframe.legs,
+            );
+        }
+        pose
+    }
+}
+
+impl Choreography {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let choreography: Self = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => toml::from_str(&text)?,
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&text)?,
+            _ => anyhow::bail!("Unsupported choreography file {:?}", path),
+        };
+        anyhow::ensure!(
+            !choreography.keyframes.is_empty(),
+            "Choreography {:?} has no keyframes",
+            path
+        );
+        Ok(choreography)
+    }
+
+    pub fn return_duration(&self) -> Duration {
+        Duration::from_millis(self.return_duration_ms)
+    }
+}
+
+/// Poses interpolated between start and target spaced by tick
+///
+/// Last pose is always the target
+pub fn interpolate_poses(
+    start: &LegPositions,
+    target: &LegPositions,
+    duration: Duration,
+    tick: Duration,
+    easing: Easing,
+) -> Vec<LegPositions> {
+    let steps = (duration.as_secs_f32() / tick.as_secs_f32())
+        .ceil()
+        .max(1.0) as usize;
+    (1..=steps)
+        .map(|step| {
+            let t = easing.apply(step as f32 / steps as f32);
+            let legs: Vec<Point3<f32>> = start
+                .as_legs()
+                .iter()
+                .zip(target.as_legs().iter())
+                .map(|(start, target)| start.coords.lerp(&target.coords, t).into())
+                .collect();
+            LegPositions::from_legs([&legs[0], &legs[1], &legs[2], &legs[3], &legs[4], &legs[5]])
+        })
+        .collect()
+}
+
+/// Choreographies loaded from a directory keyed by file name
+#[derive(Debug, Default)]
+pub struct ChoreographyLibrary {
+    choreographies: BTreeMap<String, Arc<Choreography>>,
+}
+
+impl ChoreographyLibrary {
+    /// Load all toml and yaml files in directory
+    ///
+    /// Files that fail to parse are logged and skipped
+    pub fn load_directory(directory: &Path) -> Self {
+        let mut choreographies = BTreeMap::new();
+        let entries = match std::fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(error) => {
+                warn!(
+                    "Failed to read choreography directory {:?}: {}",
+                    directory, error
+                );
+                return Self::default();
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_lowercase(),
+                None => continue,
+            };
+            match Choreography::from_file(&path) {
+                Ok(choreography) => {
+                    info!("Loaded choreography {} from {:?}", name, path);
+                    choreographies.insert(name, Arc::new(choreography));
+                }
+                Err(error) => error!("Failed to load choreography {:?}: {}", path, error),
+            }
+        }
+        Self { choreographies }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Choreography>> {
+        self.choreographies.get(&name.to_lowercase()).cloned()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.choreographies.keys().cloned().collect()
+    }
+
+    /// Names with descriptions in a human readable list
+    pub fn describe(&self) -> String {
+        self.choreographies
+            .iter()
+            .map(|(name, choreography)| {
+                if choreography.description.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{} ({})", name, choreography.description)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::motion_controller::stance;
+    use approx::assert_relative_eq;
+
+    const TEST_CHOREOGRAPHY: &str = r#"
+description = "test"
+
+[[keyframes]]
+duration_ms = 100
+easing = "ease_in_out"
+body_translation = [0.0, 0.0, 0.02]
+
+[[keyframes]]
+duration_ms = 200
+sound = "beep.wav"
+
+[[keyframes.legs]]
+legs = ["left_front", "right_rear"]
+translation = [0.0, 0.0, 0.05]
+"#;
+
+    #[test]
+    fn parse_toml_choreography() {
+        let choreography: Choreography = toml::from_str(TEST_CHOREOGRAPHY).unwrap();
+        assert_eq!(choreography.keyframes.len(), 2);
+        assert_eq!(choreography.return_duration_ms, 1000);
+        assert_eq!(choreography.keyframes[0].easing, Easing::EaseInOut);
+        assert_eq!(
+            choreography.keyframes[1].legs[0].legs,
+            LegFlags::LEFT_FRONT | LegFlags::RIGHT_REAR
+        );
+    }
+
+    #[test]
+    fn unknown_leg_name_is_rejected() {
+        let text = TEST_CHOREOGRAPHY.replace("right_rear", "tail");
+        assert!(toml::from_str::<Choreography>(&text).is_err());
+    }
+
+    #[test]
+    fn lifting_body_lowers_feet() {
+        let choreography: Choreography = toml::from_str(TEST_CHOREOGRAPHY).unwrap();
+        let start = stance::relaxed_stance();
+        let target = choreography.keyframes[0].target_pose(start);
+        assert_relative_eq!(target.left_front().z, start.left_front().z - 0.02);
+
+        let target = choreography.keyframes[1].target_pose(start);
+        assert_relative_eq!(target.left_front().z, start.left_front().z + 0.05);
+        assert_relative_eq!(target.left_middle().z, start.left_middle().z);
+    }
+
+    #[test]
+    fn interpolation_ends_on_target() {
+        let start = stance::relaxed_stance();
+        let target = start.transform(Vector3::new(0.0, 0.0, 0.02), UnitQuaternion::identity());
+        let poses = interpolate_poses(
+            start,
+            &target,
+            Duration::from_millis(100),
+            Duration::from_millis(20),
+            Easing::EaseIn,
+        );
+        assert_eq!(poses.len(), 5);
+        assert_eq!(poses.last(), Some(&target));
+        // ease in starts slowly
+        assert!(poses[0].left_front().z - start.left_front().z < 0.02 / 5.0);
+    }
+
+    #[test]
+    fn easing_keeps_endpoints() {
+        for easing in [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ] {
+            assert_relative_eq!(easing.apply(0.0), 0.0);
+            assert_relative_eq!(easing.apply(1.0), 1.0);
+        }
+    }
+
+    #[test]
+    fn example_choreographies_load() {
+        for entry in std::fs::read_dir("config/choreography").unwrap() {
+            Choreography::from_file(&entry.unwrap().path()).unwrap();
+        }
+        let library = ChoreographyLibrary::load_directory(Path::new("config/choreography"));
+        assert!(library.get("bow").is_some());
+        assert!(library.get("Stretch").is_some());
+    }
+}
@@ -0,0 +1,74 @@
+//! Latched emergency stop
+//!
+//! Triggering interrupts whatever the control loop is doing at its next await.
+//! The stop stays latched until it's explicitly cleared.
+
+use std::sync::Arc;
+use tokio::sync::watch;
+use tracing::*;
+
+#[derive(Clone)]
+pub struct EmergencyStop {
+    latched: Arc<watch::Sender<bool>>,
+}
+
+impl Default for EmergencyStop {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EmergencyStop {
+    pub fn new() -> Self {
+        let (latched, _) = watch::channel(false);
+        Self {
+            latched: Arc::new(latched),
+        }
+    }
+
+    pub fn trigger(&self) {
+        if self.latched.send_replace(true) {
+            return;
+        }
+        error!("Emergency stop triggered");
+    }
+
+    pub fn clear(&self) {
+        if self.latched.send_replace(false) {
+            warn!("Emergency stop cleared");
+        }
+    }
+
+    pub fn is_latched(&self) -> bool {
+        *self.latched.borrow()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.latched.subscribe()
+    }
+}
+
+/// Resolves once the stop is latched
+pub async fn wait_until_latched(receiver: &mut watch::Receiver<bool>) {
+    if receiver.wait_for(|latched| *latched).await.is_err() {
+        // nobody is left to trigger it
+        std::future::pending::<()>().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stays_latched_until_cleared() {
+        let emergency_stop = EmergencyStop::new();
+        let mut receiver = emergency_stop.subscribe();
+        emergency_stop.trigger();
+        emergency_stop.trigger();
+        wait_until_latched(&mut receiver).await;
+        assert!(emergency_stop.clone().is_latched());
+        emergency_stop.clear();
+        assert!(!emergency_stop.is_latched());
+    }
+}
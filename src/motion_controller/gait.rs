@@ -0,0 +1,447 @@
+use crate::hexapod::{LegFlags, LEGS_IN_ORDER};
+use crate::ik_controller::leg_positions::LegPositions;
+use nalgebra::{Point3, UnitQuaternion, Vector2, Vector3};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use super::walking::{step_lifted_leg, step_with_relaxed_transformation, MoveCommand, Tripod};
+
+const TRIPOD_PHASES: [LegFlags; 2] = [LegFlags::LRL_TRIPOD, LegFlags::RLR_TRIPOD];
+
+/// Two diagonal legs lifted at a time
+const RIPPLE_PHASES: [LegFlags; 3] = [
+    LegFlags::LEFT_REAR.union(LegFlags::RIGHT_FRONT),
+    LegFlags::LEFT_MIDDLE.union(LegFlags::RIGHT_REAR),
+    LegFlags::LEFT_FRONT.union(LegFlags::RIGHT_MIDDLE),
+];
+
+/// Single leg lifted at a time going from rear to front
+const WAVE_PHASES: [LegFlags; 6] = [
+    LegFlags::LEFT_REAR,
+    LegFlags::LEFT_MIDDLE,
+    LegFlags::LEFT_FRONT,
+    LegFlags::RIGHT_REAR,
+    LegFlags::RIGHT_MIDDLE,
+    LegFlags::RIGHT_FRONT,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GaitType {
+    /// Three legs lifted at a time. Fastest gait
+    #[default]
+    Tripod,
+    /// Two legs lifted at a time
+    Ripple,
+    /// One leg lifted at a time. Slowest but most stable gait
+    Wave,
+}
+
+impl GaitType {
+    /// Groups of legs that get lifted together in order
+    pub(crate) fn phases(&self) -> &'static [LegFlags] {
+        match self {
+            GaitType::Tripod => &TRIPOD_PHASES,
+            GaitType::Ripple => &RIPPLE_PHASES,
+            GaitType::Wave => &WAVE_PHASES,
+        }
+    }
+}
+
+/// How fast the walking command blends towards a new command
+///
+/// In meters of step distance per second
+const DIRECTION_BLEND_RATE: f32 = 0.1;
+/// In radians of step rotation per second
+const ROTATION_BLEND_RATE: f32 = std::f32::consts::PI / 3.0;
+/// Longest time the oscillator advances in one tick
+const MAX_TICK_DURATION: Duration = Duration::from_millis(100);
+
+/// Continuous walking driven by a phase oscillator
+///
+/// Every leg has its own part of the gait cycle during which it swings.
+/// Grounded legs continuously push the body by the current velocity
+/// and lifted legs aim for a touchdown point that is recalculated every tick.
+/// This allows changes of direction and rotation in the middle of a step.
+#[derive(Debug, Clone)]
+pub(crate) struct GaitOscillator {
+    gait_type: GaitType,
+    /// Position in the gait cycle in range [0, 1)
+    phase: f32,
+    /// Current step blending towards the commanded one
+    direction: Vector2<f32>,
+    rotation: f32,
+    swinging_legs: LegFlags,
+    /// Progress of the current swing in range [0, 1)
+    swing_progress: f32,
+    lift_off: LegPositions,
+    /// Legs that were placed in relaxed position since walking stopped
+    reset_legs: LegFlags,
+    last_tick: Option<Instant>,
+}
+
+impl GaitOscillator {
+    pub(crate) fn new(starting_pose: LegPositions) -> Self {
+        Self {
+            gait_type: GaitType::default(),
+            phase: 0.0,
+            direction: Vector2::zeros(),
+            rotation: 0.0,
+            swinging_legs: LegFlags::empty(),
+            swing_progress: 0.0,
+            lift_off: starting_pose,
+            reset_legs: LegFlags::empty(),
+            last_tick: None,
+        }
+    }
+
+    /// All legs are in relaxed position and no step is in progress
+    pub(crate) fn is_settled(&self) -> bool {
+        self.reset_legs.contains(LegFlags::ALL) && self.swinging_legs.is_empty()
+    }
+
+    /// Legs lifted during the last tick
+    pub(crate) fn swinging_legs(&self) -> LegFlags {
+        self.swinging_legs
+    }
+
+    pub(crate) fn swing_progress(&self) -> f32 {
+        self.swing_progress
+    }
+
+    /// Advance oscillator by time since last tick
+    pub(crate) fn tick(
+        &mut self,
+        last: &LegPositions,
+        relaxed: &LegPositions,
+        command: &MoveCommand,
+        default_tick: Duration,
+    ) -> LegPositions {
+        let now = Instant::now();
+        let elapsed = self
+            .last_tick
+            .filter(|_| !self.is_settled())
+            .map(|last_tick| now - last_tick)
+            .unwrap_or(default_tick)
+            .min(MAX_TICK_DURATION);
+        self.last_tick = Some(now);
+        self.advance(elapsed, last, relaxed, command)
+    }
+
+    pub(crate) fn advance(
+        &mut self,
+        elapsed: Duration,
+        last: &LegPositions,
+        relaxed: &LegPositions,
+        command: &MoveCommand,
+    ) -> LegPositions {
+        let elapsed_secs = elapsed.as_secs_f32();
+        self.blend_command(command, elapsed_secs);
+        if command.should_move() {
+            self.reset_legs = LegFlags::empty();
+        }
+        let blended_command = MoveCommand::new(self.direction, self.rotation);
+        let step_time = command.step_time().as_secs_f32().max(f32::EPSILON);
+
+        let previous_gait_type = self.gait_type;
+        let previous_group = self.current_group();
+        let phase_count = self.gait_type.phases().len() as f32;
+        self.phase = (self.phase + elapsed_secs / (step_time * phase_count)).fract();
+        let started_step = self.current_group() != previous_group;
+        if started_step && command.gait() != self.gait_type {
+            // only switch gaits between steps
+            self.gait_type = command.gait();
+            self.phase = 0.0;
+        }
+        let phase_count = self.gait_type.phases().len();
+        let swinging_group = self.gait_type.phases()[self.current_group()];
+        let swing_progress = (self.phase * phase_count as f32).fract();
+        self.swing_progress = swing_progress;
+        // legs only lift at the start of their step and only if they aren't already in place
+        let swinging_legs = if (started_step || self.swinging_legs == swinging_group)
+            && !self.reset_legs.contains(swinging_group)
+        {
+            swinging_group
+        } else {
+            LegFlags::empty()
+        };
+
+        // grounded legs push the body
+        let grounded_time = (phase_count - 1) as f32 * step_time;
+        let velocity = self.direction.to_homogeneous() * 2.0 / grounded_time;
+        let angular_velocity = self.rotation / grounded_time;
+        let grounded_rotation =
+            UnitQuaternion::from_axis_angle(&Vector3::z_axis(), -angular_velocity * elapsed_secs);
+        let mut positions = last.transform(
+            -(grounded_rotation * velocity * elapsed_secs),
+            grounded_rotation,
+        );
+
+        // legs that finished their swing land on their touchdown point
+        let landed_legs = self.swinging_legs - swinging_legs;
+        if !landed_legs.is_empty() {
+            let touchdown = step_with_gait(
+                last,
+                relaxed,
+                previous_gait_type,
+                self.swinging_legs,
+                blended_command,
+            );
+            positions = positions.merge_with(&touchdown, landed_legs);
+            if self.direction.norm() < f32::EPSILON && self.rotation.abs() < f32::EPSILON {
+                self.reset_legs |= landed_legs;
+            } else {
+                self.reset_legs = LegFlags::empty();
+            }
+        }
+
+        let lifting_legs = swinging_legs - self.swinging_legs;
+        self.lift_off = self.lift_off.merge_with(last, lifting_legs);
+        self.swinging_legs = swinging_legs;
+        if swinging_legs.is_empty() {
+            return positions;
+        }
+
+        let touchdown = step_with_gait(
+            last,
+            relaxed,
+            self.gait_type,
+            swinging_group,
+            blended_command,
+        );
+        let lift_off = self.lift_off.as_legs();
+        let last = last.as_legs();
+        let touchdown = touchdown.as_legs();
+        let mut lifted = [Point3::origin(); 6];
+        for (index, leg) in LEGS_IN_ORDER.iter().enumerate() {
+            if self.swinging_legs.contains(*leg) {
+                (lifted[index], _) = step_lifted_leg(
+                    lift_off[index],
+                    last[index],
+                    touchdown[index],
+                    command.step_height(),
+                    swing_progress,
+                    command.aggressive_leg_lift(),
+                );
+            }
+        }
+        let lifted = LegPositions::from_legs([
+            &lifted[0], &lifted[1], &lifted[2], &lifted[3], &lifted[4], &lifted[5],
+        ]);
+        positions.merge_with(&lifted, self.swinging_legs)
+    }
+
+    fn current_group(&self) -> usize {
+        let phase_count = self.gait_type.phases().len();
+        ((self.phase * phase_count as f32) as usize).min(phase_count - 1)
+    }
+
+    fn blend_command(&mut self, command: &MoveCommand, elapsed_secs: f32) {
+        let direction_error = command.direction() - self.direction;
+        let max_direction_change = DIRECTION_BLEND_RATE * elapsed_secs;
+        if direction_error.norm() <= max_direction_change {
+            self.direction = command.direction();
+        } else {
+            self.direction += direction_error.normalize() * max_direction_change;
+        }
+        let rotation_error = command.rotation() - self.rotation;
+        let max_rotation_change = ROTATION_BLEND_RATE * elapsed_secs;
+        self.rotation += rotation_error.clamp(-max_rotation_change, max_rotation_change);
+    }
+}
+
+/// Calculate target of a single step of a gait
+///
+/// Lifted legs are placed a full step forward of the relaxed stance.
+/// Grounded legs push the body by a share of the stride so that every leg ends
+/// a full step behind the relaxed stance, same as the tripod.
+pub(crate) fn step_with_gait(
+    start: &LegPositions,
+    relaxed: &LegPositions,
+    gait_type: GaitType,
+    lifted_legs: LegFlags,
+    command: MoveCommand,
+) -> LegPositions {
+    // tripod has its own drift correction so keep using it
+    if gait_type == GaitType::Tripod {
+        let tripod = if lifted_legs == LegFlags::LRL_TRIPOD {
+            Tripod::LRL
+        } else {
+            Tripod::RLR
+        };
+        return step_with_relaxed_transformation(start, relaxed, &tripod, command);
+    }
+    let grounded_phases = (gait_type.phases().len() - 1) as f32;
+    let linear_motion = command.direction().to_homogeneous();
+
+    let lifted_rotation =
+        UnitQuaternion::from_axis_angle(&Vector3::z_axis(), command.rotation() / 2.0);
+    let lifted = relaxed.transform(lifted_rotation * linear_motion, lifted_rotation);
+
+    // legs travel from +step to -step over all grounded phases
+    let grounded_rotation =
+        UnitQuaternion::from_axis_angle(&Vector3::z_axis(), -command.rotation() / grounded_phases);
+    let grounded_motion = linear_motion * 2.0 / grounded_phases;
+    let grounded = start.transform(-(grounded_rotation * grounded_motion), grounded_rotation);
+
+    grounded.merge_with(&lifted, lifted_legs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::motion_controller::stance::relaxed_stance;
+    use approx::assert_relative_eq;
+    use nalgebra::Vector2;
+
+    const TICK: Duration = Duration::from_millis(20);
+
+    fn walk_for(
+        oscillator: &mut GaitOscillator,
+        pose: LegPositions,
+        command: MoveCommand,
+        duration: Duration,
+    ) -> LegPositions {
+        let relaxed = *relaxed_stance();
+        let mut pose = pose;
+        for _ in 0..(duration.as_millis() / TICK.as_millis()) {
+            pose = oscillator.advance(TICK, &pose, &relaxed, &command);
+        }
+        pose
+    }
+
+    #[test]
+    fn every_leg_lifted_once_per_cycle() {
+        for gait_type in [GaitType::Tripod, GaitType::Ripple, GaitType::Wave] {
+            let mut lifted = LegFlags::empty();
+            for legs in gait_type.phases() {
+                assert!(!lifted.intersects(*legs), "{:?} lifts leg twice", gait_type);
+                lifted |= *legs;
+            }
+            assert_eq!(lifted, LegFlags::ALL);
+        }
+    }
+
+    #[test]
+    fn oscillator_settles_in_relaxed_after_stop() {
+        let relaxed = *relaxed_stance();
+        let mut oscillator = GaitOscillator::new(relaxed);
+        let command = MoveCommand::new(Vector2::new(0.02, 0.01), 0.1);
+        let pose = walk_for(&mut oscillator, relaxed, command, Duration::from_secs(3));
+        assert!(!oscillator.is_settled());
+        let pose = walk_for(
+            &mut oscillator,
+            pose,
+            MoveCommand::default(),
+            Duration::from_secs(3),
+        );
+        assert!(oscillator.is_settled());
+        for (leg, relaxed_leg) in pose.as_legs().iter().zip(relaxed.as_legs()) {
+            assert_relative_eq!(leg.x, relaxed_leg.x, epsilon = 0.0001);
+            assert_relative_eq!(leg.y, relaxed_leg.y, epsilon = 0.0001);
+            assert_relative_eq!(leg.z, relaxed_leg.z, epsilon = 0.0001);
+        }
+    }
+
+    #[test]
+    fn command_change_applies_mid_step() {
+        let relaxed = *relaxed_stance();
+        let mut oscillator = GaitOscillator::new(relaxed);
+        let forward = MoveCommand::new(Vector2::new(0.02, 0.0), 0.0);
+        let pose = walk_for(&mut oscillator, relaxed, forward, Duration::from_secs(2));
+        let swinging_legs = oscillator.swinging_legs;
+        let backward = MoveCommand::new(Vector2::new(-0.02, 0.0), 0.0);
+        walk_for(&mut oscillator, pose, backward, Duration::from_millis(100));
+        // still the same step but direction is already blending
+        assert_eq!(oscillator.swinging_legs, swinging_legs);
+        assert_relative_eq!(oscillator.direction.x, 0.01, epsilon = 0.0001);
+    }
+
+    #[test]
+    fn grounded_legs_move_with_velocity() {
+        let relaxed = *relaxed_stance();
+        let mut oscillator = GaitOscillator::new(relaxed);
+        let command = MoveCommand::new(Vector2::new(0.02, 0.0), 0.0);
+        let pose = walk_for(&mut oscillator, relaxed, command, Duration::from_secs(2));
+        let grounded = LegFlags::ALL - oscillator.swinging_legs;
+        let next = oscillator.advance(TICK, &pose, &relaxed, &command);
+        // tripod moves full step in each direction during one step time
+        let expected = 2.0 * 0.02 / command.step_time().as_secs_f32() * TICK.as_secs_f32();
+        for (before, after) in pose
+            .selected_legs(grounded)
+            .into_iter()
+            .zip(next.selected_legs(grounded))
+        {
+            assert_relative_eq!(before.x - after.x, expected, epsilon = 0.00001);
+        }
+    }
+
+    #[test]
+    fn gait_switches_between_steps() {
+        let relaxed = *relaxed_stance();
+        let mut oscillator = GaitOscillator::new(relaxed);
+        let command = MoveCommand::new(Vector2::new(0.02, 0.0), 0.0);
+        let pose = walk_for(
+            &mut oscillator,
+            relaxed,
+            command,
+            Duration::from_millis(700),
+        );
+        let wave = command.with_gait(GaitType::Wave);
+        let pose = walk_for(&mut oscillator, pose, wave, Duration::from_millis(100));
+        assert_eq!(oscillator.gait_type, GaitType::Tripod);
+        walk_for(&mut oscillator, pose, wave, Duration::from_millis(600));
+        assert_eq!(oscillator.gait_type, GaitType::Wave);
+    }
+
+    #[test]
+    fn wave_gait_returns_to_relaxed_position() {
+        let relaxed = *relaxed_stance();
+        let command = MoveCommand::new(Vector2::new(0.03, 0.0), 0.0);
+        let mut pose = relaxed;
+        for lifted in WAVE_PHASES.iter().chain(WAVE_PHASES.iter()) {
+            pose = step_with_gait(&pose, &relaxed, GaitType::Wave, *lifted, command);
+        }
+        let stop = MoveCommand::default();
+        for lifted in WAVE_PHASES {
+            pose = step_with_gait(&pose, &relaxed, GaitType::Wave, lifted, stop);
+        }
+        for (leg, relaxed_leg) in pose.as_legs().iter().zip(relaxed.as_legs()) {
+            assert_relative_eq!(leg.x, relaxed_leg.x, epsilon = 0.0001);
+            assert_relative_eq!(leg.y, relaxed_leg.y, epsilon = 0.0001);
+        }
+    }
+
+    #[test]
+    fn wave_gait_stride_matches_tripod() {
+        let relaxed = *relaxed_stance();
+        let command = MoveCommand::new(Vector2::new(0.03, 0.0), 0.0);
+        let first_lifted = WAVE_PHASES[0];
+        let relaxed_x = relaxed.selected_legs(first_lifted)[0].x;
+        let mut pose = step_with_gait(&relaxed, &relaxed, GaitType::Wave, first_lifted, command);
+        assert_relative_eq!(
+            pose.selected_legs(first_lifted)[0].x,
+            relaxed_x + 0.03,
+            epsilon = 0.0001
+        );
+        for lifted in &WAVE_PHASES[1..] {
+            pose = step_with_gait(&pose, &relaxed, GaitType::Wave, *lifted, command);
+        }
+        assert_relative_eq!(
+            pose.selected_legs(first_lifted)[0].x,
+            relaxed_x - 0.03,
+            epsilon = 0.0001
+        );
+    }
+
+    #[test]
+    fn ripple_gait_grounded_legs_push_body() {
+        let relaxed = *relaxed_stance();
+        let command = MoveCommand::new(Vector2::new(0.02, 0.0), 0.0);
+        let lifted = RIPPLE_PHASES[0];
+        let pose = step_with_gait(&relaxed, &relaxed, GaitType::Ripple, lifted, command);
+        assert_relative_eq!(pose.left_rear().x, relaxed.left_rear().x + 0.02);
+        assert_relative_eq!(pose.left_front().x, relaxed.left_front().x - 0.02);
+    }
+}
@@ -0,0 +1,191 @@
+//! Runtime updates of leg geometry and stances
+//!
+//! Body config and stance files are polled for changes. The same TOML can also be
+//! published over zenoh. The motion controller only applies updates while grounded
+//! or standing still.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tracing::*;
+use zenoh::prelude::r#async::*;
+
+use super::{
+    stance::{load_stance, StanceKind},
+    MotionControllerService,
+};
+use crate::{
+    configuration::HotReloadConfig,
+    error::HopperError,
+    hopper_body_config::HopperConfig,
+    ik_controller::leg_positions::LegPositions,
+    zenoh_remotes::topic_consts::{BODY_CONFIG_SUBSCRIBER, STANCE_CONFIG_SUBSCRIBER},
+};
+
+#[derive(Debug, Clone)]
+pub enum BodyUpdate {
+    BodyConfig(Box<HopperConfig>),
+    Stance(StanceKind, LegPositions),
+}
+
+/// Tracks modification time of a file
+struct WatchedFile {
+    path: PathBuf,
+    last_modified: Option<SystemTime>,
+}
+
+impl WatchedFile {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            last_modified: None,
+        }
+    }
+
+    /// Already loaded files are only reported once they change
+    fn already_loaded(path: PathBuf) -> Self {
+        let last_modified = modified(&path);
+        Self {
+            path,
+            last_modified,
+        }
+    }
+
+    /// True if file exists and changed since last check
+    fn changed(&mut self) -> bool {
+        let modified = modified(&self.path);
+        if modified.is_some() && modified != self.last_modified {
+            self.last_modified = modified;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn parse_stance_sample(sample: Sample) -> anyhow::Result<BodyUpdate> {
+    let name = sample
+        .key_expr
+        .as_str()
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    let kind =
+        StanceKind::from_name(name).ok_or_else(|| anyhow::anyhow!("Unknown stance {}", name))?;
+    let text: String = sample.value.try_into()?;
+    Ok(BodyUpdate::Stance(kind, toml::from_str(&text)?))
+}
+
+fn parse_body_config_sample(sample: Sample) -> anyhow::Result<BodyUpdate> {
+    let text: String = sample.value.try_into()?;
+    Ok(BodyUpdate::BodyConfig(Box::new(toml::from_str(&text)?)))
+}
+
+/// Forward changed files and zenoh updates to the motion controller
+///
+/// Existing stance files are loaded on start. The body config file was already
+/// loaded on boot so it's only forwarded once it changes.
+pub async fn start_body_config_reloader(
+    zenoh_session: Arc<Session>,
+    motion_controller: MotionControllerService,
+    body_config_path: Option<PathBuf>,
+    config: HotReloadConfig,
+) -> anyhow::Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let body_config_subscriber = zenoh_session
+        .declare_subscriber(BODY_CONFIG_SUBSCRIBER)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+    let stance_subscriber = zenoh_session
+        .declare_subscriber(STANCE_CONFIG_SUBSCRIBER)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+
+    let mut body_config_file = body_config_path.map(WatchedFile::already_loaded);
+    let mut stance_files: Vec<_> = StanceKind::ALL
+        .into_iter()
+        .map(|kind| {
+            let path = Path::new(&config.stance_directory).join(kind.file_name());
+            (kind, WatchedFile::new(path))
+        })
+        .collect();
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(config.poll_period_ms));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if let Some(file) = body_config_file.as_mut() {
+                        if file.changed() {
+                            match HopperConfig::load(&file.path) {
+                                Ok(body_config) => {
+                                    info!("Body config {:?} changed", file.path);
+                                    motion_controller
+                                        .update_body(BodyUpdate::BodyConfig(Box::new(body_config)));
+                                }
+                                Err(error) => warn!("Failed to load body config {:?}: {}", file.path, error),
+                            }
+                        }
+                    }
+                    for (kind, file) in stance_files.iter_mut() {
+                        if !file.changed() {
+                            continue;
+                        }
+                        match load_stance(&file.path) {
+                            Ok(stance) => {
+                                info!("Stance {:?} loaded from {:?}", kind, file.path);
+                                motion_controller.update_body(BodyUpdate::Stance(*kind, stance));
+                            }
+                            Err(error) => warn!("Failed to load stance {:?}: {}", file.path, error),
+                        }
+                    }
+                }
+                Ok(sample) = body_config_subscriber.recv_async() => {
+                    match parse_body_config_sample(sample) {
+                        Ok(update) => motion_controller.update_body(update),
+                        Err(error) => warn!("Failed to parse body config: {}", error),
+                    }
+                }
+                Ok(sample) = stance_subscriber.recv_async() => {
+                    match parse_stance_sample(sample) {
+                        Ok(update) => motion_controller.update_body(update),
+                        Err(error) => warn!("Failed to parse stance: {}", error),
+                    }
+                }
+                else => break,
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watched_file_reports_changes_once() {
+        let dir = tempdir::TempDir::new("hot_reload").unwrap();
+        let path = dir.path().join("relaxed.toml");
+        let mut file = WatchedFile::new(path.clone());
+        assert!(!file.changed());
+        fs::write(&path, "").unwrap();
+        assert!(file.changed());
+        assert!(!file.changed());
+        assert!(!WatchedFile::already_loaded(path).changed());
+    }
+}
@@ -1,21 +1,36 @@
+pub mod body_state;
 mod choreographer;
+pub mod choreography;
+pub mod estop;
 pub mod folding;
+pub mod gait;
+pub mod hot_reload;
 pub mod stance;
+mod terrain;
 #[cfg(feature = "visualizer")]
 pub mod visualizer;
 pub mod walking;
 
+use crate::battery::{BatteryMonitor, BatteryState};
 use crate::body_controller::motor_controller::{HexapodCompliance, HexapodMotorSpeed};
+use crate::body_controller::motor_telemetry::{
+    named_motors, BodyMotorTelemetry, ServoHealth, ServoHealthMonitor,
+};
+use crate::configuration::{
+    BatteryConfig, EstopAction, EstopConfig, ServoTelemetryConfig, TerrainConfig,
+};
 use crate::error::{HopperError, HopperResult};
-use crate::face::FaceController;
+use crate::face::{animations::Animation, FaceController};
 use crate::hexapod::LegFlags;
 use crate::high_five::{HighFiveCommand, HighFiveDetector};
+use crate::hopper_body_config::HopperConfig;
 use crate::ik_controller::{
     leg_positions::{LegPositions, MoveTowards},
+    validation::calculate_validated_ik,
     IkControllable,
 };
 use crate::ioc_container::IocContainer;
-use crate::speech::SpeechService;
+use crate::speech::{AzureVoiceStyle, SpeechService};
 use crate::utilities::{MpscChannelHelper, RateTracker};
 
 use nalgebra::{Point3, UnitQuaternion, Vector3};
@@ -23,14 +38,26 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::VecDeque,
-    sync::mpsc,
+    sync::{mpsc, Arc},
     time::{Duration, Instant},
 };
-use tokio::{spawn, sync::mpsc::Receiver, task::JoinHandle, time};
+use tokio::{
+    spawn,
+    sync::{mpsc::Receiver, oneshot, watch},
+    task::JoinHandle,
+    time,
+};
 use tracing::*;
 
+use body_state::{BodyPhase, BodyStateOutcome};
 use choreographer::Choreographer;
+use choreography::Choreography;
+use estop::EmergencyStop;
 use folding::FoldingManager;
+use gait::GaitOscillator;
+use hot_reload::BodyUpdate;
+use stance::{StanceKind, Stances};
+use terrain::TerrainAdapter;
 use walking::*;
 
 pub use choreographer::DanceMove;
@@ -44,9 +71,30 @@ pub enum BodyState {
     Folded = 2,
 }
 
+/// Latest state reported by the control loop
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MotionControllerStatus {
+    pub body_state: BodyState,
+    /// Current phase including transitions between body states
+    pub body_phase: BodyPhase,
+    /// Latest move command received by the control loop
+    pub move_command: MoveCommand,
+    /// Mean motor voltage
+    pub voltage: Option<f32>,
+    /// Estimated charge between 0 and 1
+    pub battery_level: Option<f32>,
+    pub battery_state: BatteryState,
+    /// Latest temperature, load and errors of all servos
+    #[serde(skip)]
+    pub servo_telemetry: Option<BodyMotorTelemetry>,
+    pub servo_health: ServoHealth,
+}
+
 pub struct MotionController {
     command_sender: last_message_channel::Sender<MotionControllerCommand>,
     blocking_command_sender: mpsc::Sender<BlockingCommand>,
+    status_receiver: watch::Receiver<MotionControllerStatus>,
+    emergency_stop: EmergencyStop,
     command: MotionControllerCommand,
     _handle: JoinHandle<anyhow::Result<()>>,
 }
@@ -56,18 +104,39 @@ impl MotionController {
         ik_controller: Box<dyn IkControllable>,
         control_loop_rate_tracker: RateTracker,
         high_five_receiver: Receiver<HighFiveCommand>,
+        battery_config: BatteryConfig,
+        servo_telemetry_config: ServoTelemetryConfig,
+        terrain_config: TerrainConfig,
+        estop_config: EstopConfig,
     ) -> HopperResult<Self> {
         let (command_sender, receiver) = last_message_channel::latest_message_channel();
         let command = MotionControllerCommand::default();
 
         let (blocking_command_sender, blocking_command_receiver) = mpsc::channel();
+        let emergency_stop = EmergencyStop::new();
+        let (status_sender, status_receiver) = watch::channel(MotionControllerStatus {
+            body_state: BodyState::Grounded,
+            body_phase: BodyPhase::Grounded,
+            move_command: MoveCommand::default(),
+            voltage: None,
+            battery_level: None,
+            battery_state: BatteryState::Normal,
+            servo_telemetry: None,
+            servo_health: ServoHealth::Normal,
+        });
 
         let motion_controller_loop = MotionControllerLoop::new(
             ik_controller,
             receiver,
             blocking_command_receiver,
+            status_sender,
             control_loop_rate_tracker,
             high_five_receiver,
+            BatteryMonitor::new(battery_config),
+            servo_telemetry_config,
+            TerrainAdapter::new(terrain_config),
+            emergency_stop.clone(),
+            estop_config,
         )
         .await?;
 
@@ -75,6 +144,8 @@ impl MotionController {
         Ok(MotionController {
             command_sender,
             blocking_command_sender,
+            status_receiver,
+            emergency_stop,
             command,
             _handle: handle,
         })
@@ -104,7 +175,7 @@ impl MotionController {
 
     pub fn set_body_state(&mut self, state: BodyState) {
         self.blocking_command_sender
-            .send(BlockingCommand::SetBodyState(state))
+            .send(BlockingCommand::SetBodyState(state, None))
             .unwrap();
     }
 
@@ -123,7 +194,16 @@ impl MotionController {
     pub fn start_sequence(&mut self, dance_move: DanceMove) {
         info!("Starting dance sequence");
         self.blocking_command_sender
-            .send(BlockingCommand::Choreography(dance_move))
+            .send(BlockingCommand::Choreography(Dance::Move(dance_move)))
+            .unwrap();
+    }
+
+    pub fn start_choreography(&mut self, choreography: Arc<Choreography>) {
+        info!("Starting choreography");
+        self.blocking_command_sender
+            .send(BlockingCommand::Choreography(Dance::Keyframes(
+                choreography,
+            )))
             .unwrap();
     }
 
@@ -143,9 +223,19 @@ impl MotionController {
         self.command_sender.send(self.command.clone()).unwrap();
     }
 
+    pub fn subscribe_status(&self) -> watch::Receiver<MotionControllerStatus> {
+        self.status_receiver.clone()
+    }
+
+    pub fn emergency_stop(&self) -> EmergencyStop {
+        self.emergency_stop.clone()
+    }
+
     pub fn create_dance_service(&self) -> MotionControllerService {
         MotionControllerService {
             blocking_command_sender: self.blocking_command_sender.clone(),
+            status_receiver: self.status_receiver.clone(),
+            emergency_stop: self.emergency_stop.clone(),
         }
     }
 }
@@ -160,18 +250,53 @@ impl Drop for MotionController {
 
 pub struct MotionControllerService {
     blocking_command_sender: mpsc::Sender<BlockingCommand>,
+    status_receiver: watch::Receiver<MotionControllerStatus>,
+    emergency_stop: EmergencyStop,
 }
 
 impl MotionControllerService {
+    pub fn status(&self) -> MotionControllerStatus {
+        *self.status_receiver.borrow()
+    }
+
+    pub fn emergency_stop(&self) -> EmergencyStop {
+        self.emergency_stop.clone()
+    }
+
     pub fn start_dance_sequence(&self, dance_move: DanceMove) {
         self.blocking_command_sender
-            .send(BlockingCommand::Choreography(dance_move))
+            .send(BlockingCommand::Choreography(Dance::Move(dance_move)))
+            .unwrap();
+    }
+
+    pub fn start_choreography(&self, choreography: Arc<Choreography>) {
+        self.blocking_command_sender
+            .send(BlockingCommand::Choreography(Dance::Keyframes(
+                choreography,
+            )))
             .unwrap();
     }
 
-    pub fn set_body_state(&self, state: BodyState) {
+    /// Request is sent immediately. Await the result to learn the outcome
+    pub fn set_body_state(
+        &self,
+        state: BodyState,
+    ) -> impl std::future::Future<Output = BodyStateOutcome> {
+        let (sender, receiver) = oneshot::channel();
         self.blocking_command_sender
-            .send(BlockingCommand::SetBodyState(state))
+            .send(BlockingCommand::SetBodyState(state, Some(sender)))
+            .unwrap();
+        async move {
+            receiver.await.unwrap_or_else(|_| BodyStateOutcome::Failed {
+                error: String::from("motion controller stopped"),
+            })
+        }
+    }
+
+    /// Queued until body is grounded or standing still
+    pub fn update_body(&self, update: BodyUpdate) {
+        self.blocking_command_sender
+            .send(BlockingCommand::UpdateBody(update))
             .unwrap();
     }
 }
@@ -196,14 +321,22 @@ impl SingleLegCommand {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
+enum Dance {
+    Move(DanceMove),
+    /// Loaded from choreography file
+    Keyframes(Arc<Choreography>),
+}
+
+#[derive(Debug)]
 enum BlockingCommand {
     Terminate,
     DisableMotors,
-    Choreography(DanceMove),
+    Choreography(Dance),
     SetCompliance(HexapodCompliance),
     SetMotorSpeed(HexapodMotorSpeed),
-    SetBodyState(BodyState),
+    SetBodyState(BodyState, Option<oneshot::Sender<BodyStateOutcome>>),
+    UpdateBody(BodyUpdate),
 }
 
 const TICK_DURATION: Duration = Duration::from_millis(1000 / 50);
@@ -213,7 +346,6 @@ const MAX_TRANSLATION_STEP: f32 = 0.004;
 // const MAX_TRANSLATION_STEP: f32 = 0.005;
 const MAX_ROTATION_STEP: f32 = std::f32::consts::PI / 180.0;
 const NON_WALK_STEP_HEIGHT: f32 = 0.03;
-const GROUNDED_STEP_HEIGHT: f32 = -0.0;
 const VOLTAGE_READ_PERIOD: Duration = Duration::from_millis(1000);
 const HARDWARE_ERROR_SOUND_TIMEOUT: Duration = Duration::from_secs(30);
 // const MOVE_DURATION: Duration = Duration::from_millis(400);
@@ -225,21 +357,32 @@ struct MotionControllerLoop {
     ik_controller: Box<dyn IkControllable>,
     command_receiver: last_message_channel::Receiver<MotionControllerCommand>,
     blocking_command_receiver: mpsc::Receiver<BlockingCommand>,
+    status_sender: watch::Sender<MotionControllerStatus>,
     command: MotionControllerCommand,
     current_body_state: BodyState,
     last_tripod: Tripod,
+    gait_oscillator: GaitOscillator,
     last_written_pose: LegPositions,
     current_rotation: UnitQuaternion<f32>,
     current_translation: Vector3<f32>,
     base_relaxed: LegPositions,
-    lrl_reset: bool,
-    rlr_reset: bool,
+    stances: Stances,
+    pending_body_updates: VecDeque<BodyUpdate>,
     last_voltage_read: Instant,
-    dance_moves: VecDeque<DanceMove>,
+    dance_moves: VecDeque<Dance>,
     control_loop_rate_tracker: RateTracker,
     was_single_leg_mode: bool,
     high_five_receiver: Receiver<HighFiveCommand>,
     last_hardware_error_sound_player: Instant,
+    battery_monitor: BatteryMonitor,
+    servo_health_monitor: ServoHealthMonitor,
+    servo_telemetry_config: ServoTelemetryConfig,
+    last_servo_telemetry_read: Instant,
+    /// Compliance last requested by a user. Restored after servo relief
+    requested_compliance: Option<HexapodCompliance>,
+    terrain_adapter: TerrainAdapter,
+    emergency_stop: EmergencyStop,
+    estop_config: EstopConfig,
 }
 
 impl MotionControllerLoop {
@@ -247,35 +390,52 @@ impl MotionControllerLoop {
         mut ik_controller: Box<dyn IkControllable>,
         command_receiver: last_message_channel::Receiver<MotionControllerCommand>,
         blocking_command_receiver: mpsc::Receiver<BlockingCommand>,
+        status_sender: watch::Sender<MotionControllerStatus>,
         control_loop_rate_tracker: RateTracker,
         high_five_receiver: Receiver<HighFiveCommand>,
+        battery_monitor: BatteryMonitor,
+        servo_telemetry_config: ServoTelemetryConfig,
+        terrain_adapter: TerrainAdapter,
+        emergency_stop: EmergencyStop,
+        estop_config: EstopConfig,
     ) -> HopperResult<Self> {
         let last_written_pose = ik_controller.read_leg_positions().await?;
+        ik_controller.restart_odometry();
         Ok(Self {
             ik_controller,
             command_receiver,
             blocking_command_receiver,
+            status_sender,
             command: MotionControllerCommand::default(),
             current_body_state: BodyState::Grounded,
             last_tripod: Tripod::LRL,
+            gait_oscillator: GaitOscillator::new(last_written_pose),
             last_written_pose,
             current_rotation: UnitQuaternion::identity(),
             current_translation: Vector3::zeros(),
             base_relaxed: *stance::relaxed_stance(),
-            lrl_reset: false,
-            rlr_reset: false,
+            stances: Stances::default(),
+            pending_body_updates: VecDeque::new(),
             last_voltage_read: Instant::now(),
             dance_moves: VecDeque::new(),
             control_loop_rate_tracker,
             was_single_leg_mode: false,
             high_five_receiver,
             last_hardware_error_sound_player: Instant::now(),
+            battery_monitor,
+            servo_health_monitor: ServoHealthMonitor::new(servo_telemetry_config.clone()),
+            servo_telemetry_config,
+            last_servo_telemetry_read: Instant::now(),
+            requested_compliance: None,
+            terrain_adapter,
+            emergency_stop,
+            estop_config,
         })
     }
 
     /// Attempt to estimate current body state
     async fn estimate_current_body_state(&mut self) -> HopperResult<BodyState> {
-        self.last_written_pose = self.ik_controller.read_leg_positions().await?;
+        self.read_current_pose().await?;
 
         let z_heights: Vec<_> = self
             .last_written_pose
@@ -311,24 +471,57 @@ impl MotionControllerLoop {
     async fn initialize_body_state(&mut self) -> HopperResult<()> {
         let estimate = self.estimate_current_body_state().await?;
         info!("Estimated body state to be {:?}", estimate);
-        self.current_body_state = estimate;
+        self.set_current_body_state(estimate);
         Ok(())
     }
 
+    fn set_current_body_state(&mut self, body_state: BodyState) {
+        self.current_body_state = body_state;
+        self.status_sender.send_modify(|status| {
+            status.body_state = body_state;
+            status.body_phase = body_state.into();
+        });
+    }
+
+    fn set_body_phase(&mut self, body_phase: BodyPhase) {
+        self.status_sender.send_if_modified(|status| {
+            let modified = status.body_phase != body_phase;
+            status.body_phase = body_phase;
+            modified
+        });
+    }
+
+    /// Resynchronize with motors after moves were interrupted or geometry changed
     async fn read_current_pose(&mut self) -> HopperResult<()> {
         self.last_written_pose = self.ik_controller.read_leg_positions().await?;
+        self.ik_controller.restart_odometry();
         Ok(())
     }
 
     async fn run(mut self) -> anyhow::Result<()> {
         tracing::info!("Running motion controller");
+        let mut estop_receiver = self.emergency_stop.subscribe();
         loop {
-            match self.control_loop().await {
+            // dropping the control loop interrupts any running move at its next await
+            let result = tokio::select! {
+                result = self.control_loop() => result,
+                _ = estop::wait_until_latched(&mut estop_receiver) => {
+                    Err(HopperError::EmergencyStop)
+                }
+            };
+            match result {
                 Err(error) => {
                     error!("Control loop error {}", error);
                     match error {
-                        HopperError::GenericIkError => {
-                            warn!("Error is generic IK error. Restarting");
+                        HopperError::EmergencyStop => {
+                            if self.handle_emergency_stop().await? {
+                                info!("Terminate command received while stopped");
+                                return Ok(());
+                            }
+                        }
+                        HopperError::GenericIkError | HopperError::IkError(_) => {
+                            warn!("Error is IK error. Restarting");
+                            self.set_body_phase(BodyPhase::Recovering);
 
                             IocContainer::global_instance()
                                 .service::<SpeechService>()?
@@ -337,16 +530,18 @@ impl MotionControllerLoop {
 
                             // Attempt recovery
                             self.command = MotionControllerCommand::default();
-                            self.current_body_state = BodyState::Grounded;
                             self.ik_controller.disable_motors().await?;
                             tokio::time::sleep(Duration::from_millis(500)).await;
                             self.dance_moves.clear();
                             self.read_current_pose().await?;
+                            self.set_current_body_state(BodyState::Grounded);
                         }
                         error if error.is_recoverable_driver_error() => {
                             info!("Error is recoverable. Restarting controller");
+                            self.set_body_phase(BodyPhase::Recovering);
                             self.play_hardware_error_sound().await?;
                             self.attempt_motor_recovery().await?;
+                            self.set_body_phase(self.current_body_state.into());
                         }
                         error => {
                             error!("Error is not recoverable. Stopping controller");
@@ -390,30 +585,25 @@ impl MotionControllerLoop {
     }
 
     async fn stand_up(&mut self) -> HopperResult<()> {
+        let stances = self.stances;
+        self.terrain_adapter.reset();
         self.read_current_pose().await?;
-        self.transition_direct(
-            &[&self.last_written_pose.clone(), stance::grounded_stance()],
-            0.005,
-        )
-        .await?;
-        self.transition_direct(
-            &[stance::grounded_stance(), stance::relaxed_wide_stance()],
-            0.003,
-        )
-        .await?;
-        self.transition_step(&[stance::relaxed_wide_stance(), stance::relaxed_stance()])
+        self.transition_direct(&[&self.last_written_pose.clone(), &stances.grounded], 0.005)
+            .await?;
+        self.transition_direct(&[&stances.grounded, &stances.relaxed_wide], 0.003)
+            .await?;
+        self.transition_step(&[&stances.relaxed_wide, &stances.relaxed])
             .await?;
         Ok(())
     }
 
     async fn sit_down(&mut self) -> HopperResult<()> {
-        self.transition_step(&[stance::relaxed_stance(), stance::relaxed_wide_stance()])
+        let stances = self.stances;
+        self.transition_step(&[&stances.relaxed, &stances.relaxed_wide])
             .await?;
-        self.transition_direct(
-            &[stance::relaxed_wide_stance(), stance::grounded_stance()],
-            MAX_MOVE,
-        )
-        .await?;
+        self.transition_direct(&[&stances.relaxed_wide, &stances.grounded], MAX_MOVE)
+            .await?;
+        self.terrain_adapter.reset();
         self.ik_controller.disable_motors().await?;
         Ok(())
     }
@@ -497,10 +687,6 @@ impl MotionControllerLoop {
             .transform(self.current_translation, self.current_rotation)
     }
 
-    fn should_reset_legs(&self) -> bool {
-        !(self.lrl_reset && self.rlr_reset)
-    }
-
     async fn scan_motors(&mut self) -> HopperResult<()> {
         self.ik_controller.scan_motors().await?;
         Ok(())
@@ -521,62 +707,33 @@ impl MotionControllerLoop {
                 self.current_body_state, desired_body_state
             );
         }
-        match (self.current_body_state, desired_body_state) {
-            (BodyState::Folded, BodyState::Grounded) => {
-                IocContainer::global_instance()
-                    .service::<FaceController>()?
-                    .larson_scanner(crate::face::driver::PURPLE)?;
-                FoldingManager::new(&mut self.ik_controller)
-                    .await?
-                    .unfold_on_ground()
-                    .await?;
-            }
-            (BodyState::Folded, BodyState::Standing) => {
-                IocContainer::global_instance()
-                    .service::<FaceController>()?
-                    .larson_scanner(crate::face::driver::PURPLE)?;
-                FoldingManager::new(&mut self.ik_controller)
-                    .await?
-                    .unfold_on_ground()
-                    .await?;
-                self.stand_up().await?;
-            }
-            (BodyState::Grounded, BodyState::Standing) => {
-                self.stand_up().await?;
-            }
-            (BodyState::Standing, BodyState::Grounded) => {
-                self.sit_down().await?;
-            }
-            (BodyState::Standing, BodyState::Folded) => {
-                self.sit_down().await?;
-                FoldingManager::new(&mut self.ik_controller)
-                    .await?
-                    .fold_on_ground()
-                    .await?;
-                IocContainer::global_instance()
-                    .service::<FaceController>()?
-                    .off()?;
-            }
-            (BodyState::Grounded, BodyState::Folded) => {
-                FoldingManager::new(&mut self.ik_controller)
-                    .await?
-                    .fold_on_ground()
-                    .await?;
-                IocContainer::global_instance()
-                    .service::<FaceController>()?
-                    .off()?;
-            }
-            (BodyState::Grounded, BodyState::Grounded) => {
-                // do nothing
-            }
-            (BodyState::Standing, BodyState::Standing) => {
-                // do nothing
-            }
-            (BodyState::Folded, BodyState::Folded) => {
-                // do nothing
+        for phase in body_state::transition_path(self.current_body_state, desired_body_state) {
+            self.set_body_phase(*phase);
+            match phase {
+                BodyPhase::Unfolding => {
+                    IocContainer::global_instance()
+                        .service::<FaceController>()?
+                        .larson_scanner(crate::face::driver::PURPLE)?;
+                    FoldingManager::new(&mut self.ik_controller)
+                        .await?
+                        .unfold_on_ground()
+                        .await?;
+                }
+                BodyPhase::StandingUp => self.stand_up().await?,
+                BodyPhase::SittingDown => self.sit_down().await?,
+                BodyPhase::Folding => {
+                    FoldingManager::new(&mut self.ik_controller)
+                        .await?
+                        .fold_on_ground()
+                        .await?;
+                    IocContainer::global_instance()
+                        .service::<FaceController>()?
+                        .off()?;
+                }
+                phase => unreachable!("{:?} is not part of a transition", phase),
             }
         }
-        self.current_body_state = desired_body_state;
+        self.set_current_body_state(desired_body_state);
 
         Ok(())
     }
@@ -590,7 +747,14 @@ impl MotionControllerLoop {
         interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
         loop {
             match self.command_receiver.try_recv() {
-                Ok(Some(command)) => self.command = command,
+                Ok(Some(command)) => {
+                    self.status_sender.send_if_modified(|status| {
+                        let modified = status.move_command != command.move_command;
+                        status.move_command = command.move_command;
+                        modified
+                    });
+                    self.command = command;
+                }
                 Err(_) => {
                     warn!("Motion command sender gone. Exiting control loop");
                     break;
@@ -605,39 +769,67 @@ impl MotionControllerLoop {
                         info!("Terminate command received. Exiting control loop");
                         break;
                     }
-                    BlockingCommand::Choreography(dance_move) => {
-                        self.dance_moves.push_back(dance_move);
+                    BlockingCommand::Choreography(dance) => {
+                        self.dance_moves.push_back(dance);
                     }
                     BlockingCommand::DisableMotors => {
                         self.ik_controller.disable_motors().await?;
                         continue;
                     }
                     BlockingCommand::SetCompliance(compliance) => {
-                        self.ik_controller
-                            .set_body_compliance_slope(compliance)
-                            .await?;
+                        self.requested_compliance = Some(compliance.clone());
+                        if self.servo_health_monitor.health() == ServoHealth::Normal {
+                            self.ik_controller
+                                .set_body_compliance_slope(compliance)
+                                .await?;
+                        } else {
+                            info!("Servos need relief. Compliance will be set once they recover");
+                        }
                         continue;
                     }
                     BlockingCommand::SetMotorSpeed(speed) => {
                         self.ik_controller.set_body_motor_speed(speed).await?;
                         continue;
                     }
-                    BlockingCommand::SetBodyState(new_body_state) => {
-                        self.handle_body_state_transition(new_body_state).await?;
+                    BlockingCommand::SetBodyState(new_body_state, reply) => {
+                        let result = self.request_body_state(new_body_state).await;
+                        if let Some(reply) = reply {
+                            let outcome = match &result {
+                                Ok(outcome) => outcome.clone(),
+                                Err(error) => BodyStateOutcome::Failed {
+                                    error: error.to_string(),
+                                },
+                            };
+                            // requester may have stopped waiting
+                            _ = reply.send(outcome);
+                        }
+                        result?;
                         // no need to continue here
                     }
+                    BlockingCommand::UpdateBody(update) => {
+                        self.pending_body_updates.push_back(update);
+                    }
                 }
             }
 
-            // measure voltage only if not walking
-            if !self.command.move_command.should_move()
-                && self.last_voltage_read.elapsed() > VOLTAGE_READ_PERIOD
-            {
+            // voltage is read from one servo at a time so it's cheap enough while walking
+            // sag under load is filtered by consecutive readings in battery monitor
+            if self.last_voltage_read.elapsed() > VOLTAGE_READ_PERIOD {
                 self.last_voltage_read = Instant::now();
                 match self.ik_controller.read_mean_voltage().await {
                     Ok(voltage) => {
-                        // TODO(David): Figure out a better way to propagate this
-                        tracing::debug!("Voltage is {}", voltage)
+                        tracing::debug!("Voltage is {}", voltage);
+                        let battery_level = self.battery_monitor.level(voltage);
+                        let battery_change = self.battery_monitor.update(voltage);
+                        self.status_sender.send_modify(|status| {
+                            status.voltage = Some(voltage);
+                            status.battery_level = Some(battery_level);
+                            status.battery_state = self.battery_monitor.state();
+                        });
+                        if let Some(battery_state) = battery_change {
+                            self.handle_battery_state_change(battery_state, voltage)
+                                .await?;
+                        }
                     }
                     Err(error) => {
                         error!(?error, "Failed to read voltage: {:?}", error);
@@ -648,6 +840,34 @@ impl MotionControllerLoop {
                 }
             }
 
+            // servo telemetry is read only if not walking
+            if self.servo_telemetry_config.enabled
+                && !self.command.move_command.should_move()
+                && self.last_servo_telemetry_read.elapsed()
+                    > Duration::from_millis(self.servo_telemetry_config.read_period_ms)
+            {
+                self.last_servo_telemetry_read = Instant::now();
+                match self.ik_controller.read_motor_telemetry().await {
+                    Ok(telemetry) => {
+                        let health_change = self.servo_health_monitor.update(&telemetry);
+                        self.status_sender.send_modify(|status| {
+                            status.servo_telemetry = Some(telemetry);
+                            status.servo_health = self.servo_health_monitor.health();
+                        });
+                        if let Some(servo_health) = health_change {
+                            self.handle_servo_health_change(servo_health, &telemetry)
+                                .await?;
+                        }
+                    }
+                    Err(error) => {
+                        error!(?error, "Failed to read servo telemetry: {:?}", error);
+                        self.play_hardware_error_sound().await?;
+                        warn!("Clearing driver io buffers");
+                        self.ik_controller.clear_serial_io_buffers().await?;
+                    }
+                }
+            }
+
             // only walk if standing
             if self.current_body_state == BodyState::Standing {
                 // only do high fives if standing
@@ -682,60 +902,52 @@ impl MotionControllerLoop {
                     continue;
                 }
 
-                if self.command.move_command.should_move() || self.should_reset_legs() {
-                    self.last_tripod.invert();
-                    let target = step_with_relaxed_transformation(
-                        &self.last_written_pose,
-                        &self.transformed_relaxed(),
-                        &self.last_tripod,
-                        self.command.move_command,
-                    );
-                    for new_pose in TimedStepIterator::step(
-                        self.last_written_pose,
-                        target,
-                        self.command.move_command.step_time(),
-                        self.command.move_command.step_height(),
-                        GROUNDED_STEP_HEIGHT,
-                        self.last_tripod,
-                        self.command.move_command.aggressive_leg_lift(),
-                    ) {
-                        self.shift_transformation();
-                        self.ik_controller.move_to_positions(&new_pose).await?;
-                        self.last_written_pose = new_pose;
-                        self.control_loop_rate_tracker.tick();
-                        interval.tick().await;
-                    }
-                    match self.last_tripod {
-                        Tripod::LRL => self.lrl_reset = true,
-                        Tripod::RLR => self.rlr_reset = true,
-                    }
-                    if self.command.move_command.should_move() {
-                        // invalidate legs if moving
-                        self.lrl_reset = false;
-                        self.rlr_reset = false;
-                    }
+                if self.command.move_command.should_move() || !self.gait_oscillator.is_settled() {
+                    self.walking_step().await?;
+                    self.control_loop_rate_tracker.tick();
+                    interval.tick().await;
                 } else {
                     // shift transformation
                     self.shift_transformation();
                     // we can do transformations here
-                    let transformed_pose = self.transformed_relaxed();
+                    let transformed_pose = self.terrain_adapter.adapt(
+                        &self.transformed_relaxed(),
+                        LegFlags::empty(),
+                        0.0,
+                        TICK_DURATION.as_secs_f32(),
+                    );
                     if self.last_written_pose != transformed_pose {
                         self.ik_controller
                             .move_to_positions(&transformed_pose)
                             .await?;
                         self.last_written_pose = transformed_pose;
                     }
-                    if let Some(dance_move) = self.dance_moves.pop_front() {
+                    if self.dance_moves.is_empty() {
+                        self.apply_pending_body_updates().await?;
+                    }
+                    if let Some(dance) = self.dance_moves.pop_front() {
                         let transformed_relaxed = self.transformed_relaxed();
-                        Choreographer::new(&mut self.ik_controller, transformed_relaxed)?
-                            .execute_move(dance_move)
-                            .await?;
+                        self.set_body_phase(BodyPhase::Dancing);
+                        let mut choreographer =
+                            Choreographer::new(&mut self.ik_controller, transformed_relaxed)?;
+                        match dance {
+                            Dance::Move(dance_move) => {
+                                choreographer.execute_move(dance_move).await?
+                            }
+                            Dance::Keyframes(choreography) => {
+                                choreographer.execute_choreography(&choreography).await?
+                            }
+                        }
+                        self.set_body_phase(BodyPhase::Standing);
                     }
                     // sleep if not walking
                     self.control_loop_rate_tracker.tick();
                     interval.tick().await;
                 }
             } else {
+                if self.current_body_state == BodyState::Grounded {
+                    self.apply_pending_body_updates().await?;
+                }
                 // sleep if not standing
                 self.control_loop_rate_tracker.tick();
                 interval.tick().await;
@@ -747,6 +959,305 @@ impl MotionControllerLoop {
         Ok(())
     }
 
+    async fn handle_battery_state_change(
+        &mut self,
+        battery_state: BatteryState,
+        voltage: f32,
+    ) -> HopperResult<()> {
+        let face_controller = IocContainer::global_instance().service::<FaceController>()?;
+        match battery_state {
+            BatteryState::Normal => {
+                info!("Battery recovered at {:.2}V", voltage);
+                face_controller.set_animation(Animation::Off)?;
+            }
+            BatteryState::Low => {
+                warn!("Battery low at {:.2}V", voltage);
+                face_controller.set_animation(Animation::Breathing(crate::face::driver::YELLOW))?;
+                announce("My battery is getting low. Please charge me soon.");
+            }
+            BatteryState::Critical => {
+                error!("Battery critical at {:.2}V. Sitting down", voltage);
+                face_controller.set_animation(Animation::Breathing(crate::face::driver::RED))?;
+                announce("My battery is critically low. I am sitting down now.");
+                self.command = MotionControllerCommand::default();
+                self.dance_moves.clear();
+                if self.current_body_state == BodyState::Standing {
+                    self.handle_body_state_transition(BodyState::Grounded)
+                        .await?;
+                }
+                self.ik_controller.disable_motors().await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_servo_health_change(
+        &mut self,
+        servo_health: ServoHealth,
+        telemetry: &BodyMotorTelemetry,
+    ) -> HopperResult<()> {
+        let hottest = named_motors(telemetry)
+            .into_iter()
+            .max_by(|(_, a), (_, b)| a.temperature_c.total_cmp(&b.temperature_c));
+        match servo_health {
+            ServoHealth::Normal => {
+                info!("Servos recovered. Hottest {:?}", hottest);
+                self.restore_compliance().await?;
+            }
+            ServoHealth::Strained => {
+                warn!(
+                    "Servos strained. Relaxing compliance. Hottest {:?}",
+                    hottest
+                );
+                self.ik_controller
+                    .set_compliance_slope(self.servo_telemetry_config.relief_compliance_slope)
+                    .await?;
+            }
+            ServoHealth::Critical => {
+                error!("Servos overheated. Sitting down. Hottest {:?}", hottest);
+                announce("My servos are overheating. I need to rest.");
+                self.command = MotionControllerCommand::default();
+                self.dance_moves.clear();
+                if self.current_body_state == BodyState::Standing {
+                    self.handle_body_state_transition(BodyState::Grounded)
+                        .await?;
+                }
+                self.ik_controller.disable_motors().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Compliance from before servo relief
+    async fn restore_compliance(&mut self) -> HopperResult<()> {
+        match self.requested_compliance.clone() {
+            Some(compliance) => {
+                self.ik_controller
+                    .set_body_compliance_slope(compliance)
+                    .await
+            }
+            None => {
+                self.ik_controller
+                    .set_compliance_slope(self.servo_telemetry_config.default_compliance_slope)
+                    .await
+            }
+        }
+    }
+
+    /// Advance gait by one tick
+    async fn walking_step(&mut self) -> HopperResult<()> {
+        self.shift_transformation();
+        let planned_pose = self.gait_oscillator.tick(
+            &self.terrain_adapter.to_planned(&self.last_written_pose),
+            &self.transformed_relaxed(),
+            &self.command.move_command,
+            TICK_DURATION,
+        );
+        let new_pose = self.adapt_to_terrain(&planned_pose).await?;
+        self.ik_controller.move_to_positions(&new_pose).await?;
+        self.last_written_pose = new_pose;
+        Ok(())
+    }
+
+    /// Place planned feet on measured ground
+    async fn adapt_to_terrain(&mut self, planned: &LegPositions) -> HopperResult<LegPositions> {
+        if !self.terrain_adapter.is_enabled() {
+            return Ok(*planned);
+        }
+        // reading the whole body every tick doesn't fit into the tick on the servo bus
+        let legs_to_measure = self.terrain_adapter.legs_to_measure();
+        if !legs_to_measure.is_empty() {
+            let measured = self
+                .ik_controller
+                .read_selected_leg_positions(legs_to_measure, &self.last_written_pose)
+                .await?;
+            self.terrain_adapter
+                .detect_contacts(&self.last_written_pose, &measured);
+        }
+        Ok(self.terrain_adapter.adapt(
+            planned,
+            self.gait_oscillator.swinging_legs(),
+            self.gait_oscillator.swing_progress(),
+            TICK_DURATION.as_secs_f32(),
+        ))
+    }
+
+    /// Apply queued geometry and stance updates
+    ///
+    /// Only called while grounded or standing still. Invalid updates are dropped.
+    async fn apply_pending_body_updates(&mut self) -> HopperResult<()> {
+        while let Some(update) = self.pending_body_updates.pop_front() {
+            match update {
+                BodyUpdate::BodyConfig(body_config) => {
+                    if let Err(error) = check_stances_reachable(&self.stances, &body_config) {
+                        warn!("Rejecting body config update: {}", error);
+                        continue;
+                    }
+                    if let Err(error) = self.ik_controller.set_body_configuration(*body_config) {
+                        warn!("Rejecting body config update: {}", error);
+                        continue;
+                    }
+                    info!("Body config updated");
+                    // same motor positions map to new leg positions
+                    self.read_current_pose().await?;
+                    if self.current_body_state == BodyState::Standing {
+                        let relaxed = self.transformed_relaxed();
+                        self.transition_step(&[&relaxed]).await?;
+                    }
+                }
+                BodyUpdate::Stance(kind, positions) => {
+                    let transformed =
+                        positions.transform(self.current_translation, self.current_rotation);
+                    if let Err(error) = self
+                        .ik_controller
+                        .check_reachable(&positions)
+                        .and_then(|_| self.ik_controller.check_reachable(&transformed))
+                    {
+                        warn!("Rejecting {:?} stance update: {}", kind, error);
+                        continue;
+                    }
+                    info!("{:?} stance updated", kind);
+                    self.stances.set(kind, positions);
+                    if kind == StanceKind::Relaxed {
+                        self.base_relaxed = positions;
+                        if self.current_body_state == BodyState::Standing {
+                            self.transition_step(&[&transformed]).await?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop, wait for the stop to be cleared and resume from the measured pose
+    ///
+    /// Returns true if the loop was asked to terminate while stopped
+    async fn handle_emergency_stop(&mut self) -> HopperResult<bool> {
+        self.stop_for_emergency().await?;
+        if self.wait_for_estop_clear().await? {
+            return Ok(true);
+        }
+        // legs could have been moved by hand while they were limp
+        self.initialize_body_state().await?;
+        Ok(false)
+    }
+
+    async fn stop_for_emergency(&mut self) -> HopperResult<()> {
+        self.set_body_phase(BodyPhase::EmergencyStopped);
+        self.command = MotionControllerCommand::default();
+        self.dance_moves.clear();
+        // interrupted reads can leave replies on the bus
+        self.ik_controller.clear_serial_io_buffers().await?;
+        if self.estop_config.action == EstopAction::SitDown
+            && self.current_body_state == BodyState::Standing
+        {
+            if let Err(error) = self.lower_to_ground().await {
+                error!("Failed to sit down on emergency stop: {}", error);
+            }
+        }
+        self.ik_controller.disable_motors().await?;
+        self.terrain_adapter.reset();
+        // the body collapsed or sat down so the state and pose from before the stop are stale
+        let estimate = self.estimate_current_body_state().await?;
+        info!(
+            "Estimated body state after emergency stop to be {:?}",
+            estimate
+        );
+        self.current_body_state = estimate;
+        self.status_sender
+            .send_modify(|status| status.body_state = estimate);
+        Ok(())
+    }
+
+    /// Lower body straight down from wherever the interrupted move left it
+    async fn lower_to_ground(&mut self) -> HopperResult<()> {
+        let grounded = self.stances.grounded;
+        self.read_current_pose().await?;
+        self.transition_direct(&[&grounded], MAX_MOVE).await
+    }
+
+    /// Refuse body state requests until the stop is cleared
+    ///
+    /// Returns true if the loop was asked to terminate
+    async fn wait_for_estop_clear(&mut self) -> HopperResult<bool> {
+        let mut interval = time::interval(TICK_DURATION);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+        while self.emergency_stop.is_latched() {
+            // move commands sent while stopped are dropped
+            _ = self.command_receiver.try_recv();
+            match self.blocking_command_receiver.try_recv_optional().unwrap() {
+                Some(BlockingCommand::Terminate) => return Ok(true),
+                Some(BlockingCommand::SetBodyState(body_state, reply)) => {
+                    warn!(
+                        "Refusing transition to {:?} during emergency stop",
+                        body_state
+                    );
+                    if let Some(reply) = reply {
+                        _ = reply.send(BodyStateOutcome::Refused {
+                            reason: body_state::transition_blocker(BodyPhase::EmergencyStopped)
+                                .unwrap_or_default()
+                                .to_owned(),
+                        });
+                    }
+                }
+                Some(BlockingCommand::UpdateBody(update)) => {
+                    self.pending_body_updates.push_back(update);
+                }
+                Some(command) => warn!("Dropping {:?} during emergency stop", command),
+                None => (),
+            }
+            interval.tick().await;
+        }
+        Ok(false)
+    }
+
+    /// Transition to desired body state unless it's refused
+    async fn request_body_state(
+        &mut self,
+        desired_body_state: BodyState,
+    ) -> HopperResult<BodyStateOutcome> {
+        if let Some(reason) = self.body_state_blocker(desired_body_state) {
+            warn!(
+                "Refusing transition to {:?} because {}",
+                desired_body_state, reason
+            );
+            return Ok(BodyStateOutcome::Refused {
+                reason: reason.to_owned(),
+            });
+        }
+        self.handle_body_state_transition(desired_body_state)
+            .await?;
+        Ok(BodyStateOutcome::Completed {
+            body_state: desired_body_state,
+        })
+    }
+
+    fn body_state_blocker(&self, desired_body_state: BodyState) -> Option<&'static str> {
+        if desired_body_state == self.current_body_state {
+            return None;
+        }
+        // dances queued while standing are part of the running choreography
+        if self.current_body_state == BodyState::Standing && !self.dance_moves.is_empty() {
+            return body_state::transition_blocker(BodyPhase::Dancing);
+        }
+        if desired_body_state == BodyState::Standing {
+            return self.stand_up_blocker();
+        }
+        None
+    }
+
+    /// Reason why standing up is not safe
+    fn stand_up_blocker(&self) -> Option<&'static str> {
+        if self.battery_monitor.state() == BatteryState::Critical {
+            Some("battery is critical")
+        } else if self.servo_health_monitor.health() == ServoHealth::Critical {
+            Some("servos are overheated")
+        } else {
+            None
+        }
+    }
+
     async fn play_hardware_error_sound(&mut self) -> HopperResult<()> {
         if self.last_hardware_error_sound_player.elapsed() > HARDWARE_ERROR_SOUND_TIMEOUT {
             self.last_hardware_error_sound_player = Instant::now();
@@ -761,6 +1272,27 @@ impl MotionControllerLoop {
     }
 }
 
+/// All stances have to be reachable before new leg geometry is accepted
+fn check_stances_reachable(stances: &Stances, body_config: &HopperConfig) -> HopperResult<()> {
+    for kind in StanceKind::ALL {
+        calculate_validated_ik(stances.get(kind), body_config)?;
+    }
+    Ok(())
+}
+
+/// Speak without blocking the control loop
+fn announce(text: &'static str) {
+    spawn(async move {
+        let result = match IocContainer::global_instance().service::<SpeechService>() {
+            Ok(speech_service) => speech_service.say(text, AzureVoiceStyle::Sad).await,
+            Err(error) => Err(error.into()),
+        };
+        if let Err(error) = result {
+            error!("Failed to announce {:?}: {}", text, error);
+        }
+    });
+}
+
 pub trait RotateTowards {
     type Item;
 
@@ -883,4 +1415,161 @@ mod tests {
         assert!(rotated);
         assert_relative_eq!(expected, res);
     }
+
+    #[test]
+    fn default_stances_are_reachable() {
+        assert!(check_stances_reachable(&Stances::default(), &HopperConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn short_legs_are_rejected() {
+        let body_config = HopperConfig {
+            femur_length: 0.01,
+            tibia_length: 0.01,
+            ..HopperConfig::default()
+        };
+        assert!(check_stances_reachable(&Stances::default(), &body_config).is_err());
+    }
+
+    /// Motion controller loop driving a simulated body
+    struct SimulatedLoop {
+        motion_controller_loop: MotionControllerLoop,
+        emergency_stop: EmergencyStop,
+        status_receiver: watch::Receiver<MotionControllerStatus>,
+        odometry: Arc<std::sync::Mutex<crate::ik_controller::odometry::Odometry>>,
+        _command_sender: last_message_channel::Sender<MotionControllerCommand>,
+        _blocking_command_sender: mpsc::Sender<BlockingCommand>,
+        _high_five_sender: tokio::sync::mpsc::Sender<HighFiveCommand>,
+    }
+
+    async fn simulated_loop(terrain_config: TerrainConfig) -> SimulatedLoop {
+        use crate::body_controller::SimulatedBodyController;
+        use crate::configuration::SimulatedBodyConfig;
+        use crate::ik_controller::{odometry::Odometry, IkController};
+        use crate::zenoh_remotes::pose_publisher::ZenohPosePublisher;
+        use std::sync::Mutex;
+        use zenoh::prelude::r#async::*;
+
+        let zenoh_session = zenoh::open(zenoh::config::peer())
+            .res()
+            .await
+            .unwrap()
+            .into_arc();
+        let body_config = HopperConfig::default();
+        let body_controller =
+            SimulatedBodyController::new(&body_config, &SimulatedBodyConfig::default()).unwrap();
+        let odometry = Arc::new(Mutex::new(Odometry::new()));
+        let pose_publisher = ZenohPosePublisher::new(zenoh_session.clone(), odometry.clone())
+            .await
+            .unwrap();
+        let ik_controller =
+            IkController::new(Box::new(body_controller), body_config, pose_publisher);
+        let rate_publisher = zenoh_session
+            .declare_publisher("hopper/test/rate")
+            .res()
+            .await
+            .unwrap();
+
+        let (command_sender, command_receiver) = last_message_channel::latest_message_channel();
+        let (blocking_command_sender, blocking_command_receiver) = mpsc::channel();
+        let (status_sender, status_receiver) = watch::channel(MotionControllerStatus {
+            body_state: BodyState::Grounded,
+            body_phase: BodyPhase::Grounded,
+            move_command: MoveCommand::default(),
+            voltage: None,
+            battery_level: None,
+            battery_state: BatteryState::Normal,
+            servo_telemetry: None,
+            servo_health: ServoHealth::Normal,
+        });
+        let (high_five_sender, high_five_receiver) = tokio::sync::mpsc::channel(1);
+        let emergency_stop = EmergencyStop::new();
+        let motion_controller_loop = MotionControllerLoop::new(
+            ik_controller,
+            command_receiver,
+            blocking_command_receiver,
+            status_sender,
+            RateTracker::new(Duration::from_secs(1), rate_publisher),
+            high_five_receiver,
+            BatteryMonitor::new(BatteryConfig::default()),
+            ServoTelemetryConfig::default(),
+            TerrainAdapter::new(terrain_config),
+            emergency_stop.clone(),
+            EstopConfig::default(),
+        )
+        .await
+        .unwrap();
+        SimulatedLoop {
+            motion_controller_loop,
+            emergency_stop,
+            status_receiver,
+            odometry,
+            _command_sender: command_sender,
+            _blocking_command_sender: blocking_command_sender,
+            _high_five_sender: high_five_sender,
+        }
+    }
+
+    #[tokio::test]
+    async fn emergency_stop_resumes_from_measured_state() {
+        let mut simulated = simulated_loop(TerrainConfig::default()).await;
+        let motion_controller_loop = &mut simulated.motion_controller_loop;
+        let emergency_stop = &simulated.emergency_stop;
+        let status_receiver = &simulated.status_receiver;
+
+        // simulated body is sitting on the ground but the loop thinks it's still standing
+        motion_controller_loop.set_current_body_state(BodyState::Standing);
+        emergency_stop.trigger();
+        let clear = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert_eq!(
+                status_receiver.borrow().body_phase,
+                BodyPhase::EmergencyStopped
+            );
+            emergency_stop.clear();
+        };
+        let (terminated, ()) = tokio::join!(motion_controller_loop.handle_emergency_stop(), clear);
+
+        assert!(!terminated.unwrap());
+        assert_eq!(
+            motion_controller_loop.current_body_state,
+            BodyState::Grounded
+        );
+        let status = *status_receiver.borrow();
+        assert_eq!(status.body_state, BodyState::Grounded);
+        assert_eq!(status.body_phase, BodyPhase::Grounded);
+    }
+
+    #[tokio::test]
+    async fn odometry_integrates_while_walking_on_terrain() {
+        let mut simulated = simulated_loop(TerrainConfig {
+            enabled: true,
+            ..Default::default()
+        })
+        .await;
+        let motion_controller_loop = &mut simulated.motion_controller_loop;
+
+        let relaxed = motion_controller_loop.transformed_relaxed();
+        motion_controller_loop
+            .ik_controller
+            .move_to_positions(&relaxed)
+            .await
+            .unwrap();
+        motion_controller_loop.last_written_pose = relaxed;
+        motion_controller_loop.gait_oscillator = GaitOscillator::new(relaxed);
+        motion_controller_loop.set_current_body_state(BodyState::Standing);
+        motion_controller_loop.command.move_command =
+            MoveCommand::new(nalgebra::Vector2::new(0.03, 0.0), 0.0);
+
+        // two seconds of walking
+        for _ in 0..100 {
+            motion_controller_loop.walking_step().await.unwrap();
+        }
+        let pose = simulated.odometry.lock().unwrap().pose();
+        assert!(
+            pose.translation.x > 0.02,
+            "Odometry did not follow walking {:?}",
+            pose
+        );
+    }
 }
@@ -1,7 +1,8 @@
 use crate::{error::HopperResult, ik_controller::leg_positions::LegPositions};
 use lazy_static::lazy_static;
 use nalgebra::Point3;
-use std::fs;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
 
 pub const STANDING_LEG_HEIGHT: f32 = -0.10;
 const LEG_DISTANCE_LONGITUDAL: f32 = 0.13;
@@ -121,6 +122,82 @@ pub fn relaxed_wide_stance() -> &'static LegPositions {
     &RELAXED_WIDE
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StanceKind {
+    Relaxed,
+    Grounded,
+    RelaxedWide,
+}
+
+impl StanceKind {
+    pub const ALL: [StanceKind; 3] = [
+        StanceKind::Relaxed,
+        StanceKind::Grounded,
+        StanceKind::RelaxedWide,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StanceKind::Relaxed => "relaxed",
+            StanceKind::Grounded => "grounded",
+            StanceKind::RelaxedWide => "relaxed_wide",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    /// File name in the stance directory
+    pub fn file_name(&self) -> String {
+        format!("{}.toml", self.name())
+    }
+}
+
+/// Stances used by the motion controller
+///
+/// Defaults to the compiled in stances
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stances {
+    pub relaxed: LegPositions,
+    pub grounded: LegPositions,
+    pub relaxed_wide: LegPositions,
+}
+
+impl Default for Stances {
+    fn default() -> Self {
+        Self {
+            relaxed: *RELAXED,
+            grounded: *GROUNDED,
+            relaxed_wide: *RELAXED_WIDE,
+        }
+    }
+}
+
+impl Stances {
+    pub fn get(&self, kind: StanceKind) -> &LegPositions {
+        match kind {
+            StanceKind::Relaxed => &self.relaxed,
+            StanceKind::Grounded => &self.grounded,
+            StanceKind::RelaxedWide => &self.relaxed_wide,
+        }
+    }
+
+    pub fn set(&mut self, kind: StanceKind, positions: LegPositions) {
+        match kind {
+            StanceKind::Relaxed => self.relaxed = positions,
+            StanceKind::Grounded => self.grounded = positions,
+            StanceKind::RelaxedWide => self.relaxed_wide = positions,
+        }
+    }
+}
+
+pub fn load_stance(path: &Path) -> anyhow::Result<LegPositions> {
+    let text = fs::read_to_string(path)?;
+    Ok(toml::from_str(&text)?)
+}
+
 fn random_float(range: f32) -> f32 {
     (rand::random::<f32>() - 0.5) * 2.0 * range
 }
@@ -167,17 +244,37 @@ pub fn random_grounded_stance() -> LegPositions {
 }
 
 pub fn save_basic() -> HopperResult<()> {
-    fs::write(
-        "config/stance/relaxed.toml",
-        toml::to_string_pretty(&*RELAXED)?,
-    )?;
-    fs::write(
-        "config/stance/grounded.toml",
-        toml::to_string_pretty(&*GROUNDED)?,
-    )?;
-    fs::write(
-        "config/stance/relaxed_wide.toml",
-        toml::to_string_pretty(&*RELAXED_WIDE)?,
-    )?;
+    let stances = Stances::default();
+    for kind in StanceKind::ALL {
+        fs::write(
+            Path::new("config/stance").join(kind.file_name()),
+            toml::to_string_pretty(stances.get(kind))?,
+        )?;
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn stance_files_match_compiled_stances() {
+        let stances = Stances::default();
+        for kind in StanceKind::ALL {
+            let loaded = load_stance(&Path::new("config/stance").join(kind.file_name())).unwrap();
+            for (loaded, compiled) in loaded.as_legs().iter().zip(stances.get(kind).as_legs()) {
+                assert_relative_eq!(loaded.coords, compiled.coords, epsilon = 0.0001);
+            }
+        }
+    }
+
+    #[test]
+    fn stance_names_roundtrip() {
+        for kind in StanceKind::ALL {
+            assert_eq!(StanceKind::from_name(kind.name()), Some(kind));
+        }
+        assert_eq!(StanceKind::from_name("folded"), None);
+    }
+}
@@ -0,0 +1,285 @@
+//! Terrain adaptive foot placement
+//!
+//! The gait oscillator plans steps on flat ground. Swinging legs keep descending
+//! past the planned touchdown height until the ground holds them back.
+//! Compliant servos let a blocked foot lag behind its commanded position so contact
+//! is detected from the difference between commanded and measured leg positions.
+//! Ground height under each foot is remembered as an offset from the flat plan
+//! and the body moves towards the mean ground height.
+
+use nalgebra::Point3;
+
+use crate::{
+    configuration::TerrainConfig,
+    hexapod::{LegFlags, LEGS_IN_ORDER},
+    ik_controller::leg_positions::LegPositions,
+};
+
+pub(crate) struct TerrainAdapter {
+    config: TerrainConfig,
+    /// Ground height under each foot relative to the flat plan
+    ground_offsets: [f32; 6],
+    /// Height at which a swinging foot touched the ground
+    contact_heights: [Option<f32>; 6],
+    swinging_legs: LegFlags,
+    swing_progress: f32,
+}
+
+impl TerrainAdapter {
+    pub(crate) fn new(config: TerrainConfig) -> Self {
+        Self {
+            config,
+            ground_offsets: [0.0; 6],
+            contact_heights: [None; 6],
+            swinging_legs: LegFlags::empty(),
+            swing_progress: 0.0,
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Forget measured ground. Used when the body leaves the ground
+    pub(crate) fn reset(&mut self) {
+        self.ground_offsets = [0.0; 6];
+        self.contact_heights = [None; 6];
+        self.swinging_legs = LegFlags::empty();
+        self.swing_progress = 0.0;
+    }
+
+    /// Remove ground offsets from written positions to get back the flat plan
+    pub(crate) fn to_planned(&self, written: &LegPositions) -> LegPositions {
+        self.map_legs(written, |index, position| {
+            Point3::new(
+                position.x,
+                position.y,
+                position.z - self.ground_offsets[index],
+            )
+        })
+    }
+
+    /// Swinging legs that are descending and haven't touched the ground yet
+    ///
+    /// Only these need to be measured for [`Self::detect_contacts`]
+    pub(crate) fn legs_to_measure(&self) -> LegFlags {
+        if !self.config.enabled || self.swing_progress < 0.5 {
+            return LegFlags::empty();
+        }
+        LEGS_IN_ORDER
+            .iter()
+            .enumerate()
+            .filter(|(index, leg)| {
+                self.swinging_legs.contains(**leg) && self.contact_heights[*index].is_none()
+            })
+            .fold(LegFlags::empty(), |legs, (_, leg)| legs | *leg)
+    }
+
+    /// Record contact of descending feet that are held above their commanded height
+    pub(crate) fn detect_contacts(&mut self, commanded: &LegPositions, measured: &LegPositions) {
+        if self.swing_progress < 0.5 {
+            return;
+        }
+        let commanded = commanded.as_legs();
+        let measured = measured.as_legs();
+        for (index, leg) in LEGS_IN_ORDER.iter().enumerate() {
+            if self.swinging_legs.contains(*leg)
+                && self.contact_heights[index].is_none()
+                && measured[index].z - commanded[index].z > self.config.contact_height_error
+            {
+                self.contact_heights[index] = Some(measured[index].z);
+            }
+        }
+    }
+
+    /// Adjust heights of planned positions to the measured ground
+    pub(crate) fn adapt(
+        &mut self,
+        planned: &LegPositions,
+        swinging_legs: LegFlags,
+        swing_progress: f32,
+        elapsed_secs: f32,
+    ) -> LegPositions {
+        if !self.config.enabled {
+            return *planned;
+        }
+        // landed legs stay on the ground they found
+        let landed_legs = self.swinging_legs - swinging_legs;
+        for (index, (leg, planned)) in LEGS_IN_ORDER.iter().zip(planned.as_legs()).enumerate() {
+            if landed_legs.contains(*leg) {
+                let offset = self.contact_heights[index]
+                    .map(|contact| contact - planned.z)
+                    .unwrap_or(-self.config.max_step_down);
+                self.ground_offsets[index] =
+                    offset.clamp(-self.config.max_step_down, self.config.max_step_up);
+                self.contact_heights[index] = None;
+            }
+        }
+        self.swinging_legs = swinging_legs;
+        self.swing_progress = swing_progress;
+        self.level(elapsed_secs);
+
+        self.map_legs(planned, |index, position| {
+            if swinging_legs.contains(LEGS_IN_ORDER[index]) {
+                let z = position.z + self.swing_offset(index, swing_progress);
+                let z = self.contact_heights[index].map_or(z, |contact| z.max(contact));
+                Point3::new(position.x, position.y, z)
+            } else {
+                Point3::new(
+                    position.x,
+                    position.y,
+                    position.z + self.ground_offsets[index],
+                )
+            }
+        })
+    }
+
+    /// Swinging feet lift from their old ground and descend as far as allowed
+    fn swing_offset(&self, index: usize, swing_progress: f32) -> f32 {
+        let descent = ((swing_progress - 0.5) * 2.0).clamp(0.0, 1.0);
+        let start = self.ground_offsets[index];
+        start + (-self.config.max_step_down - start) * descent
+    }
+
+    /// Move body towards mean ground height
+    fn level(&mut self, elapsed_secs: f32) {
+        let mean = self.ground_offsets.iter().sum::<f32>() / 6.0;
+        let max_shift = self.config.leveling_rate * elapsed_secs;
+        let shift = mean.clamp(-max_shift, max_shift);
+        for offset in self.ground_offsets.iter_mut() {
+            *offset -= shift;
+        }
+        for contact in self.contact_heights.iter_mut().flatten() {
+            *contact -= shift;
+        }
+    }
+
+    fn map_legs(
+        &self,
+        positions: &LegPositions,
+        map: impl Fn(usize, &Point3<f32>) -> Point3<f32>,
+    ) -> LegPositions {
+        let legs: Vec<_> = positions
+            .as_legs()
+            .iter()
+            .enumerate()
+            .map(|(index, position)| map(index, position))
+            .collect();
+        LegPositions::from_legs([&legs[0], &legs[1], &legs[2], &legs[3], &legs[4], &legs[5]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::motion_controller::stance::relaxed_stance;
+    use approx::assert_relative_eq;
+    use nalgebra::{UnitQuaternion, Vector3};
+
+    const TICK: f32 = 0.02;
+
+    fn adapter() -> TerrainAdapter {
+        TerrainAdapter::new(TerrainConfig {
+            enabled: true,
+            ..Default::default()
+        })
+    }
+
+    fn raised(positions: &LegPositions, legs: LegFlags, height: f32) -> LegPositions {
+        let raised =
+            positions.transform(Vector3::new(0.0, 0.0, height), UnitQuaternion::identity());
+        positions.merge_with(&raised, legs)
+    }
+
+    #[test]
+    fn disabled_adapter_keeps_plan() {
+        let mut adapter = TerrainAdapter::new(TerrainConfig::default());
+        let relaxed = *relaxed_stance();
+        let adapted = adapter.adapt(&relaxed, LegFlags::LRL_TRIPOD, 0.9, TICK);
+        assert_eq!(adapted, relaxed);
+    }
+
+    #[test]
+    fn foot_descends_below_plan_without_contact() {
+        let mut adapter = adapter();
+        let relaxed = *relaxed_stance();
+        let adapted = adapter.adapt(&relaxed, LegFlags::LEFT_FRONT, 1.0, TICK);
+        assert_relative_eq!(
+            adapted.left_front().z,
+            relaxed.left_front().z - 0.03,
+            epsilon = 0.0001
+        );
+        assert_relative_eq!(adapted.right_front().z, relaxed.right_front().z);
+    }
+
+    #[test]
+    fn foot_stops_descending_on_contact() {
+        let mut adapter = adapter();
+        let relaxed = *relaxed_stance();
+        let commanded = adapter.adapt(&relaxed, LegFlags::LEFT_FRONT, 0.75, TICK);
+        // obstacle holds the foot 2cm above plan
+        let measured = raised(&relaxed, LegFlags::LEFT_FRONT, 0.02);
+        adapter.detect_contacts(&commanded, &measured);
+        let adapted = adapter.adapt(&relaxed, LegFlags::LEFT_FRONT, 0.9, TICK);
+        assert_relative_eq!(
+            adapted.left_front().z,
+            measured.left_front().z,
+            epsilon = 0.0001
+        );
+    }
+
+    #[test]
+    fn only_descending_legs_without_contact_are_measured() {
+        let mut adapter = adapter();
+        let relaxed = *relaxed_stance();
+        adapter.adapt(&relaxed, LegFlags::LRL_TRIPOD, 0.25, TICK);
+        assert!(adapter.legs_to_measure().is_empty());
+        let commanded = adapter.adapt(&relaxed, LegFlags::LRL_TRIPOD, 0.75, TICK);
+        assert_eq!(adapter.legs_to_measure(), LegFlags::LRL_TRIPOD);
+        let measured = raised(&relaxed, LegFlags::LEFT_FRONT, 0.02);
+        adapter.detect_contacts(&commanded, &measured);
+        assert_eq!(
+            adapter.legs_to_measure(),
+            LegFlags::LRL_TRIPOD - LegFlags::LEFT_FRONT
+        );
+    }
+
+    #[test]
+    fn body_levels_over_ground() {
+        let mut adapter = adapter();
+        let relaxed = *relaxed_stance();
+        let commanded = adapter.adapt(&relaxed, LegFlags::LRL_TRIPOD, 0.75, TICK);
+        let measured = raised(&relaxed, LegFlags::LRL_TRIPOD, 0.02);
+        adapter.detect_contacts(&commanded, &measured);
+        // landing on the obstacle
+        let landed = adapter.adapt(&relaxed, LegFlags::empty(), 0.0, TICK);
+        let left_front_offset = landed.left_front().z - relaxed.left_front().z;
+        assert!(left_front_offset > 0.0);
+        assert_relative_eq!(
+            landed.right_front().z,
+            relaxed.right_front().z,
+            epsilon = 0.001
+        );
+        // body rises over mean ground height but keeps feet on their ground
+        let mut pose = landed;
+        for _ in 0..100 {
+            pose = adapter.adapt(&relaxed, LegFlags::empty(), 0.0, TICK);
+        }
+        assert_relative_eq!(
+            pose.left_front().z - pose.right_front().z,
+            0.02,
+            epsilon = 0.0001
+        );
+        let mean_offset = pose
+            .as_legs()
+            .iter()
+            .zip(relaxed.as_legs())
+            .map(|(pose, relaxed)| pose.z - relaxed.z)
+            .sum::<f32>();
+        assert_relative_eq!(mean_offset, 0.0, epsilon = 0.0001);
+        let planned = adapter.to_planned(&pose);
+        for (planned, relaxed) in planned.as_legs().iter().zip(relaxed.as_legs()) {
+            assert_relative_eq!(planned.z, relaxed.z, epsilon = 0.0001);
+        }
+    }
+}
@@ -2,9 +2,10 @@ use crate::{
     body_controller::{
         motor_controller::{HexapodCompliance, HexapodMotorSpeed},
         motor_positions::OptionalBodyMotorPositions,
-        BodyController, BodyMotorPositions,
+        BodyController, BodyMotorPositions, BodyMotorTelemetry,
     },
     error::HopperResult,
+    hopper_body_config::HopperConfig,
     ik_controller::leg_positions::*,
 };
 use anyhow::{anyhow, Result};
@@ -115,6 +116,10 @@ impl BodyController for HopperVisualizer {
         Ok(12.0)
     }
 
+    async fn read_motor_telemetry(&mut self) -> HopperResult<BodyMotorTelemetry> {
+        unimplemented!("shouldn't be called on a mock");
+    }
+
     async fn scan_motors(&mut self) -> HopperResult<()> {
         unimplemented!("shouldn't be called on a mock");
     }
@@ -139,6 +144,14 @@ impl IkControllable for HopperVisualizer {
     async fn disable_motors(&mut self) -> HopperResult<()> {
         unimplemented!("shouldn't be called on a mock");
     }
+
+    fn set_body_configuration(&mut self, _body_configuration: HopperConfig) -> HopperResult<()> {
+        Ok(())
+    }
+
+    fn check_reachable(&self, _positions: &LegPositions) -> HopperResult<()> {
+        Ok(())
+    }
 }
 
 impl Drop for HopperVisualizer {
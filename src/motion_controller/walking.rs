@@ -1,14 +1,18 @@
-use crate::hexapod::LegFlags;
+use super::gait::GaitType;
+use crate::hexapod::{LegFlags, LEGS_IN_ORDER};
 use crate::ik_controller::leg_positions::LegPositions;
 use nalgebra::{distance, Point3, Rotation2, Rotation3, Vector2, Vector3};
 use serde::{Deserialize, Serialize};
 use std::f32;
-use std::time::{Duration, Instant};
-use tracing::*;
+use std::time::Duration;
 
 pub const DEFAULT_STEP_TIME: Duration = Duration::from_millis(600);
 pub const DEFAULT_STEP_HEIGHT: f32 = 0.03;
 pub const DEFAULT_STEP_DISTANCE: f32 = 0.025;
+pub const DEFAULT_MAX_YAW_RATE_DEG: f32 = 15.0;
+/// Fast walking mode of the gamepad
+pub const FAST_STEP_TIME: Duration = Duration::from_millis(400);
+pub const FAST_STEP_DISTANCE: f32 = 0.03;
 
 #[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq)]
 pub struct MoveCommand {
@@ -17,6 +21,8 @@ pub struct MoveCommand {
     step_time: Duration,
     step_height: f32,
     aggressive_leg_lift: bool,
+    #[serde(default)]
+    gait: GaitType,
 }
 
 impl Default for MoveCommand {
@@ -27,6 +33,7 @@ impl Default for MoveCommand {
             step_time: DEFAULT_STEP_TIME,
             step_height: DEFAULT_STEP_HEIGHT,
             aggressive_leg_lift: false,
+            gait: GaitType::default(),
         }
     }
 }
@@ -53,9 +60,25 @@ impl MoveCommand {
             step_time,
             step_height,
             aggressive_leg_lift,
+            gait: GaitType::default(),
         }
     }
 
+    pub fn with_direction(mut self, direction: Vector2<f32>) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_gait(mut self, gait: GaitType) -> Self {
+        self.gait = gait;
+        self
+    }
+
     pub fn direction(&self) -> Vector2<f32> {
         self.direction
     }
@@ -76,6 +99,10 @@ impl MoveCommand {
         self.aggressive_leg_lift
     }
 
+    pub fn gait(&self) -> GaitType {
+        self.gait
+    }
+
     pub fn should_move(&self) -> bool {
         let is_zero = self.rotation.abs() < f32::EPSILON
             && self.direction.x.abs() < f32::EPSILON
@@ -114,7 +141,7 @@ pub(crate) struct StepIterator {
     max_move: f32,
     step_height: f32,
     grounded_leg_descent: f32,
-    tripod: Tripod,
+    lifted_legs: LegFlags,
     /// In this mode the legs will immediately lift to full height
     /// this prevents dragging of feet but also results in a very choppy movement
     aggressive_leg_lift: bool,
@@ -136,7 +163,7 @@ impl StepIterator {
             max_move,
             step_height,
             grounded_leg_descent: 0.0,
-            tripod,
+            lifted_legs: tripod.as_flag(),
             aggressive_leg_lift,
         }
     }
@@ -159,76 +186,7 @@ impl Iterator for StepIterator {
             &self.target,
             self.step_height,
             self.grounded_leg_descent,
-            self.tripod,
-            progress,
-            self.aggressive_leg_lift,
-        );
-        if moved {
-            self.last = positions;
-            Some(self.last)
-        } else {
-            None
-        }
-    }
-}
-
-pub(crate) struct TimedStepIterator {
-    start: LegPositions,
-    last: LegPositions,
-    target: LegPositions,
-    period: Duration,
-    step_height: f32,
-    grounded_leg_descent: f32,
-    tripod: Tripod,
-    start_time: Instant,
-    /// In this mode the legs will immediately lift to full height
-    /// this prevents dragging of feet but also results in a very choppy movement
-    aggressive_leg_lift: bool,
-}
-
-impl TimedStepIterator {
-    pub(crate) fn step(
-        start: LegPositions,
-        target: LegPositions,
-        period: Duration,
-        step_height: f32,
-        grounded_leg_descent: f32,
-        tripod: Tripod,
-        aggressive_leg_lift: bool,
-    ) -> Self {
-        Self {
-            start,
-            last: start,
-            target,
-            period,
-            step_height,
-            grounded_leg_descent,
-            tripod,
-            start_time: Instant::now(),
-            aggressive_leg_lift,
-        }
-    }
-}
-
-impl Iterator for TimedStepIterator {
-    type Item = LegPositions;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let full_distance = max_horizontal_distance(&self.start, &self.target);
-        if full_distance <= 0.0 {
-            return None;
-        }
-        let step_portion = self.start_time.elapsed().as_secs_f32() / self.period.as_secs_f32();
-        let progress = step_portion / self.period.as_secs_f32();
-        trace!("Step progress is {}", progress);
-
-        let (positions, moved) = shift_legs(
-            &self.start,
-            &self.last,
-            &self.target,
-            self.step_height,
-            self.grounded_leg_descent,
-            self.tripod,
+            self.lifted_legs,
             progress,
             self.aggressive_leg_lift,
         );
@@ -248,136 +206,41 @@ fn shift_legs(
     target: &LegPositions,
     step_height: f32,
     grounded_leg_descent: f32,
-    tripod: Tripod,
+    lifted_legs: LegFlags,
     progress: f32,
     aggressive_leg_lift: bool,
 ) -> (LegPositions, bool) {
-    match tripod {
-        Tripod::LRL => {
-            // lifted
-            let (left_front, lf_moved) = step_lifted_leg(
-                start.left_front(),
-                last.left_front(),
-                target.left_front(),
-                step_height,
-                progress,
-                aggressive_leg_lift,
-            );
-            let (right_middle, rm_moved) = step_lifted_leg(
-                start.right_middle(),
-                last.right_middle(),
-                target.right_middle(),
-                step_height,
-                progress,
-                aggressive_leg_lift,
-            );
-            let (left_rear, lr_moved) = step_lifted_leg(
-                start.left_rear(),
-                last.left_rear(),
-                target.left_rear(),
-                step_height,
-                progress,
-                aggressive_leg_lift,
-            );
-            // grounded
-            let (right_front, rf_moved) = step_lifted_leg(
-                start.right_front(),
-                last.right_front(),
-                target.right_front(),
-                grounded_leg_descent,
-                progress,
-                aggressive_leg_lift,
-            );
-            let (left_middle, lm_moved) = step_lifted_leg(
-                start.left_middle(),
-                last.left_middle(),
-                target.left_middle(),
-                grounded_leg_descent,
-                progress,
-                aggressive_leg_lift,
-            );
-            let (right_rear, rr_moved) = step_lifted_leg(
-                start.right_rear(),
-                last.right_rear(),
-                target.right_rear(),
-                grounded_leg_descent,
-                progress,
-                aggressive_leg_lift,
-            );
-            let moved = lf_moved || lm_moved || lr_moved || rf_moved || rm_moved || rr_moved;
-            let positions = LegPositions::new(
-                left_front,
-                left_middle,
-                left_rear,
-                right_front,
-                right_middle,
-                right_rear,
-            );
-            (positions, moved)
-        }
-        Tripod::RLR => {
-            // grounded
-            let (left_front, lf_moved) = step_lifted_leg(
-                start.left_front(),
-                last.left_front(),
-                target.left_front(),
-                grounded_leg_descent,
-                progress,
-                aggressive_leg_lift,
-            );
-            let (right_middle, rm_moved) = step_lifted_leg(
-                start.right_middle(),
-                last.right_middle(),
-                target.right_middle(),
-                grounded_leg_descent,
-                progress,
-                aggressive_leg_lift,
-            );
-            let (left_rear, lr_moved) = step_lifted_leg(
-                start.left_rear(),
-                last.left_rear(),
-                target.left_rear(),
-                grounded_leg_descent,
-                progress,
-                aggressive_leg_lift,
-            );
-            // lifted
-            let (right_front, rf_moved) = step_lifted_leg(
-                start.right_front(),
-                last.right_front(),
-                target.right_front(),
-                step_height,
-                progress,
-                aggressive_leg_lift,
-            );
-            let (left_middle, lm_moved) = step_lifted_leg(
-                start.left_middle(),
-                last.left_middle(),
-                target.left_middle(),
-                step_height,
-                progress,
-                aggressive_leg_lift,
-            );
-            let (right_rear, rr_moved) = step_lifted_leg(
-                start.right_rear(),
-                last.right_rear(),
-                target.right_rear(),
-                step_height,
-                progress,
-                aggressive_leg_lift,
-            );
-            let moved = lf_moved || lm_moved || lr_moved || rf_moved || rm_moved || rr_moved;
-            let positions = LegPositions::new(
-                left_front,
-                left_middle,
-                left_rear,
-                right_front,
-                right_middle,
-                right_rear,
-            );
-            (positions, moved)
-        }
+    let start = start.as_legs();
+    let last = last.as_legs();
+    let target = target.as_legs();
+    let mut positions = [Point3::origin(); 6];
+    let mut moved = false;
+    for (index, leg) in LEGS_IN_ORDER.iter().enumerate() {
+        let height = if lifted_legs.contains(*leg) {
+            step_height
+        } else {
+            grounded_leg_descent
+        };
+        let (position, leg_moved) = step_lifted_leg(
+            start[index],
+            last[index],
+            target[index],
+            height,
+            progress,
+            aggressive_leg_lift,
+        );
+        positions[index] = position;
+        moved |= leg_moved;
     }
+    let positions = LegPositions::from_legs([
+        &positions[0],
+        &positions[1],
+        &positions[2],
+        &positions[3],
+        &positions[4],
+        &positions[5],
+    ]);
+    (positions, moved)
 }
 
 pub(crate) fn step_lifted_leg(
@@ -0,0 +1,440 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use nalgebra::{Isometry2, Vector2};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch};
+use tracing::*;
+use zenoh::prelude::r#async::*;
+
+use crate::{
+    error::HopperError,
+    ik_controller::odometry::Odometry,
+    ioc_container::IocContainer,
+    motion_controller::walking::MoveCommand,
+    zenoh_remotes::{
+        remote_controller::MoveService,
+        topic_consts::{
+            NAVIGATION_CANCEL_SUBSCRIBER, NAVIGATION_GOAL_SUBSCRIBER, NAVIGATION_STATUS,
+        },
+    },
+};
+
+const CONTROL_PERIOD: Duration = Duration::from_millis(100);
+const POSITION_TOLERANCE: f32 = 0.03;
+const YAW_TOLERANCE: f32 = 5.0 * std::f32::consts::PI / 180.0;
+const MAX_STEP_DISTANCE: f32 = 0.03;
+const MAX_ROTATION: f32 = 15.0 * std::f32::consts::PI / 180.0;
+/// Goal fails if remaining error doesn't shrink by this much within timeout
+const MIN_PROGRESS: f32 = 0.01;
+const NO_PROGRESS_TIMEOUT: Duration = Duration::from_secs(10);
+/// Weight of yaw error in radians when measuring progress
+const YAW_PROGRESS_WEIGHT: f32 = 0.1;
+
+/// Goal relative to the body pose at the time it was received
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema, Default)]
+pub struct NavigationGoal {
+    /// forward in meters
+    #[serde(default)]
+    pub x: f32,
+    /// left in meters
+    #[serde(default)]
+    pub y: f32,
+    /// counter clockwise rotation in radians
+    #[serde(default)]
+    pub yaw: f32,
+}
+
+impl NavigationGoal {
+    fn to_isometry(self) -> Isometry2<f32> {
+        Isometry2::new(Vector2::new(self.x, self.y), self.yaw)
+    }
+}
+
+/// Status of navigation request
+///
+/// Each request gets its own id so that identical goals can be told apart
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum NavigationStatus {
+    Idle,
+    Active {
+        id: u64,
+        goal: NavigationGoal,
+        remaining_distance_m: f32,
+        remaining_yaw_rad: f32,
+    },
+    Completed {
+        id: u64,
+        goal: NavigationGoal,
+    },
+    Failed {
+        id: u64,
+        goal: NavigationGoal,
+        reason: String,
+    },
+    Canceled {
+        id: u64,
+        goal: NavigationGoal,
+    },
+}
+
+impl NavigationStatus {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, NavigationStatus::Active { .. })
+    }
+
+    pub fn goal(&self) -> Option<NavigationGoal> {
+        match self {
+            NavigationStatus::Idle => None,
+            NavigationStatus::Active { goal, .. }
+            | NavigationStatus::Completed { goal, .. }
+            | NavigationStatus::Failed { goal, .. }
+            | NavigationStatus::Canceled { goal, .. } => Some(*goal),
+        }
+    }
+
+    pub fn id(&self) -> Option<u64> {
+        match self {
+            NavigationStatus::Idle => None,
+            NavigationStatus::Active { id, .. }
+            | NavigationStatus::Completed { id, .. }
+            | NavigationStatus::Failed { id, .. }
+            | NavigationStatus::Canceled { id, .. } => Some(*id),
+        }
+    }
+}
+
+enum NavigationRequest {
+    Goal { id: u64, goal: NavigationGoal },
+    Cancel,
+}
+
+/// Drives the robot to goal poses using odometry
+#[derive(Clone)]
+pub struct NavigationService {
+    request_sender: mpsc::Sender<NavigationRequest>,
+    status_receiver: watch::Receiver<NavigationStatus>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl NavigationService {
+    /// Returns id of the new request
+    pub async fn set_goal(&self, goal: NavigationGoal) -> anyhow::Result<u64> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.request_sender
+            .send(NavigationRequest::Goal { id, goal })
+            .await?;
+        Ok(id)
+    }
+
+    pub async fn cancel(&self) -> anyhow::Result<()> {
+        self.request_sender.send(NavigationRequest::Cancel).await?;
+        Ok(())
+    }
+
+    pub fn status(&self) -> NavigationStatus {
+        self.status_receiver.borrow().clone()
+    }
+
+    /// Set goal and wait until it's finished
+    pub async fn navigate(&self, goal: NavigationGoal) -> anyhow::Result<NavigationStatus> {
+        let mut status_receiver = self.status_receiver.clone();
+        // ignore statuses from before this goal was set
+        status_receiver.borrow_and_update();
+        let id = self.set_goal(goal).await?;
+        loop {
+            status_receiver.changed().await?;
+            let status = status_receiver.borrow_and_update().clone();
+            if status.id() == Some(id) && status.is_finished() {
+                return Ok(status);
+            }
+        }
+    }
+}
+
+struct ActiveGoal {
+    id: u64,
+    goal: NavigationGoal,
+    target: Isometry2<f32>,
+    best_error: f32,
+    last_progress: Instant,
+}
+
+impl ActiveGoal {
+    fn canceled(&self) -> NavigationStatus {
+        NavigationStatus::Canceled {
+            id: self.id,
+            goal: self.goal,
+        }
+    }
+}
+
+pub async fn start_navigation_service(
+    zenoh_session: Arc<Session>,
+    odometry: Arc<Mutex<Odometry>>,
+) -> anyhow::Result<NavigationService> {
+    let goal_subscriber = zenoh_session
+        .declare_subscriber(NAVIGATION_GOAL_SUBSCRIBER)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+    let cancel_subscriber = zenoh_session
+        .declare_subscriber(NAVIGATION_CANCEL_SUBSCRIBER)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+    let status_publisher = zenoh_session
+        .declare_publisher(NAVIGATION_STATUS)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+
+    let (request_sender, mut request_receiver) = mpsc::channel(10);
+    let (status_sender, status_receiver) = watch::channel(NavigationStatus::Idle);
+    let next_id = Arc::new(AtomicU64::new(0));
+
+    tokio::spawn({
+        let request_sender = request_sender.clone();
+        let next_id = next_id.clone();
+        async move {
+            loop {
+                tokio::select! {
+                    Ok(sample) = goal_subscriber.recv_async() => {
+                        match parse_goal(sample) {
+                            Ok(goal) => {
+                                let id = next_id.fetch_add(1, Ordering::Relaxed);
+                                if request_sender.send(NavigationRequest::Goal { id, goal }).await.is_err() {
+                                    break;
+                                }
+                            }
+                            Err(error) => warn!("Failed to parse navigation goal: {}", error),
+                        }
+                    }
+                    Ok(_) = cancel_subscriber.recv_async() => {
+                        if request_sender.send(NavigationRequest::Cancel).await.is_err() {
+                            break;
+                        }
+                    }
+                    else => break,
+                }
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut active_goal: Option<ActiveGoal> = None;
+        let mut interval = tokio::time::interval(CONTROL_PERIOD);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            let status = tokio::select! {
+                request = request_receiver.recv() => {
+                    match request {
+                        Some(NavigationRequest::Goal { id, goal }) => {
+                            info!(id, ?goal, "New navigation goal");
+                            let pose = odometry.lock().unwrap().pose();
+                            let previous_goal = active_goal.replace(ActiveGoal {
+                                id,
+                                goal,
+                                target: pose * goal.to_isometry(),
+                                best_error: f32::INFINITY,
+                                last_progress: Instant::now(),
+                            });
+                            previous_goal.map(|previous| previous.canceled())
+                        }
+                        Some(NavigationRequest::Cancel) => {
+                            active_goal.take().map(|active| active.canceled())
+                        }
+                        None => break,
+                    }
+                }
+                _ = interval.tick() => {
+                    match active_goal.as_mut() {
+                        Some(active) => {
+                            let pose = odometry.lock().unwrap().pose();
+                            let status = step_goal(active, &pose).await;
+                            if status.is_finished() {
+                                active_goal = None;
+                            }
+                            Some(status)
+                        }
+                        None => None,
+                    }
+                }
+            };
+
+            if let Some(status) = status {
+                if status.is_finished() {
+                    info!(?status, "Navigation finished");
+                    if let Err(error) = send_move(MoveCommand::default()).await {
+                        error!("Failed to stop after navigation: {}", error);
+                    }
+                }
+                match serde_json::to_string(&status) {
+                    Ok(json) => {
+                        if let Err(error) = status_publisher.put(json).res().await {
+                            error!("Failed to publish navigation status: {}", error);
+                        }
+                    }
+                    Err(error) => error!("Failed to serialize navigation status: {}", error),
+                }
+                status_sender.send_replace(status);
+            }
+        }
+    });
+
+    Ok(NavigationService {
+        request_sender,
+        status_receiver,
+        next_id,
+    })
+}
+
+fn parse_goal(sample: zenoh::sample::Sample) -> anyhow::Result<NavigationGoal> {
+    let message: String = sample.value.try_into()?;
+    Ok(serde_yaml::from_str(&message)?)
+}
+
+async fn send_move(command: MoveCommand) -> anyhow::Result<()> {
+    IocContainer::global_instance()
+        .service::<MoveService>()?
+        .send_move(command)
+        .await
+}
+
+/// Advance active goal and return its status
+async fn step_goal(active: &mut ActiveGoal, pose: &Isometry2<f32>) -> NavigationStatus {
+    let error = pose.inverse() * active.target;
+    let remaining_distance_m = error.translation.vector.norm();
+    let remaining_yaw_rad = error.rotation.angle();
+
+    let command = match navigation_command(&error) {
+        Some(command) => command,
+        None => {
+            return NavigationStatus::Completed {
+                id: active.id,
+                goal: active.goal,
+            }
+        }
+    };
+
+    let progress_error = remaining_distance_m + remaining_yaw_rad.abs() * YAW_PROGRESS_WEIGHT;
+    if progress_error < active.best_error - MIN_PROGRESS {
+        active.best_error = progress_error;
+        active.last_progress = Instant::now();
+    } else if active.last_progress.elapsed() > NO_PROGRESS_TIMEOUT {
+        return NavigationStatus::Failed {
+            id: active.id,
+            goal: active.goal,
+            reason: "No progress towards goal. Path may be blocked".to_string(),
+        };
+    }
+
+    if let Err(error) = send_move(command).await {
+        return NavigationStatus::Failed {
+            id: active.id,
+            goal: active.goal,
+            reason: format!("Failed to send move command: {}", error),
+        };
+    }
+
+    NavigationStatus::Active {
+        id: active.id,
+        goal: active.goal,
+        remaining_distance_m,
+        remaining_yaw_rad,
+    }
+}
+
+/// Walking command reducing error between body and goal
+///
+/// Error is goal pose in body frame. Returns None when goal is reached.
+fn navigation_command(error: &Isometry2<f32>) -> Option<MoveCommand> {
+    let translation = error.translation.vector;
+    let distance = translation.norm();
+    let yaw = error.rotation.angle();
+    if distance < POSITION_TOLERANCE && yaw.abs() < YAW_TOLERANCE {
+        return None;
+    }
+    let direction = if distance < POSITION_TOLERANCE {
+        Vector2::zeros()
+    } else {
+        translation / distance * distance.min(MAX_STEP_DISTANCE)
+    };
+    let rotation = if yaw.abs() < YAW_TOLERANCE {
+        0.0
+    } else {
+        yaw.clamp(-MAX_ROTATION, MAX_ROTATION)
+    };
+    Some(MoveCommand::new(direction, rotation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn reached_goal_has_no_command() {
+        let error = Isometry2::new(Vector2::new(0.01, -0.01), 0.01);
+        assert!(navigation_command(&error).is_none());
+    }
+
+    #[test]
+    fn far_goal_is_approached_with_max_step() {
+        let error = Isometry2::new(Vector2::new(1.0, 0.0), 0.0);
+        let command = navigation_command(&error).unwrap();
+        assert_relative_eq!(command.direction().x, MAX_STEP_DISTANCE);
+        assert_relative_eq!(command.direction().y, 0.0);
+        assert_relative_eq!(command.rotation(), 0.0);
+    }
+
+    #[test]
+    fn rotation_is_limited() {
+        let error = Isometry2::new(Vector2::zeros(), -std::f32::consts::FRAC_PI_2);
+        let command = navigation_command(&error).unwrap();
+        assert_eq!(command.direction(), Vector2::zeros());
+        assert_relative_eq!(command.rotation(), -MAX_ROTATION);
+    }
+
+    #[tokio::test]
+    async fn repeated_goal_is_not_finished_by_cancel_of_previous() {
+        let zenoh_session = zenoh::open(zenoh::config::peer())
+            .res()
+            .await
+            .unwrap()
+            .into_arc();
+        let navigation_service =
+            start_navigation_service(zenoh_session, Arc::new(Mutex::new(Odometry::new())))
+                .await
+                .unwrap();
+        let goal = NavigationGoal {
+            x: 1.0,
+            y: 0.0,
+            yaw: 0.0,
+        };
+        let first = navigation_service.set_goal(goal).await.unwrap();
+        let status = navigation_service.navigate(goal).await.unwrap();
+        assert_ne!(status.id(), Some(first));
+        assert!(!matches!(status, NavigationStatus::Canceled { .. }));
+    }
+
+    #[test]
+    fn goal_is_relative_to_current_pose() {
+        let pose = Isometry2::new(Vector2::new(1.0, 0.0), std::f32::consts::FRAC_PI_2);
+        let goal = NavigationGoal {
+            x: 1.0,
+            y: 0.0,
+            yaw: 0.0,
+        };
+        let target = pose * goal.to_isometry();
+        assert_relative_eq!(target.translation.x, 1.0, epsilon = 0.0001);
+        assert_relative_eq!(target.translation.y, 1.0, epsilon = 0.0001);
+    }
+}
@@ -0,0 +1,263 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use nalgebra::{Point2, Vector2};
+use rplidar_driver::ScanPoint;
+use serde::Serialize;
+
+use crate::{
+    configuration::ObstacleGuardConfig, lidar::scan_to_body_points,
+    motion_controller::walking::MoveCommand,
+};
+
+/// Why walking command was limited
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum MotionLimit {
+    /// No obstacles in direction of travel
+    Clear,
+    /// Obstacle guard is disabled in config
+    Disabled,
+    /// No recent lidar scan available. Translation is stopped
+    NoScan,
+    Slowed {
+        obstacle_distance_m: f32,
+        speed_ratio: f32,
+    },
+    Stopped {
+        obstacle_distance_m: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ObstacleGuardStatus {
+    #[serde(flatten)]
+    pub limit: MotionLimit,
+    pub requested_direction: [f32; 2],
+    pub limited_direction: [f32; 2],
+}
+
+/// Closest lidar point in latest scan
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct NearestObstacle {
+    /// Distance from center of the body
+    pub distance_m: f32,
+    /// Counter clockwise from forward
+    pub bearing_deg: f32,
+    pub scan_age_ms: u64,
+}
+
+struct Scan {
+    points: Vec<Point2<f32>>,
+    capture_time: Instant,
+}
+
+/// Limits walking commands based on obstacles in latest lidar scan
+#[derive(Clone)]
+pub struct ObstacleGuard {
+    config: ObstacleGuardConfig,
+    latest_scan: Arc<Mutex<Option<Scan>>>,
+}
+
+impl ObstacleGuard {
+    pub fn new(config: ObstacleGuardConfig) -> Self {
+        Self {
+            config,
+            latest_scan: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Store new scan
+    pub fn process_scan(&self, scan: &[ScanPoint]) {
+        self.update_points(scan_to_body_points(scan));
+    }
+
+    /// Store new set of obstacle points in body frame
+    pub fn update_points(&self, points: Vec<Point2<f32>>) {
+        self.latest_scan.lock().unwrap().replace(Scan {
+            points,
+            capture_time: Instant::now(),
+        });
+    }
+
+    /// Closest point in latest scan. None if there is no recent scan
+    pub fn nearest_obstacle(&self) -> Option<NearestObstacle> {
+        let max_scan_age = Duration::from_millis(self.config.max_scan_age_ms);
+        let latest_scan = self.latest_scan.lock().unwrap();
+        let scan = latest_scan
+            .as_ref()
+            .filter(|scan| scan.capture_time.elapsed() <= max_scan_age)?;
+        scan.points
+            .iter()
+            .min_by(|a, b| a.coords.norm().total_cmp(&b.coords.norm()))
+            .map(|point| NearestObstacle {
+                distance_m: point.coords.norm(),
+                bearing_deg: point.y.atan2(point.x).to_degrees(),
+                scan_age_ms: scan.capture_time.elapsed().as_millis() as u64,
+            })
+    }
+
+    /// Slow down or stop command if there are obstacles in direction of travel
+    ///
+    /// Only the translation is limited. Rotating in place is always allowed.
+    /// Without a recent scan the guard can't see anything so translation is stopped.
+    pub fn limit(&self, command: MoveCommand) -> (MoveCommand, ObstacleGuardStatus) {
+        let requested_direction = command.direction();
+        let limit = self.check_direction(requested_direction);
+        let speed_ratio = match limit {
+            MotionLimit::Slowed { speed_ratio, .. } => speed_ratio,
+            MotionLimit::Stopped { .. } | MotionLimit::NoScan => 0.0,
+            _ => 1.0,
+        };
+        let limited_direction = requested_direction * speed_ratio;
+        let status = ObstacleGuardStatus {
+            limit,
+            requested_direction: requested_direction.into(),
+            limited_direction: limited_direction.into(),
+        };
+        (command.with_direction(limited_direction), status)
+    }
+
+    fn check_direction(&self, direction: Vector2<f32>) -> MotionLimit {
+        if !self.config.enabled {
+            return MotionLimit::Disabled;
+        }
+        let max_scan_age = Duration::from_millis(self.config.max_scan_age_ms);
+        let latest_scan = self.latest_scan.lock().unwrap();
+        let scan = match latest_scan.as_ref() {
+            Some(scan) if scan.capture_time.elapsed() <= max_scan_age => scan,
+            _ => return MotionLimit::NoScan,
+        };
+        let speed = direction.norm();
+        if speed < f32::EPSILON {
+            return MotionLimit::Clear;
+        }
+        let heading = direction / speed;
+
+        let closest = scan
+            .points
+            .iter()
+            .filter_map(|point| {
+                let along = point.coords.dot(&heading);
+                let lateral = point.coords.perp(&heading).abs();
+                (along > 0.0 && lateral <= self.config.corridor_half_width_m).then_some(along)
+            })
+            .fold(f32::INFINITY, f32::min);
+
+        if closest <= self.config.stop_distance_m {
+            MotionLimit::Stopped {
+                obstacle_distance_m: closest,
+            }
+        } else if closest < self.config.slow_distance_m {
+            let speed_ratio = (closest - self.config.stop_distance_m)
+                / (self.config.slow_distance_m - self.config.stop_distance_m);
+            MotionLimit::Slowed {
+                obstacle_distance_m: closest,
+                speed_ratio,
+            }
+        } else {
+            MotionLimit::Clear
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn guard_with_points(points: Vec<Point2<f32>>) -> ObstacleGuard {
+        let guard = ObstacleGuard::new(ObstacleGuardConfig::default());
+        guard.update_points(points);
+        guard
+    }
+
+    #[test]
+    fn no_scan_stops_translation() {
+        let guard = ObstacleGuard::new(ObstacleGuardConfig::default());
+        let command = MoveCommand::new(Vector2::new(0.03, 0.0), 0.1);
+        let (limited, status) = guard.limit(command);
+        assert_eq!(limited.direction(), Vector2::zeros());
+        assert_eq!(limited.rotation(), 0.1);
+        assert_eq!(status.limit, MotionLimit::NoScan);
+    }
+
+    #[test]
+    fn stale_scan_stops_translation() {
+        let guard = ObstacleGuard::new(ObstacleGuardConfig {
+            max_scan_age_ms: 0,
+            ..Default::default()
+        });
+        guard.update_points(vec![]);
+        std::thread::sleep(Duration::from_millis(5));
+        let (limited, status) = guard.limit(MoveCommand::new(Vector2::new(0.03, 0.0), 0.0));
+        assert_eq!(limited.direction(), Vector2::zeros());
+        assert_eq!(status.limit, MotionLimit::NoScan);
+    }
+
+    #[test]
+    fn disabled_guard_passes_command_through() {
+        let guard = ObstacleGuard::new(ObstacleGuardConfig {
+            enabled: false,
+            ..Default::default()
+        });
+        let command = MoveCommand::new(Vector2::new(0.03, 0.0), 0.0);
+        let (limited, status) = guard.limit(command);
+        assert_eq!(limited, command);
+        assert_eq!(status.limit, MotionLimit::Disabled);
+    }
+
+    #[test]
+    fn obstacle_ahead_stops_forward_motion() {
+        let guard = guard_with_points(vec![Point2::new(0.2, 0.05)]);
+        let command = MoveCommand::new(Vector2::new(0.03, 0.0), 0.1);
+        let (limited, status) = guard.limit(command);
+        assert_eq!(limited.direction(), Vector2::zeros());
+        assert_eq!(limited.rotation(), 0.1);
+        assert!(matches!(status.limit, MotionLimit::Stopped { .. }));
+    }
+
+    #[test]
+    fn obstacle_ahead_does_not_limit_walking_away() {
+        let guard = guard_with_points(vec![Point2::new(0.2, 0.05)]);
+        let command = MoveCommand::new(Vector2::new(-0.03, 0.0), 0.0);
+        let (limited, status) = guard.limit(command);
+        assert_eq!(limited, command);
+        assert_eq!(status.limit, MotionLimit::Clear);
+    }
+
+    #[test]
+    fn obstacle_outside_of_corridor_is_ignored() {
+        let guard = guard_with_points(vec![Point2::new(0.2, 0.3)]);
+        let command = MoveCommand::new(Vector2::new(0.03, 0.0), 0.0);
+        let (_, status) = guard.limit(command);
+        assert_eq!(status.limit, MotionLimit::Clear);
+    }
+
+    #[test]
+    fn nearest_obstacle_is_closest_point() {
+        let guard = guard_with_points(vec![Point2::new(1.0, 0.0), Point2::new(0.0, 0.5)]);
+        let nearest = guard.nearest_obstacle().unwrap();
+        assert_relative_eq!(nearest.distance_m, 0.5);
+        assert_relative_eq!(nearest.bearing_deg, 90.0);
+        assert!(ObstacleGuard::new(ObstacleGuardConfig::default())
+            .nearest_obstacle()
+            .is_none());
+    }
+
+    #[test]
+    fn obstacle_in_slow_zone_scales_speed() {
+        let guard = guard_with_points(vec![Point2::new(0.0, -0.375)]);
+        let command = MoveCommand::new(Vector2::new(0.0, -0.02), 0.0);
+        let (limited, status) = guard.limit(command);
+        assert_relative_eq!(limited.direction().y, -0.01, epsilon = 0.0001);
+        match status.limit {
+            MotionLimit::Slowed { speed_ratio, .. } => {
+                assert_relative_eq!(speed_ratio, 0.5, epsilon = 0.0001)
+            }
+            limit => panic!("Unexpected limit {:?}", limit),
+        }
+    }
+}
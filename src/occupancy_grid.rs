@@ -0,0 +1,279 @@
+use std::time::Instant;
+
+use nalgebra::{Isometry2, Point2};
+
+use crate::{
+    configuration::OccupancyGridConfig,
+    foxglove,
+    ik_controller::{leg_positions::proto_timestamp_now, odometry::ODOMETRY_FRAME_ID},
+    lidar::LIDAR_OFFSET_X,
+};
+
+/// Log odds added to a cell with a scan point in it
+const HIT_LOG_ODDS: f32 = 0.85;
+/// Log odds added to a cell a scan ray passed through
+const MISS_LOG_ODDS: f32 = -0.4;
+const MAX_LOG_ODDS: f32 = 4.0;
+
+/// Rolling occupancy grid in odometry frame centered on the robot
+pub struct OccupancyGrid {
+    config: OccupancyGridConfig,
+    /// Log odds of each cell being occupied. Rows are along the y axis
+    cells: Vec<f32>,
+    /// Position of cell (0, 0) in cells
+    origin: (i64, i64),
+    last_decay: Instant,
+}
+
+impl OccupancyGrid {
+    pub fn new(config: OccupancyGridConfig) -> Self {
+        let size = config.size_cells;
+        let origin = (-(size as i64) / 2, -(size as i64) / 2);
+        Self {
+            config,
+            cells: vec![0.0; size * size],
+            origin,
+            last_decay: Instant::now(),
+        }
+    }
+
+    /// Log odds of cell containing point in odometry frame
+    pub fn log_odds_at(&self, point: &Point2<f32>) -> Option<f32> {
+        let cell = self.world_to_cell(point);
+        self.index(cell).map(|index| self.cells[index])
+    }
+
+    /// Integrate scan points in body frame taken at given body pose in odometry frame
+    pub fn integrate_scan(&mut self, points: &[Point2<f32>], body_pose: &Isometry2<f32>) {
+        self.decay();
+        self.recenter(&body_pose.translation.vector.into());
+
+        let sensor = self.world_to_cell(&(body_pose * Point2::new(LIDAR_OFFSET_X, 0.0)));
+        // no ray has to be longer than the grid to cross it
+        let max_ray_cells = self.config.size_cells as i64;
+        for point in points {
+            let end = self.world_to_cell(&(body_pose * point));
+            let (end, reached_hit) = clip_ray(sensor, end, max_ray_cells);
+            let ray = bresenham_line(sensor, end);
+            let (hit, free) = ray.split_last().expect("Ray contains at least one cell");
+            for cell in free {
+                self.add_log_odds(*cell, MISS_LOG_ODDS);
+            }
+            // clipped ray ends in free space
+            let hit_log_odds = if reached_hit {
+                HIT_LOG_ODDS
+            } else {
+                MISS_LOG_ODDS
+            };
+            self.add_log_odds(*hit, hit_log_odds);
+        }
+    }
+
+    pub fn to_foxglove_grid(&self) -> foxglove::Grid {
+        let size = self.config.size_cells;
+        let resolution = self.config.resolution_m as f64;
+        let data = self
+            .cells
+            .iter()
+            .map(|log_odds| {
+                let probability = 1.0 - 1.0 / (1.0 + log_odds.exp());
+                (probability * 100.0).round() as u8
+            })
+            .collect();
+        foxglove::Grid {
+            timestamp: Some(proto_timestamp_now()),
+            frame_id: ODOMETRY_FRAME_ID.to_string(),
+            pose: Some(foxglove::Pose {
+                position: Some(foxglove::Vector3 {
+                    x: self.origin.0 as f64 * resolution,
+                    y: self.origin.1 as f64 * resolution,
+                    z: 0.0,
+                }),
+                orientation: Some(foxglove::Quaternion {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                    w: 1.0,
+                }),
+            }),
+            column_count: size as u32,
+            cell_size: Some(foxglove::Vector2 {
+                x: resolution,
+                y: resolution,
+            }),
+            row_stride: size as u32,
+            cell_stride: 1,
+            fields: vec![foxglove::PackedElementField {
+                name: "occupancy".to_string(),
+                offset: 0,
+                r#type: foxglove::packed_element_field::NumericType::Uint8 as i32,
+            }],
+            data,
+        }
+    }
+
+    fn decay(&mut self) {
+        let elapsed = self.last_decay.elapsed().as_secs_f32();
+        self.last_decay = Instant::now();
+        let factor = (-self.config.decay_per_second * elapsed).exp();
+        self.cells.iter_mut().for_each(|cell| *cell *= factor);
+    }
+
+    /// Shift grid so that position is in the middle
+    fn recenter(&mut self, position: &Point2<f32>) {
+        let size = self.config.size_cells as i64;
+        let center = self.world_to_cell(position);
+        let origin = (center.0 - size / 2, center.1 - size / 2);
+        if origin == self.origin {
+            return;
+        }
+        let mut cells = vec![0.0; self.cells.len()];
+        for row in 0..size {
+            for column in 0..size {
+                let old_cell = (origin.0 + column, origin.1 + row);
+                if let Some(index) = self.index(old_cell) {
+                    cells[(row * size + column) as usize] = self.cells[index];
+                }
+            }
+        }
+        self.cells = cells;
+        self.origin = origin;
+    }
+
+    fn world_to_cell(&self, point: &Point2<f32>) -> (i64, i64) {
+        (
+            (point.x / self.config.resolution_m).floor() as i64,
+            (point.y / self.config.resolution_m).floor() as i64,
+        )
+    }
+
+    fn index(&self, cell: (i64, i64)) -> Option<usize> {
+        let size = self.config.size_cells as i64;
+        let column = cell.0 - self.origin.0;
+        let row = cell.1 - self.origin.1;
+        if (0..size).contains(&column) && (0..size).contains(&row) {
+            Some((row * size + column) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn add_log_odds(&mut self, cell: (i64, i64), log_odds: f32) {
+        if let Some(index) = self.index(cell) {
+            self.cells[index] = (self.cells[index] + log_odds).clamp(-MAX_LOG_ODDS, MAX_LOG_ODDS);
+        }
+    }
+}
+
+/// Shorten ray to at most max_cells steps
+///
+/// Returns the new end and whether it's still the original end
+fn clip_ray(start: (i64, i64), end: (i64, i64), max_cells: i64) -> ((i64, i64), bool) {
+    let dx = end.0 - start.0;
+    let dy = end.1 - start.1;
+    let length = dx.abs().max(dy.abs());
+    if length <= max_cells {
+        return (end, true);
+    }
+    let scale = max_cells as f64 / length as f64;
+    let clipped = (
+        start.0 + (dx as f64 * scale).round() as i64,
+        start.1 + (dy as f64 * scale).round() as i64,
+    );
+    (clipped, false)
+}
+
+/// Cells on line between start and end including both
+fn bresenham_line(start: (i64, i64), end: (i64, i64)) -> Vec<(i64, i64)> {
+    let dx = (end.0 - start.0).abs();
+    let dy = -(end.1 - start.1).abs();
+    let step_x = if start.0 < end.0 { 1 } else { -1 };
+    let step_y = if start.1 < end.1 { 1 } else { -1 };
+    let mut error = dx + dy;
+    let (mut x, mut y) = start;
+    let mut cells = vec![];
+    loop {
+        cells.push((x, y));
+        if (x, y) == end {
+            return cells;
+        }
+        let double_error = 2 * error;
+        if double_error >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if double_error <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector2;
+
+    fn test_config() -> OccupancyGridConfig {
+        OccupancyGridConfig {
+            decay_per_second: 0.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn bresenham_includes_both_ends() {
+        let line = bresenham_line((0, 0), (3, -2));
+        assert_eq!(line.first(), Some(&(0, 0)));
+        assert_eq!(line.last(), Some(&(3, -2)));
+        assert_eq!(line.len(), 4);
+    }
+
+    #[test]
+    fn scan_marks_hit_occupied_and_ray_free() {
+        let mut grid = OccupancyGrid::new(test_config());
+        grid.integrate_scan(&[Point2::new(1.0, 0.0)], &Isometry2::identity());
+        assert!(grid.log_odds_at(&Point2::new(1.0, 0.0)).unwrap() > 0.0);
+        assert!(grid.log_odds_at(&Point2::new(0.5, 0.0)).unwrap() < 0.0);
+        assert_eq!(grid.log_odds_at(&Point2::new(0.0, 1.0)), Some(0.0));
+    }
+
+    #[test]
+    fn far_scan_point_ray_is_clipped() {
+        let mut grid = OccupancyGrid::new(test_config());
+        grid.integrate_scan(&[Point2::new(1.0e9, 0.0)], &Isometry2::identity());
+        assert!(grid.log_odds_at(&Point2::new(1.0, 0.0)).unwrap() < 0.0);
+
+        let size = test_config().size_cells as i64;
+        let (end, reached_hit) = clip_ray((0, 0), (size * 10, -size * 5), size);
+        assert!(!reached_hit);
+        assert_eq!(end, (size, -size / 2));
+    }
+
+    #[test]
+    fn scan_is_placed_using_odometry() {
+        let mut grid = OccupancyGrid::new(test_config());
+        let pose = Isometry2::new(Vector2::new(1.0, 1.0), std::f32::consts::FRAC_PI_2);
+        grid.integrate_scan(&[Point2::new(1.0, 0.0)], &pose);
+        assert!(grid.log_odds_at(&Point2::new(1.0, 2.0)).unwrap() > 0.0);
+    }
+
+    #[test]
+    fn grid_keeps_cells_when_recentering() {
+        let mut grid = OccupancyGrid::new(test_config());
+        grid.integrate_scan(&[Point2::new(1.0, 0.0)], &Isometry2::identity());
+        grid.integrate_scan(&[], &Isometry2::translation(0.5, 0.0));
+        assert!(grid.log_odds_at(&Point2::new(1.0, 0.0)).unwrap() > 0.0);
+    }
+
+    #[test]
+    fn foxglove_grid_has_cell_for_each_byte() {
+        let grid = OccupancyGrid::new(test_config());
+        let foxglove_grid = grid.to_foxglove_grid();
+        let size = test_config().size_cells;
+        assert_eq!(foxglove_grid.data.len(), size * size);
+        assert_eq!(foxglove_grid.column_count as usize, size);
+        // unknown cells are at 50%
+        assert_eq!(foxglove_grid.data[0], 50);
+    }
+}
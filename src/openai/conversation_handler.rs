@@ -16,15 +16,14 @@ use futures::StreamExt;
 use schemars::{gen::SchemaSettings, JsonSchema};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, sync::Arc};
-use tracing::{info, instrument};
+use tracing::{info, instrument, warn};
 
-use crate::{
-    ioc_container::IocContainer,
-    speech::{SpeechService, DEFAULT_ELEVEN_LABS_VOICE_NAME},
-};
+use super::memory::{ConversationMemory, SUMMARY_PROMPT};
+use crate::{ioc_container::IocContainer, speech::SpeechService};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct OpenAiHistory {
+    summary: Option<String>,
     history: Vec<ChatCompletionRequestMessage>,
     functions: Vec<FunctionObject>,
     tools: Vec<ChatCompletionTool>,
@@ -47,7 +46,11 @@ pub fn json_schema_for_func_args<T: ?Sized + JsonSchema>() -> serde_json::Value
 }
 
 pub enum OpenAiApiResponse {
-    AssistantResponse(String),
+    /// Streamed is false if the response still needs to be spoken
+    AssistantResponse {
+        text: String,
+        streamed: bool,
+    },
     FunctionCallWithNoResponse,
 }
 
@@ -62,7 +65,8 @@ pub trait ChatGptFunction: Send + Sync {
 
 #[derive(Clone)]
 pub struct ChatGptConversation {
-    history: Vec<ChatCompletionRequestMessage>,
+    system_prompt: ChatCompletionRequestMessage,
+    memory: ConversationMemory,
     tools: Vec<ChatCompletionTool>,
     temperature: Option<f32>,
     top_p: Option<f32>,
@@ -72,14 +76,15 @@ pub struct ChatGptConversation {
 
 impl ChatGptConversation {
     pub fn new(system_prompt: &str, model_name: &str) -> Self {
-        let history = vec![ChatCompletionRequestSystemMessageArgs::default()
+        let system_prompt = ChatCompletionRequestSystemMessageArgs::default()
             .content(system_prompt)
             .build()
             // can this fail?
             .expect("Failed to build system prompt message")
-            .into()];
+            .into();
         Self {
-            history,
+            system_prompt,
+            memory: ConversationMemory::default(),
             tools: vec![],
             temperature: None,
             top_p: None,
@@ -105,6 +110,101 @@ impl ChatGptConversation {
         Ok(())
     }
 
+    pub fn set_temperature(&mut self, temperature: Option<f32>) {
+        self.temperature = temperature;
+    }
+
+    pub fn memory(&self) -> &ConversationMemory {
+        &self.memory
+    }
+
+    pub fn set_memory(&mut self, memory: ConversationMemory) {
+        self.memory = memory;
+    }
+
+    pub fn reset_memory(&mut self) {
+        self.memory.clear();
+    }
+
+    /// Number of turns used to roll back a failed exchange
+    pub fn memory_checkpoint(&self) -> usize {
+        self.memory.turns.len()
+    }
+
+    /// Drop turns of a failed exchange so that dangling tool calls don't break later requests
+    pub fn rollback_memory(&mut self, checkpoint: usize) {
+        self.memory.turns.truncate(checkpoint);
+    }
+
+    /// Summarize oldest turns if memory is over budget
+    pub async fn compact_memory(
+        &mut self,
+        client: &Client<OpenAIConfig>,
+        max_tokens: usize,
+    ) -> anyhow::Result<()> {
+        if self.memory.estimated_tokens() <= max_tokens {
+            return Ok(());
+        }
+        let old_turns = self.memory.take_oldest_turns(max_tokens);
+        if old_turns.is_empty() {
+            return Ok(());
+        }
+        info!(
+            "Compacting {} messages into conversation summary",
+            old_turns.len()
+        );
+        let messages: Vec<ChatCompletionRequestMessage> = vec![
+            ChatCompletionRequestSystemMessageArgs::default()
+                .content(SUMMARY_PROMPT)
+                .build()?
+                .into(),
+            ChatCompletionRequestUserMessageArgs::default()
+                .content(self.memory.summary_request(&old_turns))
+                .build()?
+                .into(),
+        ];
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(self.model_name.clone())
+            .messages(messages)
+            .build()?;
+        let summary = match client.chat().create(request).await {
+            Ok(response) => response
+                .choices
+                .first()
+                .and_then(|choice| choice.message.content.clone())
+                .context("Summary response is empty"),
+            Err(error) => Err(error.into()),
+        };
+        match summary {
+            Ok(summary) => {
+                self.memory.summary = Some(summary);
+                Ok(())
+            }
+            Err(error) => {
+                // keep turns so that nothing is forgotten and retry after next command
+                let mut turns = old_turns;
+                turns.append(&mut self.memory.turns);
+                self.memory.turns = turns;
+                Err(error)
+            }
+        }
+    }
+
+    /// System prompt followed by summary and recent turns
+    fn request_messages(&self) -> anyhow::Result<Vec<ChatCompletionRequestMessage>> {
+        let mut messages = vec![self.system_prompt.clone()];
+        if let Some(summary) = &self.memory.summary {
+            messages.push(
+                ChatCompletionRequestSystemMessageArgs::default()
+                    .content(format!("Summary of earlier conversation: {}", summary))
+                    .build()?
+                    .into(),
+            );
+        }
+        messages.extend(self.memory.turns.iter().cloned());
+        Ok(messages)
+    }
+
     async fn call_function(&self, name: &str, args: &str) -> anyhow::Result<serde_json::Value> {
         info!("Calling function {:?} with args {:?}", name, args);
         let function = self
@@ -121,7 +221,7 @@ impl ChatGptConversation {
 
         request_builder
             .model(self.model_name.clone())
-            .messages(self.history.clone())
+            .messages(self.request_messages()?)
             .tools(self.tools.clone())
             .tool_choice(ChatCompletionToolChoiceOption::Auto);
 
@@ -150,7 +250,7 @@ impl ChatGptConversation {
                 .build()?
                 .into();
 
-            self.history.push(user_message);
+            self.memory.turns.push(user_message);
         }
 
         let request = self.build_request_message()?;
@@ -164,12 +264,18 @@ impl ChatGptConversation {
         let mut response_content_buffer = String::new();
 
         let mut streamer = if stream_content_response {
-            Some(
-                IocContainer::global_instance()
-                    .service::<SpeechService>()?
-                    .start_eleven_labs_voice_stream(DEFAULT_ELEVEN_LABS_VOICE_NAME)
-                    .await?,
-            )
+            match IocContainer::global_instance()
+                .service::<SpeechService>()?
+                .start_eleven_labs_voice_stream()
+                .await
+            {
+                Ok(streamer) => Some(streamer),
+                Err(error) => {
+                    // response gets spoken by fallback backends once it's complete
+                    warn!("Failed to start voice stream: {}", error);
+                    None
+                }
+            }
         } else {
             None
         };
@@ -239,7 +345,7 @@ impl ChatGptConversation {
                 .tool_calls(tool_calls.clone())
                 .build()?
                 .into();
-            self.history.push(tool_call_request);
+            self.memory.turns.push(tool_call_request);
         }
 
         for tool_call in tool_calls {
@@ -261,7 +367,7 @@ impl ChatGptConversation {
                 .build()
                 .context("Failed to build tool response")?
                 .into();
-            self.history.push(tool_response);
+            self.memory.turns.push(tool_response);
         }
 
         if !response_content_buffer.is_empty() {
@@ -270,10 +376,11 @@ impl ChatGptConversation {
                 .build()?
                 .into();
 
-            self.history.push(added_response);
-            return Ok(OpenAiApiResponse::AssistantResponse(
-                response_content_buffer,
-            ));
+            self.memory.turns.push(added_response);
+            return Ok(OpenAiApiResponse::AssistantResponse {
+                text: response_content_buffer,
+                streamed: streamer.is_some(),
+            });
         }
 
         Ok(OpenAiApiResponse::FunctionCallWithNoResponse)
@@ -281,7 +388,8 @@ impl ChatGptConversation {
 
     pub fn get_history(&self) -> String {
         let history = OpenAiHistory {
-            history: self.history.clone(),
+            summary: self.memory.summary.clone(),
+            history: self.memory.turns.clone(),
             functions: vec![],
             tools: self.tools.clone(),
             timestamp: chrono::Utc::now(),
@@ -289,3 +397,74 @@ impl ChatGptConversation {
         serde_json::to_string_pretty(&history).expect("Failed to serialize chat history")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openai::mock_server::MockOpenAiServer;
+
+    fn mock_client(server: &MockOpenAiServer) -> Client<OpenAIConfig> {
+        Client::with_config(
+            OpenAIConfig::new()
+                .with_api_key("test")
+                .with_api_base(&server.base_url),
+        )
+    }
+
+    #[tokio::test]
+    async fn configured_model_and_prompt_are_sent() {
+        let server = MockOpenAiServer::start("Hi there").await;
+        let client = mock_client(&server);
+        let mut conversation = ChatGptConversation::new("Be nice", "local-model");
+        conversation.set_temperature(Some(0.5));
+
+        let response = conversation
+            .next_message_stream(Some("Hello"), &client, false)
+            .await
+            .unwrap();
+        match response {
+            OpenAiApiResponse::AssistantResponse { text, streamed } => {
+                assert_eq!(text, "Hi there");
+                assert!(!streamed);
+            }
+            OpenAiApiResponse::FunctionCallWithNoResponse => panic!("Expected text response"),
+        }
+
+        let request = &server.requests()[0];
+        assert_eq!(request["model"], "local-model");
+        assert_eq!(request["temperature"], 0.5);
+        assert_eq!(request["messages"][0]["content"], "Be nice");
+        assert_eq!(request["messages"][1]["content"], "Hello");
+        assert_eq!(conversation.memory().turns.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn compaction_replaces_old_turns_with_summary() {
+        let server = MockOpenAiServer::start("User said hello twice").await;
+        let client = mock_client(&server);
+        let mut conversation = ChatGptConversation::new("Be nice", "local-model");
+        for _ in 0..2 {
+            conversation
+                .next_message_stream(Some("Hello"), &client, false)
+                .await
+                .unwrap();
+        }
+
+        conversation.compact_memory(&client, 0).await.unwrap();
+        assert_eq!(
+            conversation.memory().summary.as_deref(),
+            Some("User said hello twice")
+        );
+        assert_eq!(conversation.memory().turns.len(), 2);
+
+        conversation
+            .next_message_stream(Some("Hello"), &client, false)
+            .await
+            .unwrap();
+        let request = server.requests().pop().unwrap();
+        assert_eq!(
+            request["messages"][1]["content"],
+            "Summary of earlier conversation: User said hello twice"
+        );
+    }
+}
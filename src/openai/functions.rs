@@ -1,4 +1,15 @@
+use async_openai::{
+    config::OpenAIConfig,
+    types::{
+        ChatCompletionRequestMessage, ChatCompletionRequestMessageContentPart,
+        ChatCompletionRequestMessageContentPartImageArgs,
+        ChatCompletionRequestMessageContentPartTextArgs, ChatCompletionRequestUserMessageArgs,
+        CreateChatCompletionRequestArgs, ImageUrlArgs, ImageUrlDetail,
+    },
+    Client,
+};
 use async_trait::async_trait;
+use base64::Engine;
 use nalgebra::Vector2;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -10,13 +21,19 @@ use std::{
 use tracing::info;
 
 use crate::{
+    camera::CameraService,
+    face::FaceController,
     high_five::HighFiveServiceController,
     ioc_container::IocContainer,
     lidar::LidarServiceController,
+    monitoring::DiagnosticsService,
     motion_controller::{
+        choreography::ChoreographyLibrary,
         walking::{MoveCommand, DEFAULT_STEP_HEIGHT},
         BodyState, DanceMove, MotionControllerService,
     },
+    navigation::{NavigationGoal, NavigationService},
+    obstacle_guard::ObstacleGuard,
     zenoh_remotes::remote_controller::{MoveService, ScheduledCommand},
 };
 
@@ -63,12 +80,14 @@ impl ChatGptFunction for HopperBodyPoseFuncCallback {
             _ => (),
         }
 
-        IocContainer::global_instance()
+        let outcome = IocContainer::global_instance()
             .service::<MotionControllerService>()?
-            .set_body_state(hopper_body_pose_func.body_pose);
+            .set_body_state(hopper_body_pose_func.body_pose)
+            .await;
 
         let result = json!({
-            "success": true
+            "success": outcome.is_completed(),
+            "outcome": outcome,
         });
         Ok(result)
     }
@@ -77,7 +96,9 @@ impl ChatGptFunction for HopperBodyPoseFuncCallback {
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct HopperDanceFuncArgs {
     /// dance move to perform
-    pub dance_move: DanceMove,
+    pub dance_move: Option<DanceMove>,
+    /// name of custom choreography to perform instead of dance move
+    pub choreography: Option<String>,
 }
 
 pub struct HopperDanceFuncCallback;
@@ -89,7 +110,19 @@ impl ChatGptFunction for HopperDanceFuncCallback {
     }
 
     fn description(&self) -> String {
-        "perform a dance move with your body. Can be useful to express emotion or react to what user is saying.".to_string()
+        let description = "perform a dance move with your body. Can be useful to express emotion or react to what user is saying.";
+        let choreographies = IocContainer::global_instance()
+            .service::<ChoreographyLibrary>()
+            .map(|library| library.describe())
+            .unwrap_or_default();
+        if choreographies.is_empty() {
+            description.to_string()
+        } else {
+            format!(
+                "{} Available custom choreographies: {}",
+                description, choreographies
+            )
+        }
     }
 
     fn parameters_schema(&self) -> serde_json::Value {
@@ -98,10 +131,26 @@ impl ChatGptFunction for HopperDanceFuncCallback {
 
     async fn call(&self, args: &str) -> anyhow::Result<serde_json::Value> {
         let hopper_dance_move: HopperDanceFuncArgs = serde_json::from_str(args)?;
-
-        IocContainer::global_instance()
-            .service::<MotionControllerService>()?
-            .start_dance_sequence(hopper_dance_move.dance_move);
+        let motion_controller =
+            IocContainer::global_instance().service::<MotionControllerService>()?;
+
+        if let Some(name) = hopper_dance_move.choreography {
+            let choreography = IocContainer::global_instance()
+                .service::<ChoreographyLibrary>()?
+                .get(&name);
+            match choreography {
+                Some(choreography) => motion_controller.start_choreography(choreography),
+                None => {
+                    return Ok(json!({
+                        "success": false,
+                        "error": format!("Unknown choreography {}", name)
+                    }))
+                }
+            }
+        } else {
+            motion_controller
+                .start_dance_sequence(hopper_dance_move.dance_move.unwrap_or(DanceMove::Random));
+        }
 
         let result = json!({
             "success": true
@@ -139,12 +188,13 @@ impl ChatGptFunction for HopperHighFiveFuncCallback {
             .service::<HighFiveServiceController>()?
             .set_active(high_five_args.enable_high_fives);
 
-        IocContainer::global_instance()
-            .service::<LidarServiceController>()?
-            .set_active(high_five_args.enable_high_fives);
+        let lidar = IocContainer::global_instance().service::<LidarServiceController>()?;
+        lidar.set_active(high_five_args.enable_high_fives);
 
+        // lidar stays on while obstacle guard is enabled
         let result = json!({
-            "high_fives_enabled": high_five_args.enable_high_fives
+            "high_fives_enabled": high_five_args.enable_high_fives,
+            "lidar_active": lidar.is_active(),
         });
         Ok(result)
     }
@@ -364,3 +414,298 @@ impl ChatGptFunction for MoveCommandFunction {
         Ok(result)
     }
 }
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct NavigateArgs {
+    /// distance to walk forward in meters. Negative is backwards
+    #[serde(default)]
+    pub x_meters: f32,
+    /// distance to walk to the left in meters. Negative is right
+    #[serde(default)]
+    pub y_meters: f32,
+    /// rotation in degrees, left is positive, right is negative
+    #[serde(default)]
+    pub rotation_degrees: f32,
+}
+
+pub struct NavigateFunction;
+
+#[async_trait]
+impl ChatGptFunction for NavigateFunction {
+    fn name(&self) -> String {
+        "navigate".to_string()
+    }
+
+    fn description(&self) -> String {
+        "Walk to a position relative to the current one and turn. Waits until the goal is reached"
+            .to_string()
+    }
+
+    fn parameters_schema(&self) -> serde_json::Value {
+        json_schema_for_func_args::<NavigateArgs>()
+    }
+
+    async fn call(&self, args: &str) -> anyhow::Result<serde_json::Value> {
+        let navigate_args: NavigateArgs = serde_json::from_str(args)?;
+        info!(?navigate_args, "processing navigation command");
+
+        const MAX_DISTANCE: f32 = 3.0;
+        let goal = NavigationGoal {
+            x: navigate_args.x_meters.clamp(-MAX_DISTANCE, MAX_DISTANCE),
+            y: navigate_args.y_meters.clamp(-MAX_DISTANCE, MAX_DISTANCE),
+            yaw: navigate_args.rotation_degrees.to_radians(),
+        };
+
+        let status = IocContainer::global_instance()
+            .service::<NavigationService>()?
+            .navigate(goal)
+            .await?;
+
+        Ok(serde_json::to_value(status)?)
+    }
+}
+
+/// Arguments of functions that only read state
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, Default)]
+pub struct NoArgs {}
+
+pub struct BodyStatusFunction;
+
+#[async_trait]
+impl ChatGptFunction for BodyStatusFunction {
+    fn name(&self) -> String {
+        "get_body_status".to_string()
+    }
+
+    fn description(&self) -> String {
+        "Get current body pose (standing, grounded or folded) and battery voltage of the robot"
+            .to_string()
+    }
+
+    fn parameters_schema(&self) -> serde_json::Value {
+        json_schema_for_func_args::<NoArgs>()
+    }
+
+    async fn call(&self, _args: &str) -> anyhow::Result<serde_json::Value> {
+        let status = IocContainer::global_instance()
+            .service::<MotionControllerService>()?
+            .status();
+        Ok(serde_json::to_value(status)?)
+    }
+}
+
+pub struct DiagnosticsFunction;
+
+#[async_trait]
+impl ChatGptFunction for DiagnosticsFunction {
+    fn name(&self) -> String {
+        "get_diagnostics".to_string()
+    }
+
+    fn description(&self) -> String {
+        "Get latest system diagnostics of the robot such as CPU temperature in celsius".to_string()
+    }
+
+    fn parameters_schema(&self) -> serde_json::Value {
+        json_schema_for_func_args::<NoArgs>()
+    }
+
+    async fn call(&self, _args: &str) -> anyhow::Result<serde_json::Value> {
+        let diagnostics = IocContainer::global_instance()
+            .service::<DiagnosticsService>()?
+            .latest_json()?;
+        Ok(diagnostics.unwrap_or_else(|| json!({"error": "No diagnostics measured yet"})))
+    }
+}
+
+pub struct NearestObstacleFunction;
+
+#[async_trait]
+impl ChatGptFunction for NearestObstacleFunction {
+    fn name(&self) -> String {
+        "get_nearest_obstacle".to_string()
+    }
+
+    fn description(&self) -> String {
+        "Get distance and direction of the closest object seen by the lidar. \
+Bearing is in degrees counter clockwise from forward"
+            .to_string()
+    }
+
+    fn parameters_schema(&self) -> serde_json::Value {
+        json_schema_for_func_args::<NoArgs>()
+    }
+
+    async fn call(&self, _args: &str) -> anyhow::Result<serde_json::Value> {
+        let nearest_obstacle = IocContainer::global_instance()
+            .service::<ObstacleGuard>()?
+            .nearest_obstacle();
+        match nearest_obstacle {
+            Some(nearest_obstacle) => Ok(serde_json::to_value(nearest_obstacle)?),
+            None => Ok(json!({"error": "No recent lidar scan. Lidar may be turned off"})),
+        }
+    }
+}
+
+pub struct FaceAnimationFunction;
+
+#[async_trait]
+impl ChatGptFunction for FaceAnimationFunction {
+    fn name(&self) -> String {
+        "get_face_animation".to_string()
+    }
+
+    fn description(&self) -> String {
+        "Get animation currently shown on the face LEDs of the robot".to_string()
+    }
+
+    fn parameters_schema(&self) -> serde_json::Value {
+        json_schema_for_func_args::<NoArgs>()
+    }
+
+    async fn call(&self, _args: &str) -> anyhow::Result<serde_json::Value> {
+        let animation = IocContainer::global_instance()
+            .service::<FaceController>()?
+            .get_last_animation();
+        let animation = animation
+            .map(|animation| format!("{:?}", animation))
+            .unwrap_or_else(|| "Off".to_string());
+        Ok(json!({ "animation": animation }))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
+pub struct CameraVisionArgs {
+    /// What to look for or what to describe. Describes the whole view if empty
+    #[serde(default)]
+    pub question: Option<String>,
+}
+
+/// Describes the latest camera frame using a vision model
+pub struct CameraVisionFunction {
+    pub camera_service: CameraService,
+    pub client: Client<OpenAIConfig>,
+    pub model: String,
+}
+
+const CAMERA_FRAME_MAX_AGE: Duration = Duration::from_secs(5);
+
+#[async_trait]
+impl ChatGptFunction for CameraVisionFunction {
+    fn name(&self) -> String {
+        "look_with_camera".to_string()
+    }
+
+    fn description(&self) -> String {
+        "Look through the camera on the front of the robot and get a description of what you see"
+            .to_string()
+    }
+
+    fn parameters_schema(&self) -> serde_json::Value {
+        json_schema_for_func_args::<CameraVisionArgs>()
+    }
+
+    async fn call(&self, args: &str) -> anyhow::Result<serde_json::Value> {
+        let args: CameraVisionArgs = serde_json::from_str(args)?;
+        info!(?args, "looking with camera");
+
+        let frame = match self.camera_service.latest_frame(CAMERA_FRAME_MAX_AGE) {
+            Some(frame) => frame,
+            None => return Ok(json!({"error": "No recent camera frame available"})),
+        };
+        let image_url = format!(
+            "data:image/{};base64,{}",
+            frame.format,
+            base64::engine::general_purpose::STANDARD.encode(&frame.data)
+        );
+        let question = args
+            .question
+            .filter(|question| !question.trim().is_empty())
+            .unwrap_or_else(|| "Describe what you see.".to_string());
+
+        let content: Vec<ChatCompletionRequestMessageContentPart> = vec![
+            ChatCompletionRequestMessageContentPartTextArgs::default()
+                .text(format!(
+                    "This image is from the camera of a small hexapod robot. Answer briefly. {}",
+                    question
+                ))
+                .build()?
+                .into(),
+            ChatCompletionRequestMessageContentPartImageArgs::default()
+                .image_url(
+                    ImageUrlArgs::default()
+                        .url(image_url)
+                        .detail(ImageUrlDetail::Low)
+                        .build()?,
+                )
+                .build()?
+                .into(),
+        ];
+        let messages: Vec<ChatCompletionRequestMessage> =
+            vec![ChatCompletionRequestUserMessageArgs::default()
+                .content(content)
+                .build()?
+                .into()];
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(self.model.clone())
+            .messages(messages)
+            .build()?;
+
+        let response = self.client.chat().create(request).await?;
+        let description = response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.clone())
+            .unwrap_or_default();
+        Ok(json!({ "description": description }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{foxglove::CompressedImage, openai::mock_server::MockOpenAiServer};
+
+    #[tokio::test]
+    async fn camera_frame_is_sent_to_vision_model() {
+        let server = MockOpenAiServer::start("A cat on a sofa").await;
+        let camera_service = CameraService::default();
+        camera_service.update_frame(CompressedImage {
+            timestamp: None,
+            frame_id: "hopper_camera".to_string(),
+            data: vec![1, 2, 3],
+            format: "jpeg".to_string(),
+        });
+        let function = CameraVisionFunction {
+            camera_service,
+            client: Client::with_config(
+                OpenAIConfig::new()
+                    .with_api_key("test")
+                    .with_api_base(&server.base_url),
+            ),
+            model: "vision-model".to_string(),
+        };
+
+        let result = function.call("{}").await.unwrap();
+        assert_eq!(result["description"], "A cat on a sofa");
+
+        let request = &server.requests()[0];
+        assert_eq!(request["model"], "vision-model");
+        assert_eq!(
+            request["messages"][0]["content"][1]["image_url"]["url"],
+            "data:image/jpeg;base64,AQID"
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_frame_is_reported() {
+        let function = CameraVisionFunction {
+            camera_service: CameraService::default(),
+            client: Client::new(),
+            model: "vision-model".to_string(),
+        };
+        let result = function.call("{}").await.unwrap();
+        assert!(result["error"].is_string());
+    }
+}
@@ -0,0 +1,258 @@
+use async_openai::types::{
+    ChatCompletionRequestAssistantMessage, ChatCompletionRequestFunctionMessage,
+    ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage,
+    ChatCompletionRequestToolMessage, ChatCompletionRequestUserMessage,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::path::Path;
+
+/// Rough token estimate without a tokenizer
+const CHARACTERS_PER_TOKEN: usize = 4;
+
+pub const SUMMARY_PROMPT: &str =
+    "You maintain the long term memory of Hopper, a hexapod pet robot. \
+Merge the previous summary with the new conversation excerpt into a single short summary. \
+Keep names, preferences, promises and facts about people and places. \
+Drop small talk. Answer with the summary only.";
+
+/// Conversation that survives between commands and restarts
+///
+/// Older turns are compacted into the summary once the memory grows past its token budget
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ConversationMemory {
+    pub summary: Option<String>,
+    #[serde(with = "stored_turns")]
+    pub turns: Vec<ChatCompletionRequestMessage>,
+}
+
+/// Message as stored on disk
+///
+/// Request messages are untagged so any of them reads back as a system message.
+/// Tagging them keeps the role and fields such as tool call ids.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+enum StoredMessage {
+    System(ChatCompletionRequestSystemMessage),
+    User(ChatCompletionRequestUserMessage),
+    Assistant(ChatCompletionRequestAssistantMessage),
+    Tool(ChatCompletionRequestToolMessage),
+    Function(ChatCompletionRequestFunctionMessage),
+}
+
+impl From<&ChatCompletionRequestMessage> for StoredMessage {
+    fn from(message: &ChatCompletionRequestMessage) -> Self {
+        match message.clone() {
+            ChatCompletionRequestMessage::System(message) => StoredMessage::System(message),
+            ChatCompletionRequestMessage::User(message) => StoredMessage::User(message),
+            ChatCompletionRequestMessage::Assistant(message) => StoredMessage::Assistant(message),
+            ChatCompletionRequestMessage::Tool(message) => StoredMessage::Tool(message),
+            ChatCompletionRequestMessage::Function(message) => StoredMessage::Function(message),
+        }
+    }
+}
+
+impl From<StoredMessage> for ChatCompletionRequestMessage {
+    fn from(message: StoredMessage) -> Self {
+        match message {
+            StoredMessage::System(message) => ChatCompletionRequestMessage::System(message),
+            StoredMessage::User(message) => ChatCompletionRequestMessage::User(message),
+            StoredMessage::Assistant(message) => ChatCompletionRequestMessage::Assistant(message),
+            StoredMessage::Tool(message) => ChatCompletionRequestMessage::Tool(message),
+            StoredMessage::Function(message) => ChatCompletionRequestMessage::Function(message),
+        }
+    }
+}
+
+mod stored_turns {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        turns: &[ChatCompletionRequestMessage],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(turns.iter().map(StoredMessage::from))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<ChatCompletionRequestMessage>, D::Error> {
+        let turns = Vec::<StoredMessage>::deserialize(deserializer)?;
+        Ok(turns.into_iter().map(Into::into).collect())
+    }
+}
+
+fn estimate_tokens(message: &ChatCompletionRequestMessage) -> usize {
+    serde_json::to_string(message)
+        .map(|text| text.len() / CHARACTERS_PER_TOKEN)
+        .unwrap_or_default()
+}
+
+fn is_user_message(message: &ChatCompletionRequestMessage) -> bool {
+    matches!(message, ChatCompletionRequestMessage::User(_))
+}
+
+impl ConversationMemory {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // write to a temporary file first so that a crash doesn't corrupt memory
+        let temporary_path = path.with_extension("tmp");
+        std::fs::write(&temporary_path, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&temporary_path, path)?;
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.summary = None;
+        self.turns.clear();
+    }
+
+    pub fn estimated_tokens(&self) -> usize {
+        let summary_tokens = self
+            .summary
+            .as_ref()
+            .map(|summary| summary.len() / CHARACTERS_PER_TOKEN)
+            .unwrap_or_default();
+        summary_tokens + self.turns.iter().map(estimate_tokens).sum::<usize>()
+    }
+
+    /// Index that splits off the oldest turns so that the rest fits within budget
+    ///
+    /// Splits only before user messages so that tool calls stay with their responses.
+    /// The latest turn is always kept.
+    fn compaction_split(&self, max_tokens: usize) -> usize {
+        let mut tokens: usize = self.turns.iter().map(estimate_tokens).sum();
+        let mut split = 0;
+        for (index, message) in self.turns.iter().enumerate().skip(1) {
+            if tokens <= max_tokens {
+                break;
+            }
+            if is_user_message(message) {
+                tokens -= self.turns[split..index]
+                    .iter()
+                    .map(estimate_tokens)
+                    .sum::<usize>();
+                split = index;
+            }
+        }
+        split
+    }
+
+    /// Remove turns that don't fit within budget and return them for summarizing
+    pub fn take_oldest_turns(&mut self, max_tokens: usize) -> Vec<ChatCompletionRequestMessage> {
+        let split = self.compaction_split(max_tokens);
+        self.turns.drain(..split).collect()
+    }
+
+    /// Text sent to OpenAI to produce the new summary
+    pub fn summary_request(&self, old_turns: &[ChatCompletionRequestMessage]) -> String {
+        let transcript: Vec<_> = old_turns
+            .iter()
+            .filter_map(|message| serde_json::to_string(message).ok())
+            .collect();
+        format!(
+            "Previous summary:\n{}\n\nConversation excerpt:\n{}",
+            self.summary.as_deref().unwrap_or("None"),
+            transcript.join("\n")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_openai::types::{
+        ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessageArgs,
+        ChatCompletionRequestToolMessageArgs, ChatCompletionRequestUserMessageArgs,
+        ChatCompletionToolType, FunctionCall,
+    };
+
+    fn exchange(memory: &mut ConversationMemory, text: &str) {
+        memory.turns.push(
+            ChatCompletionRequestUserMessageArgs::default()
+                .content(text)
+                .build()
+                .unwrap()
+                .into(),
+        );
+        memory.turns.push(
+            ChatCompletionRequestAssistantMessageArgs::default()
+                .content(text)
+                .build()
+                .unwrap()
+                .into(),
+        );
+    }
+
+    #[test]
+    fn memory_within_budget_is_kept() {
+        let mut memory = ConversationMemory::default();
+        exchange(&mut memory, "hello");
+        exchange(&mut memory, "how are you");
+        assert!(memory.take_oldest_turns(10_000).is_empty());
+        assert_eq!(memory.turns.len(), 4);
+    }
+
+    #[test]
+    fn compaction_removes_whole_turns_and_keeps_latest() {
+        let mut memory = ConversationMemory::default();
+        for _ in 0..3 {
+            exchange(&mut memory, &"a".repeat(400));
+        }
+        let old_turns = memory.take_oldest_turns(0);
+        assert_eq!(old_turns.len(), 4);
+        assert_eq!(memory.turns.len(), 2);
+        assert!(is_user_message(&old_turns[0]));
+        assert!(is_user_message(&memory.turns[0]));
+    }
+
+    #[test]
+    fn memory_roundtrips_through_file() {
+        let dir = tempdir::TempDir::new("memory").unwrap();
+        let path = dir.path().join("nested/memory.json");
+        let mut memory = ConversationMemory {
+            summary: Some("David likes dancing".to_string()),
+            turns: vec![],
+        };
+        exchange(&mut memory, "hello");
+        memory.turns.push(
+            ChatCompletionRequestAssistantMessageArgs::default()
+                .tool_calls(vec![ChatCompletionMessageToolCall {
+                    id: "call_1".to_string(),
+                    r#type: ChatCompletionToolType::Function,
+                    function: FunctionCall {
+                        name: "dance".to_string(),
+                        arguments: "{}".to_string(),
+                    },
+                }])
+                .build()
+                .unwrap()
+                .into(),
+        );
+        memory.turns.push(
+            ChatCompletionRequestToolMessageArgs::default()
+                .content("done")
+                .tool_call_id("call_1")
+                .build()
+                .unwrap()
+                .into(),
+        );
+        exchange(&mut memory, "thanks");
+        memory.save(&path).unwrap();
+
+        let loaded = ConversationMemory::load(&path).unwrap();
+        assert_eq!(loaded.summary, memory.summary);
+        assert_eq!(loaded.turns, memory.turns);
+        assert!(is_user_message(&loaded.turns[0]));
+        assert!(matches!(
+            loaded.turns[3],
+            ChatCompletionRequestMessage::Tool(_)
+        ));
+    }
+}
@@ -0,0 +1,124 @@
+//! Minimal OpenAI compatible server for tests
+
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+pub struct MockOpenAiServer {
+    pub base_url: String,
+    requests: Arc<Mutex<Vec<serde_json::Value>>>,
+}
+
+impl MockOpenAiServer {
+    /// Answer every chat completion with given assistant message
+    pub async fn start(reply: &str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let reply = reply.to_owned();
+        tokio::spawn({
+            let requests = requests.clone();
+            async move {
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    let body = match read_request_body(&mut stream).await {
+                        Some(body) => body,
+                        None => continue,
+                    };
+                    let request: serde_json::Value =
+                        serde_json::from_slice(&body).unwrap_or_default();
+                    let response = if request["stream"].as_bool().unwrap_or(false) {
+                        stream_response(&reply)
+                    } else {
+                        completion_response(&reply)
+                    };
+                    requests.lock().unwrap().push(request);
+                    _ = stream.write_all(response.as_bytes()).await;
+                    _ = stream.shutdown().await;
+                }
+            }
+        });
+        Self { base_url, requests }
+    }
+
+    /// Bodies of received requests
+    pub fn requests(&self) -> Vec<serde_json::Value> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn read_request_body(stream: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut buffer = vec![];
+    let mut chunk = [0; 4096];
+    loop {
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(header_end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            let headers = String::from_utf8_lossy(&buffer[..header_end]).to_lowercase();
+            let content_length = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .and_then(|value| value.trim().parse::<usize>().ok())
+                .unwrap_or_default();
+            let body_start = header_end + 4;
+            if buffer.len() >= body_start + content_length {
+                return Some(buffer[body_start..body_start + content_length].to_vec());
+            }
+        }
+    }
+}
+
+fn http_response(content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+fn stream_response(reply: &str) -> String {
+    let content = json!({
+        "id": "mock",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "mock",
+        "choices": [{
+            "index": 0,
+            "delta": {"role": "assistant", "content": reply},
+            "finish_reason": null
+        }]
+    });
+    let finish = json!({
+        "id": "mock",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "mock",
+        "choices": [{"index": 0, "delta": {}, "finish_reason": "stop"}]
+    });
+    http_response(
+        "text/event-stream",
+        &format!("data: {}\n\ndata: {}\n\ndata: [DONE]\n\n", content, finish),
+    )
+}
+
+fn completion_response(reply: &str) -> String {
+    let body = json!({
+        "id": "mock",
+        "object": "chat.completion",
+        "created": 0,
+        "model": "mock",
+        "choices": [{
+            "index": 0,
+            "message": {"role": "assistant", "content": reply},
+            "finish_reason": "stop"
+        }],
+        "usage": {"prompt_tokens": 0, "completion_tokens": 0, "total_tokens": 0}
+    });
+    http_response("application/json", &body.to_string())
+}
@@ -1,27 +1,38 @@
 mod conversation_handler;
 mod events;
 mod functions;
+mod memory;
+#[cfg(test)]
+mod mock_server;
 
 use async_openai::{config::OpenAIConfig, Client};
-use std::sync::{atomic::AtomicU8, Arc, Mutex};
+use std::{
+    path::Path,
+    sync::{atomic::AtomicU8, Arc, Mutex},
+};
 use tokio::select;
 use tracing::info;
 use zenoh::prelude::r#async::*;
 
 use crate::{
+    camera::CameraService,
+    configuration::{ConversationMemoryConfig, HopperOpenAiConfig},
     error::HopperError,
     face::animations::Animation,
     ioc_container::IocContainer,
     openai::conversation_handler::OpenAiApiResponse,
     speech::SpeechService,
-    zenoh_remotes::topic_consts::{HOPPER_OPENAI_COMMAND_SUBSCRIBER, OPENAI_DIAGNOSTICS_HISTORY},
+    zenoh_remotes::topic_consts::{
+        HOPPER_OPENAI_COMMAND_SUBSCRIBER, OPENAI_DIAGNOSTICS_HISTORY,
+        OPENAI_MEMORY_COMMAND_SUBSCRIBER,
+    },
 };
 
-use self::{conversation_handler::ChatGptConversation, events::*, functions::*};
-
-
-const MODEL_NAME: &str = "gpt-4o-2024-05-13";
+use self::{
+    conversation_handler::ChatGptConversation, events::*, functions::*, memory::ConversationMemory,
+};
 
+/// Personality used unless config overrides it
 const SYSTEM_PROMPT: &str = "You are a hexapod pet robot. Your name is Hopper. \
 You can perform physical actions such as dance, sit, stand up by calling functions. \
 You have an extremely sarcastic personality. While you comply to all requests to the best of your abilities \
@@ -49,14 +60,34 @@ impl OpenAiService {
 }
 
 pub async fn start_openai_controller(
-    openai_api_key: &str,
-    topic_prefix: &str,
+    config: &HopperOpenAiConfig,
     zenoh_session: Arc<zenoh::Session>,
 ) -> anyhow::Result<OpenAiService> {
-    let config = OpenAIConfig::new().with_api_key(openai_api_key);
-    let client = Client::with_config(config);
-
-    let mut chat_gpt_conversation = ChatGptConversation::new(SYSTEM_PROMPT, MODEL_NAME);
+    let topic_prefix = &config.wakeword_topic_prefix;
+    let memory_config = config.memory.clone();
+    let client = create_client(config);
+
+    let system_prompt = config.system_prompt.as_deref().unwrap_or(SYSTEM_PROMPT);
+    let mut chat_gpt_conversation = ChatGptConversation::new(system_prompt, &config.model);
+    chat_gpt_conversation.set_temperature(config.temperature);
+
+    if let Some(path) = &memory_config.path {
+        let path = Path::new(path);
+        if path.exists() {
+            match ConversationMemory::load(path) {
+                Ok(memory) => {
+                    info!(
+                        "Restored conversation memory with {} messages",
+                        memory.turns.len()
+                    );
+                    chat_gpt_conversation.set_memory(memory);
+                }
+                Err(error) => {
+                    tracing::error!("Failed to load conversation memory {:?}: {}", path, error)
+                }
+            }
+        }
+    }
 
     chat_gpt_conversation.add_function(Arc::new(HopperBodyPoseFuncCallback {
         zenoh_session: zenoh_session.clone(),
@@ -67,9 +98,33 @@ pub async fn start_openai_controller(
     chat_gpt_conversation.add_function(Arc::new(HopperHighFiveFuncCallback))?;
 
     chat_gpt_conversation.add_function(Arc::new(FaceDisplayFuncCallback))?;
-    
+
     chat_gpt_conversation.add_function(Arc::new(MoveCommandFunction))?;
 
+    chat_gpt_conversation.add_function(Arc::new(NavigateFunction))?;
+
+    chat_gpt_conversation.add_function(Arc::new(BodyStatusFunction))?;
+
+    chat_gpt_conversation.add_function(Arc::new(DiagnosticsFunction))?;
+
+    chat_gpt_conversation.add_function(Arc::new(NearestObstacleFunction))?;
+
+    chat_gpt_conversation.add_function(Arc::new(FaceAnimationFunction))?;
+
+    match IocContainer::global_instance().service::<CameraService>() {
+        Ok(camera_service) => {
+            chat_gpt_conversation.add_function(Arc::new(CameraVisionFunction {
+                camera_service: (*camera_service).clone(),
+                client: client.clone(),
+                model: config
+                    .vision_model
+                    .clone()
+                    .unwrap_or_else(|| config.model.clone()),
+            }))?;
+        }
+        Err(error) => tracing::warn!("Camera not available for OpenAI vision: {}", error),
+    }
+
     let voice_provider_arc = Arc::new(Mutex::new(VoiceProvider::default()));
 
     chat_gpt_conversation.add_function(Arc::new(SwitchVoiceFuncCallback {
@@ -82,6 +137,12 @@ pub async fn start_openai_controller(
         .await
         .map_err(HopperError::ZenohError)?;
 
+    let memory_command_subscriber = zenoh_session
+        .declare_subscriber(OPENAI_MEMORY_COMMAND_SUBSCRIBER)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+
     let wake_word_transcript_subscriber = zenoh_session
         .declare_subscriber(format!("{topic_prefix}/event/transcript"))
         .res()
@@ -120,12 +181,27 @@ pub async fn start_openai_controller(
                         let text_command: String = text_command_msg?.value.try_into()?;
                         
 
-                        process_simple_text_command(&text_command, chat_gpt_conversation.clone(), client.clone(), zenoh_session.clone(), voice_provider_arc.clone()).await?;
+                        process_simple_text_command(&text_command, &mut chat_gpt_conversation, &client, zenoh_session.clone(), voice_provider_arc.clone(), &memory_config).await?;
                         }
                     text_command = receiver.recv() => {
                         if let Some(text_command) = text_command {
                             info!("Received new text command");
-                            process_simple_text_command(&text_command, chat_gpt_conversation.clone(), client.clone(), zenoh_session.clone(), voice_provider_arc.clone()).await?;
+                            process_simple_text_command(&text_command, &mut chat_gpt_conversation, &client, zenoh_session.clone(), voice_provider_arc.clone(), &memory_config).await?;
+                        }
+                    }
+                    memory_command_msg = memory_command_subscriber.recv_async() => {
+                        let memory_command: String = memory_command_msg?.value.try_into()?;
+                        match memory_command.trim().to_lowercase().as_str() {
+                            "reset" => {
+                                info!("Resetting conversation memory");
+                                chat_gpt_conversation.reset_memory();
+                                save_memory(&chat_gpt_conversation, &memory_config);
+                                publish_history(&chat_gpt_conversation, &zenoh_session).await?;
+                            }
+                            "inspect" => {
+                                publish_history(&chat_gpt_conversation, &zenoh_session).await?;
+                            }
+                            other => tracing::warn!("Unknown conversation memory command {:?}", other),
                         }
                     }
                     wake_word_detection = wake_word_detection_subscriber.recv_async() => {
@@ -164,7 +240,7 @@ pub async fn start_openai_controller(
                         let wake_word_transcript: AudioTranscript = serde_json::from_str(&wake_word_transcript)?;
                         if wake_word_transcript.wake_word.to_lowercase().contains("hopper") {
                             info!("Received new text command");
-                            process_simple_text_command(&wake_word_transcript.transcript, chat_gpt_conversation.clone(), client.clone(), zenoh_session.clone(), voice_provider_arc.clone()).await?;
+                            process_simple_text_command(&wake_word_transcript.transcript, &mut chat_gpt_conversation, &client, zenoh_session.clone(), voice_provider_arc.clone(), &memory_config).await?;
                         }
                     }
                 }
@@ -182,15 +258,76 @@ pub async fn start_openai_controller(
     Ok(open_ai_service)
 }
 
+fn create_client(config: &HopperOpenAiConfig) -> Client<OpenAIConfig> {
+    let mut openai_config = OpenAIConfig::new().with_api_key(&config.api_key);
+    if let Some(base_url) = &config.base_url {
+        info!("Using OpenAI compatible server at {}", base_url);
+        openai_config = openai_config.with_api_base(base_url);
+    }
+    Client::with_config(openai_config)
+}
+
+fn save_memory(conversation: &ChatGptConversation, memory_config: &ConversationMemoryConfig) {
+    if let Some(path) = &memory_config.path {
+        if let Err(error) = conversation.memory().save(Path::new(path)) {
+            tracing::error!("Failed to save conversation memory: {}", error);
+        }
+    }
+}
+
+async fn publish_history(
+    conversation: &ChatGptConversation,
+    zenoh_session: &zenoh::Session,
+) -> anyhow::Result<()> {
+    zenoh_session
+        .put(OPENAI_DIAGNOSTICS_HISTORY, conversation.get_history())
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+    Ok(())
+}
+
 async fn process_simple_text_command(
     text_command: &str,
-    mut conversation: ChatGptConversation,
-    open_ai_client: Client<OpenAIConfig>,
+    conversation: &mut ChatGptConversation,
+    open_ai_client: &Client<OpenAIConfig>,
     zenoh_session: Arc<zenoh::Session>,
     voice_provider_arc: Arc<Mutex<VoiceProvider>>,
+    memory_config: &ConversationMemoryConfig,
 ) -> anyhow::Result<()> {
     info!("Received hopper command {:?}", text_command);
 
+    let checkpoint = conversation.memory_checkpoint();
+    let result = get_responses(
+        text_command,
+        conversation,
+        open_ai_client,
+        voice_provider_arc,
+    )
+    .await;
+    if result.is_err() {
+        tracing::warn!("Dropping failed exchange from conversation memory");
+        conversation.rollback_memory(checkpoint);
+    }
+
+    if let Err(error) = conversation
+        .compact_memory(open_ai_client, memory_config.max_tokens)
+        .await
+    {
+        tracing::error!("Failed to compact conversation memory: {}", error);
+    }
+    save_memory(conversation, memory_config);
+    publish_history(conversation, &zenoh_session).await?;
+
+    result
+}
+
+async fn get_responses(
+    text_command: &str,
+    conversation: &mut ChatGptConversation,
+    open_ai_client: &Client<OpenAIConfig>,
+    voice_provider_arc: Arc<Mutex<VoiceProvider>>,
+) -> anyhow::Result<()> {
     let mut command = Some(text_command);
     // get responses
 
@@ -199,14 +336,17 @@ async fn process_simple_text_command(
         let stream_audio = matches!(voice_provider, VoiceProvider::Fast);
 
         let next_response = conversation
-            .next_message_stream(command.take(), &open_ai_client, stream_audio)
+            .next_message_stream(command.take(), open_ai_client, stream_audio)
             .await?;
 
         match next_response {
-            OpenAiApiResponse::AssistantResponse(response) => {
+            OpenAiApiResponse::AssistantResponse {
+                text: response,
+                streamed,
+            } => {
                 info!("Assistant response form ChatGPT: {:?}", response);
 
-                if !stream_audio {
+                if !streamed {
                     tokio::spawn(async move {
                         let voice_provider = *voice_provider_arc.lock().unwrap();
                         if let Err(err) = speak_with_face_animation(&response, voice_provider).await {
@@ -223,14 +363,6 @@ async fn process_simple_text_command(
         }
     }
 
-    let history = conversation.get_history();
-
-    zenoh_session
-        .put(OPENAI_DIAGNOSTICS_HISTORY, history)
-        .res()
-        .await
-        .map_err(HopperError::ZenohError)?;
-
     Ok(())
 }
 
@@ -0,0 +1,343 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use futures::Future;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tracing::*;
+use zenoh::prelude::r#async::*;
+
+use crate::{error::HopperError, DESCRIPTOR_POOL, FILE_DESCRIPTOR_SET};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordedEncoding {
+    /// Protobuf message from the compiled protos. Requires schema name
+    Protobuf,
+    Json,
+    /// Recorded without schema
+    Raw,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RecordedTopic {
+    pub key_expr: String,
+    pub encoding: RecordedEncoding,
+    /// Full protobuf message name such as `foxglove.PointCloud`
+    #[serde(default)]
+    pub schema: Option<String>,
+}
+
+impl RecordedTopic {
+    fn protobuf(key_expr: &str, schema: &str) -> Self {
+        Self {
+            key_expr: key_expr.to_owned(),
+            encoding: RecordedEncoding::Protobuf,
+            schema: Some(schema.to_owned()),
+        }
+    }
+
+    fn json(key_expr: &str) -> Self {
+        Self {
+            key_expr: key_expr.to_owned(),
+            encoding: RecordedEncoding::Json,
+            schema: None,
+        }
+    }
+
+    fn raw(key_expr: &str) -> Self {
+        Self {
+            key_expr: key_expr.to_owned(),
+            encoding: RecordedEncoding::Raw,
+            schema: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RecorderConfig {
+    pub topics: Vec<RecordedTopic>,
+}
+
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        Self {
+            topics: vec![
+                RecordedTopic::protobuf("hopper/pose/frames", "foxglove.FrameTransforms"),
+                RecordedTopic::protobuf("hopper/odometry/pose", "foxglove.PoseInFrame"),
+                RecordedTopic::protobuf("hopper/odometry/frame", "foxglove.FrameTransform"),
+                RecordedTopic::protobuf("hopper/lidar/point_cloud", "foxglove.PointCloud"),
+                RecordedTopic::protobuf("hopper/lidar/occupancy_grid", "foxglove.Grid"),
+                RecordedTopic::protobuf("hopper/camera/image", "foxglove.CompressedImage"),
+                RecordedTopic::protobuf("hopper/metrics/diagnostic", "hopper.DiagnosticMessage"),
+                RecordedTopic::json("hopper/tracing/json"),
+                // walking config is yaml and lives one level deeper than json statuses
+                RecordedTopic::json("hopper/status/*"),
+                RecordedTopic::raw("hopper/status/simple/walking_config"),
+            ],
+        }
+    }
+}
+
+impl RecorderConfig {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(serde_yaml::from_str(&text)?)
+    }
+}
+
+fn schema_for_topic(topic: &RecordedTopic) -> anyhow::Result<Option<Arc<mcap::Schema<'static>>>> {
+    match (topic.encoding, &topic.schema) {
+        (RecordedEncoding::Protobuf, Some(schema)) => {
+            DESCRIPTOR_POOL
+                .get_message_by_name(schema)
+                .with_context(|| format!("Unknown protobuf message {}", schema))?;
+            // foxglove resolves the message from the full descriptor set
+            Ok(Some(Arc::new(mcap::Schema {
+                name: schema.to_owned(),
+                encoding: "protobuf".to_owned(),
+                data: Cow::Borrowed(FILE_DESCRIPTOR_SET),
+            })))
+        }
+        (RecordedEncoding::Protobuf, None) => {
+            anyhow::bail!("Protobuf topic {} is missing schema name", topic.key_expr)
+        }
+        _ => Ok(None),
+    }
+}
+
+fn message_encoding(encoding: RecordedEncoding) -> &'static str {
+    match encoding {
+        RecordedEncoding::Protobuf => "protobuf",
+        RecordedEncoding::Json => "json",
+        RecordedEncoding::Raw => "",
+    }
+}
+
+fn nanos_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_nanos() as u64
+}
+
+/// Writes samples to MCAP creating a channel for each key
+struct McapRecorder {
+    writer: mcap::Writer<'static, BufWriter<File>>,
+    channels: HashMap<String, u16>,
+    sequence: u32,
+}
+
+impl McapRecorder {
+    fn create(path: &Path) -> anyhow::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self {
+            writer: mcap::Writer::new(BufWriter::new(file))?,
+            channels: HashMap::new(),
+            sequence: 0,
+        })
+    }
+
+    fn write(
+        &mut self,
+        key: &str,
+        topic: &RecordedTopic,
+        schema: &Option<Arc<mcap::Schema<'static>>>,
+        payload: &[u8],
+    ) -> anyhow::Result<()> {
+        let channel_id = match self.channels.get(key) {
+            Some(channel_id) => *channel_id,
+            None => {
+                let channel_id = self.writer.add_channel(&mcap::Channel {
+                    topic: key.to_owned(),
+                    schema: schema.clone(),
+                    message_encoding: message_encoding(topic.encoding).to_owned(),
+                    metadata: BTreeMap::new(),
+                })?;
+                info!("Recording new channel {}", key);
+                self.channels.insert(key.to_owned(), channel_id);
+                channel_id
+            }
+        };
+        let now = nanos_since_epoch(SystemTime::now());
+        self.sequence += 1;
+        self.writer.write_to_known_channel(
+            &mcap::records::MessageHeader {
+                channel_id,
+                sequence: self.sequence,
+                log_time: now,
+                publish_time: now,
+            },
+            payload,
+        )?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> anyhow::Result<()> {
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+/// Record configured topics into MCAP file until stop future completes
+pub async fn record(
+    zenoh_session: Arc<Session>,
+    config: &RecorderConfig,
+    output: &Path,
+    stop: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    let (sample_sender, mut sample_receiver) = mpsc::channel(100);
+    let mut schemas = vec![];
+    for (index, topic) in config.topics.iter().enumerate() {
+        schemas.push(schema_for_topic(topic)?);
+        let subscriber = zenoh_session
+            .declare_subscriber(topic.key_expr.to_owned())
+            .res()
+            .await
+            .map_err(HopperError::ZenohError)?;
+        let sample_sender = sample_sender.clone();
+        tokio::spawn(async move {
+            while let Ok(sample) = subscriber.recv_async().await {
+                if sample_sender.send((index, sample)).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(sample_sender);
+
+    let mut recorder = McapRecorder::create(output)?;
+    info!("Recording {} topics to {:?}", config.topics.len(), output);
+    tokio::pin!(stop);
+    loop {
+        tokio::select! {
+            sample = sample_receiver.recv() => {
+                match sample {
+                    Some((index, sample)) => {
+                        let payload = sample.value.payload.contiguous();
+                        recorder.write(
+                            sample.key_expr.as_str(),
+                            &config.topics[index],
+                            &schemas[index],
+                            &payload,
+                        )?;
+                    }
+                    None => break,
+                }
+            }
+            _ = &mut stop => break,
+        }
+    }
+    recorder.finish()?;
+    info!("Recording saved to {:?}", output);
+    Ok(())
+}
+
+/// Publish messages from MCAP file back onto zenoh keeping original timing
+pub async fn replay(zenoh_session: Arc<Session>, input: &Path, rate: f64) -> anyhow::Result<()> {
+    let file = File::open(input)?;
+    // recordings can be larger than memory. Only touched pages get loaded
+    let data = unsafe { memmap2::Mmap::map(&file) }?;
+    let mut first_log_time = None;
+    let start = tokio::time::Instant::now();
+    for message in mcap::MessageStream::new(&data)? {
+        let message = message?;
+        let first_log_time = *first_log_time.get_or_insert(message.log_time);
+        let offset = Duration::from_nanos(message.log_time.saturating_sub(first_log_time));
+        tokio::time::sleep_until(start + offset.div_f64(rate)).await;
+        zenoh_session
+            .put(message.channel.topic.as_str(), message.data.to_vec())
+            .res()
+            .await
+            .map_err(HopperError::ZenohError)?;
+    }
+    info!("Replay of {:?} finished", input);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zenoh_remotes::topic_consts::HOPPER_WALKING_CONFIG_PUBLISHER;
+
+    #[test]
+    fn default_protobuf_schemas_exist() {
+        for topic in RecorderConfig::default().topics {
+            let schema = schema_for_topic(&topic).unwrap();
+            assert_eq!(
+                schema.is_some(),
+                topic.encoding == RecordedEncoding::Protobuf
+            );
+        }
+    }
+
+    #[test]
+    fn walking_config_is_not_recorded_as_json() {
+        let walking_config = keyexpr::new(HOPPER_WALKING_CONFIG_PUBLISHER).unwrap();
+        let recording_topics: Vec<_> = RecorderConfig::default()
+            .topics
+            .into_iter()
+            .filter(|topic| {
+                keyexpr::new(topic.key_expr.as_str())
+                    .unwrap()
+                    .intersects(walking_config)
+            })
+            .collect();
+        assert_eq!(recording_topics.len(), 1);
+        assert_eq!(recording_topics[0].encoding, RecordedEncoding::Raw);
+    }
+
+    #[test]
+    fn example_config_matches_default() {
+        let config: RecorderConfig =
+            serde_yaml::from_str(include_str!("../config/recorder.yaml")).unwrap();
+        let default_keys: Vec<_> = RecorderConfig::default()
+            .topics
+            .into_iter()
+            .map(|topic| topic.key_expr)
+            .collect();
+        let keys: Vec<_> = config
+            .topics
+            .into_iter()
+            .map(|topic| topic.key_expr)
+            .collect();
+        assert_eq!(keys, default_keys);
+    }
+
+    #[test]
+    fn recording_can_be_read_back() {
+        let dir = tempdir::TempDir::new("recorder").unwrap();
+        let path = dir.path().join("test.mcap");
+        let topic = RecordedTopic::protobuf("hopper/odometry/pose", "foxglove.PoseInFrame");
+        let schema = schema_for_topic(&topic).unwrap();
+
+        let mut recorder = McapRecorder::create(&path).unwrap();
+        recorder
+            .write("hopper/odometry/pose", &topic, &schema, &[1, 2, 3])
+            .unwrap();
+        recorder
+            .write("hopper/odometry/pose", &topic, &schema, &[4, 5])
+            .unwrap();
+        recorder.finish().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        let messages: Vec<_> = mcap::MessageStream::new(&data)
+            .unwrap()
+            .map(|message| message.unwrap())
+            .collect();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].channel.topic, "hopper/odometry/pose");
+        assert_eq!(
+            messages[0].channel.schema.as_ref().unwrap().name,
+            "foxglove.PoseInFrame"
+        );
+        assert_eq!(&*messages[1].data, &[4, 5]);
+    }
+}
@@ -1,14 +1,11 @@
 use super::AzureVoiceStyle;
+use crate::configuration::TtsServiceConfig;
 use crate::error::HopperResult;
 
 pub struct SpeechService {}
 
 impl SpeechService {
-    pub fn new(
-        _azure_subscription_key: String,
-        _cache_dir_path: Option<String>,
-        _audio_repository_path: Option<String>,
-    ) -> HopperResult<SpeechService> {
+    pub fn new(_config: TtsServiceConfig) -> HopperResult<SpeechService> {
         Ok(SpeechService {})
     }
 
@@ -6,12 +6,16 @@ mod audio_cache;
 mod audio_repository;
 #[cfg(feature = "audio")]
 mod speech_service;
+#[cfg(feature = "audio")]
+mod tts_backend;
 
 #[cfg(feature = "audio")]
 mod eleven_labs_client;
 
 #[cfg(feature = "audio")]
-pub use speech_service::{SpeechService, DEFAULT_ELEVEN_LABS_VOICE_NAME};
+pub use speech_service::SpeechService;
+#[cfg(feature = "audio")]
+pub use tts_backend::TtsBackend;
 
 #[cfg(feature = "audio")]
 pub use eleven_labs_client::ElevenLabsTtsClient;
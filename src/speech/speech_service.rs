@@ -1,56 +1,22 @@
 use super::audio_repository::AudioRepository;
+use super::tts_backend::{AzureTtsBackend, ElevenLabsTtsBackend, LocalTtsBackend, TtsBackend};
 use super::AzureVoiceStyle;
 use super::{audio_cache::AudioCache, eleven_labs_client::StreamingSession};
+use crate::configuration::{TtsBackendKind, TtsServiceConfig};
 use crate::face::animations::Animation;
 use crate::{
     error::{HopperError, HopperResult},
     ioc_container::IocContainer,
 };
 use anyhow::Context;
-use sha2::{Digest, Sha256};
 use std::{fs::File, io::Cursor, sync::Arc, thread};
-use tokio::sync::{
-    mpsc::{channel, Receiver, Sender},
-    Mutex as TokioMutex,
-};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tracing::*;
 use zenoh::prelude::r#async::*;
 use zenoh::Session as ZenohSession;
 
 use rodio::cpal::traits::{DeviceTrait, HostTrait};
 
-// Used to invalidate old cache
-const AZURE_FORMAT_VERSION: u32 = 3;
-
-fn hash_azure_tts(
-    text: &str,
-    voice: &azure_tts::VoiceSettings,
-    format: azure_tts::AudioFormat,
-    style: AzureVoiceStyle,
-) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(text);
-    hasher.update(&voice.name);
-    hasher.update(&voice.language);
-    hasher.update(format.as_string());
-    hasher.update([style as u8]);
-    hasher.update(AZURE_FORMAT_VERSION.to_be_bytes());
-    // Turning it into json to hash is a hack.
-    // TODO: hash the type not the json
-    hasher.update(serde_json::to_string(&voice.gender).unwrap());
-    let hashed = hasher.finalize();
-    format!("{}-{:x}", voice.name, hashed)
-}
-
-fn hash_eleven_labs_tts(text: &str, voice_id: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(text);
-    hasher.update(voice_id);
-    hasher.update(AZURE_FORMAT_VERSION.to_be_bytes());
-    let hashed = hasher.finalize();
-    format!("eleven-{:x}", hashed)
-}
-
 enum AudioPlayerCommand {
     Play(Box<dyn Playable>),
     Pause,
@@ -155,13 +121,11 @@ fn create_player() -> Sender<AudioPlayerCommand> {
 }
 
 pub struct SpeechService {
-    azure_speech_client: TokioMutex<azure_tts::VoiceService>,
-    eleven_labs_client: super::eleven_labs_client::ElevenLabsTtsClient,
-    voice_name_to_voice_id_table: std::collections::HashMap<String, String>,
+    /// Backends in configured fallback order
+    backends: Vec<(TtsBackendKind, Arc<dyn TtsBackend>)>,
+    eleven_labs: Option<Arc<ElevenLabsTtsBackend>>,
     audio_cache: Option<AudioCache>,
     audio_repository: Option<AudioRepository>,
-    azure_voice: azure_tts::VoiceSettings,
-    azure_audio_format: azure_tts::AudioFormat,
     audio_sender: Sender<AudioPlayerCommand>,
 }
 
@@ -170,46 +134,76 @@ pub trait Playable: std::io::Read + std::io::Seek + Send + Sync {}
 impl Playable for Cursor<Vec<u8>> {}
 impl Playable for File {}
 
-/// voice Natasha
-pub const DEFAULT_ELEVEN_LABS_VOICE_NAME: &str = "Natasha";
+/// Order in which backends are tried with the preferred one first
+fn fallback_order(
+    backends: &[TtsBackendKind],
+    preferred: Option<TtsBackendKind>,
+) -> Vec<TtsBackendKind> {
+    let mut order: Vec<_> = preferred
+        .iter()
+        .filter(|kind| backends.contains(kind))
+        .copied()
+        .collect();
+    for kind in backends {
+        if !order.contains(kind) {
+            order.push(*kind);
+        }
+    }
+    order
+}
 
 impl SpeechService {
-    pub async fn new(
-        azure_subscription_key: String,
-        eleven_labs_api_key: String,
-        cache_dir_path: Option<String>,
-        audio_repository_path: Option<String>,
-    ) -> anyhow::Result<SpeechService> {
-        let azure_speech_client =
-            azure_tts::VoiceService::new(&azure_subscription_key, azure_tts::Region::uksouth);
-        let azure_speech_client = TokioMutex::new(azure_speech_client);
-
-        let eleven_labs_client =
-            super::eleven_labs_client::ElevenLabsTtsClient::new(eleven_labs_api_key);
-
-        let voices = eleven_labs_client.voices().await?;
-        let voice_name_to_voice_id_table = voices.name_to_id_table();
-
-        let audio_cache = match cache_dir_path {
+    /// Doesn't touch the network so that Hopper can boot offline
+    pub fn new(config: TtsServiceConfig) -> anyhow::Result<SpeechService> {
+        let mut backends: Vec<(TtsBackendKind, Arc<dyn TtsBackend>)> = vec![];
+        let mut eleven_labs = None;
+        for kind in fallback_order(&config.backends, None) {
+            match kind {
+                TtsBackendKind::Azure => {
+                    if config.azure_api_key.is_empty() {
+                        warn!("Azure api key not set. Disabling Azure text to speech");
+                        continue;
+                    }
+                    backends.push((kind, Arc::new(AzureTtsBackend::new(&config.azure_api_key))));
+                }
+                TtsBackendKind::ElevenLabs => {
+                    if config.eleven_labs_api_key.is_empty() {
+                        warn!("ElevenLabs api key not set. Disabling ElevenLabs text to speech");
+                        continue;
+                    }
+                    let backend = Arc::new(ElevenLabsTtsBackend::new(
+                        config.eleven_labs_api_key.clone(),
+                        config.eleven_labs_voice.clone(),
+                    ));
+                    eleven_labs = Some(backend.clone());
+                    backends.push((kind, backend));
+                }
+                TtsBackendKind::Local => {
+                    backends.push((kind, Arc::new(LocalTtsBackend::new(config.local.clone()))));
+                }
+            }
+        }
+        if backends.is_empty() {
+            warn!("No text to speech backends configured");
+        }
+
+        let audio_cache = match config.cache_dir_path {
             Some(path) => Some(AudioCache::new(path)?),
             None => None,
         };
 
         let audio_sender = create_player();
 
-        let audio_repository = match audio_repository_path {
+        let audio_repository = match config.audio_repository_path {
             Some(path) => Some(AudioRepository::new(path)?),
             None => None,
         };
 
         Ok(SpeechService {
-            azure_speech_client,
-            eleven_labs_client,
-            voice_name_to_voice_id_table,
+            backends,
+            eleven_labs,
             audio_cache,
             audio_repository,
-            azure_voice: azure_tts::EnUsVoices::SaraNeural.to_voice_settings(),
-            azure_audio_format: azure_tts::AudioFormat::Audio48khz192kbitrateMonoMp3,
             audio_sender,
         })
     }
@@ -221,68 +215,54 @@ impl SpeechService {
             .unwrap();
     }
 
-    async fn say_azure_with_voice(
+    async fn synthesize_cached(
         &self,
+        backend: &dyn TtsBackend,
         text: &str,
-        voice: &azure_tts::VoiceSettings,
         style: AzureVoiceStyle,
-    ) -> HopperResult<()> {
-        info!("Using {:?} style", &style);
-        let mut segments = vec![
-            azure_tts::VoiceSegment::silence(
-                azure_tts::SilenceAttributeType::Sentenceboundary,
-                "50ms".to_owned(),
-            ),
-            azure_tts::VoiceSegment::silence(
-                azure_tts::SilenceAttributeType::Tailing,
-                "25ms".to_owned(),
-            ),
-            azure_tts::VoiceSegment::silence(
-                azure_tts::SilenceAttributeType::Leading,
-                "25ms".to_owned(),
-            ),
-        ];
-        let contents = match style {
-            AzureVoiceStyle::Plain => azure_tts::VoiceSegment::plain(text),
-            AzureVoiceStyle::Angry => {
-                azure_tts::VoiceSegment::with_expression(text, azure_tts::Style::Angry)
-            }
-            AzureVoiceStyle::Sad => {
-                azure_tts::VoiceSegment::with_expression(text, azure_tts::Style::Sad)
-            }
-            AzureVoiceStyle::Cheerful => {
-                azure_tts::VoiceSegment::with_expression(text, azure_tts::Style::Cheerful)
+    ) -> anyhow::Result<Box<dyn Playable>> {
+        let file_key = backend.cache_key(text, style);
+        if let (Some(audio_cache), Some(file_key)) = (&self.audio_cache, &file_key) {
+            if let Some(file) = audio_cache.get(file_key) {
+                info!("Using cached value with key {}", file_key);
+                return Ok(file);
             }
-        };
-        segments.push(contents);
+        }
+        let data = backend.synthesize(text, style).await?;
+        if let (Some(audio_cache), Some(file_key)) = (&self.audio_cache, &file_key) {
+            info!("Writing new file with key {}", file_key);
+            audio_cache.set(file_key, data.clone())?;
+        }
+        Ok(Box::new(Cursor::new(data)))
+    }
 
-        let sound: Box<dyn Playable> = if let Some(ref audio_cache) = self.audio_cache {
-            let file_key = hash_azure_tts(text, voice, self.azure_audio_format, style);
-            if let Some(file) = audio_cache.get(&file_key) {
-                info!("Using cached value with key {}", file_key);
-                file
-            } else {
-                info!("Writing new file with key {}", file_key);
-                let data = self
-                    .azure_speech_client
-                    .lock()
-                    .await
-                    .synthesize_segments(segments, voice, self.azure_audio_format)
-                    .await?;
-                audio_cache.set(&file_key, data.clone())?;
-                Box::new(Cursor::new(data))
+    /// Say text trying the preferred backend first and falling back to the others
+    pub async fn say_preferring(
+        &self,
+        text: &str,
+        style: AzureVoiceStyle,
+        preferred: Option<TtsBackendKind>,
+    ) -> anyhow::Result<()> {
+        let kinds: Vec<_> = self.backends.iter().map(|(kind, _)| *kind).collect();
+        for kind in fallback_order(&kinds, preferred) {
+            let backend = match self.backends.iter().find(|(other, _)| *other == kind) {
+                Some((_, backend)) => backend,
+                None => continue,
+            };
+            match self.synthesize_cached(backend.as_ref(), text, style).await {
+                Ok(sound) => {
+                    self.play(sound).await;
+                    return Ok(());
+                }
+                Err(error) => warn!("{} text to speech failed: {}", backend.name(), error),
             }
-        } else {
-            let data = self
-                .azure_speech_client
-                .lock()
-                .await
-                .synthesize_segments(segments, voice, self.azure_audio_format)
-                .await?;
-            Box::new(Cursor::new(data))
-        };
-        self.play(sound).await;
-        Ok(())
+        }
+        anyhow::bail!("All text to speech backends failed")
+    }
+
+    /// Say text using configured backend order
+    pub async fn say(&self, text: &str, style: AzureVoiceStyle) -> anyhow::Result<()> {
+        self.say_preferring(text, style, None).await
     }
 
     pub async fn play_sound(&self, sound_name: &str) -> HopperResult<()> {
@@ -358,9 +338,8 @@ impl SpeechService {
         Ok(())
     }
 
-    pub async fn say_azure(&self, text: &str) -> HopperResult<()> {
-        // This cloning here is lame...
-        self.say_azure_with_voice(text, &self.azure_voice.clone(), AzureVoiceStyle::Plain)
+    pub async fn say_azure(&self, text: &str) -> anyhow::Result<()> {
+        self.say_azure_with_style(text, AzureVoiceStyle::Plain)
             .await
     }
 
@@ -368,43 +347,27 @@ impl SpeechService {
         &self,
         text: &str,
         style: AzureVoiceStyle,
-    ) -> HopperResult<()> {
-        // This cloning here is lame...
-        self.say_azure_with_voice(text, &self.azure_voice.clone(), style)
+    ) -> anyhow::Result<()> {
+        self.say_preferring(text, style, Some(TtsBackendKind::Azure))
             .await
     }
 
     pub async fn say_eleven_with_default_voice(&self, text: &str) -> anyhow::Result<()> {
-        self.say_eleven(text, DEFAULT_ELEVEN_LABS_VOICE_NAME)
-            .await?;
-        Ok(())
+        self.say_preferring(
+            text,
+            AzureVoiceStyle::Plain,
+            Some(TtsBackendKind::ElevenLabs),
+        )
+        .await
     }
 
-    pub async fn say_eleven(&self, text: &str, voice_name: &str) -> anyhow::Result<()> {
-        let voice_id = self
-            .voice_name_to_voice_id_table
-            .get(voice_name)
-            .context("Unknown voice")?
-            .clone();
-        info!("Using voice id {} for voice {}", voice_id, voice_name);
-        self.say_eleven_with_voice_id(text, &voice_id).await?;
-        Ok(())
-    }
-
-    pub async fn start_eleven_labs_voice_stream(
-        &self,
-        voice_name: &str,
-    ) -> anyhow::Result<StreamingSession> {
-        let voice_id = self
-            .voice_name_to_voice_id_table
-            .get(voice_name)
-            .context("Unknown voice")?
-            .clone();
+    pub async fn start_eleven_labs_voice_stream(&self) -> anyhow::Result<StreamingSession> {
         let (session, mut audio_sample_receiver) = self
-            .eleven_labs_client
-            .start_streaming_session(&voice_id)
+            .eleven_labs
+            .as_ref()
+            .context("ElevenLabs is not configured")?
+            .start_streaming_session()
             .await?;
-
         tokio::spawn(async move {
             while let Some(audio_file_contents) = audio_sample_receiver.recv().await {
                 // these unwraps are not great
@@ -435,27 +398,6 @@ impl SpeechService {
         Ok(session)
     }
 
-    pub async fn say_eleven_with_voice_id(&self, text: &str, voice_id: &str) -> anyhow::Result<()> {
-        let sound: Box<dyn Playable> = if let Some(ref audio_cache) = self.audio_cache {
-            let file_key = hash_eleven_labs_tts(text, voice_id);
-            if let Some(file) = audio_cache.get(&file_key) {
-                info!("Using cached value with key {}", file_key);
-                file
-            } else {
-                info!("Writing new file with key {}", file_key);
-                let data = self.eleven_labs_client.tts(text, voice_id).await?;
-                let sound: Box<dyn Playable> = Box::new(Cursor::new(data.to_vec()));
-                audio_cache.set(&file_key, data.to_vec())?;
-                sound
-            }
-        } else {
-            let data = self.eleven_labs_client.tts(text, voice_id).await?;
-            Box::new(Cursor::new(data.to_vec()))
-        };
-        self.play(sound).await;
-        Ok(())
-    }
-
     /// Say using the home speak speaker system
     pub async fn say_home_speak(&self, text: &str) -> anyhow::Result<()> {
         IocContainer::global_instance()
@@ -505,3 +447,35 @@ impl SpeechService {
             .unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preferred_backend_is_tried_first() {
+        let backends = [
+            TtsBackendKind::ElevenLabs,
+            TtsBackendKind::Azure,
+            TtsBackendKind::Local,
+        ];
+        assert_eq!(fallback_order(&backends, None), backends);
+        assert_eq!(
+            fallback_order(&backends, Some(TtsBackendKind::Azure)),
+            [
+                TtsBackendKind::Azure,
+                TtsBackendKind::ElevenLabs,
+                TtsBackendKind::Local
+            ]
+        );
+    }
+
+    #[test]
+    fn unavailable_preferred_backend_is_skipped() {
+        let backends = [TtsBackendKind::Local, TtsBackendKind::Local];
+        assert_eq!(
+            fallback_order(&backends, Some(TtsBackendKind::ElevenLabs)),
+            [TtsBackendKind::Local]
+        );
+    }
+}
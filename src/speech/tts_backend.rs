@@ -0,0 +1,281 @@
+use super::eleven_labs_client::{ElevenLabsTtsClient, StreamingSession};
+use super::AzureVoiceStyle;
+use crate::configuration::LocalTtsConfig;
+use anyhow::Context;
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, process::Stdio, time::Duration};
+use tokio::{io::AsyncWriteExt, process::Command, sync::Mutex as TokioMutex};
+use tracing::*;
+
+// Used to invalidate old cache
+const AZURE_FORMAT_VERSION: u32 = 3;
+
+/// Text to speech engine
+#[async_trait]
+pub trait TtsBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Key for the audio cache. None disables caching
+    fn cache_key(&self, text: &str, style: AzureVoiceStyle) -> Option<String>;
+
+    /// Synthesize text into audio data that rodio can decode
+    async fn synthesize(&self, text: &str, style: AzureVoiceStyle) -> anyhow::Result<Vec<u8>>;
+}
+
+fn hash_azure_tts(
+    text: &str,
+    voice: &azure_tts::VoiceSettings,
+    format: azure_tts::AudioFormat,
+    style: AzureVoiceStyle,
+) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(text);
+    hasher.update(&voice.name);
+    hasher.update(&voice.language);
+    hasher.update(format.as_string());
+    hasher.update([style as u8]);
+    hasher.update(AZURE_FORMAT_VERSION.to_be_bytes());
+    // Turning it into json to hash is a hack.
+    // TODO: hash the type not the json
+    hasher.update(serde_json::to_string(&voice.gender).unwrap());
+    let hashed = hasher.finalize();
+    format!("{}-{:x}", voice.name, hashed)
+}
+
+/// Keyed by voice name so that cached phrases play without looking up voice ids
+fn hash_eleven_labs_tts(text: &str, voice_name: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(text);
+    hasher.update(voice_name);
+    hasher.update(AZURE_FORMAT_VERSION.to_be_bytes());
+    let hashed = hasher.finalize();
+    format!("eleven-{:x}", hashed)
+}
+
+pub struct AzureTtsBackend {
+    client: TokioMutex<azure_tts::VoiceService>,
+    voice: azure_tts::VoiceSettings,
+    format: azure_tts::AudioFormat,
+}
+
+impl AzureTtsBackend {
+    pub fn new(subscription_key: &str) -> Self {
+        Self {
+            client: TokioMutex::new(azure_tts::VoiceService::new(
+                subscription_key,
+                azure_tts::Region::uksouth,
+            )),
+            voice: azure_tts::EnUsVoices::SaraNeural.to_voice_settings(),
+            format: azure_tts::AudioFormat::Audio48khz192kbitrateMonoMp3,
+        }
+    }
+}
+
+#[async_trait]
+impl TtsBackend for AzureTtsBackend {
+    fn name(&self) -> &'static str {
+        "azure"
+    }
+
+    fn cache_key(&self, text: &str, style: AzureVoiceStyle) -> Option<String> {
+        Some(hash_azure_tts(text, &self.voice, self.format, style))
+    }
+
+    async fn synthesize(&self, text: &str, style: AzureVoiceStyle) -> anyhow::Result<Vec<u8>> {
+        info!("Using {:?} style", &style);
+        let mut segments = vec![
+            azure_tts::VoiceSegment::silence(
+                azure_tts::SilenceAttributeType::Sentenceboundary,
+                "50ms".to_owned(),
+            ),
+            azure_tts::VoiceSegment::silence(
+                azure_tts::SilenceAttributeType::Tailing,
+                "25ms".to_owned(),
+            ),
+            azure_tts::VoiceSegment::silence(
+                azure_tts::SilenceAttributeType::Leading,
+                "25ms".to_owned(),
+            ),
+        ];
+        let contents = match style {
+            AzureVoiceStyle::Plain => azure_tts::VoiceSegment::plain(text),
+            AzureVoiceStyle::Angry => {
+                azure_tts::VoiceSegment::with_expression(text, azure_tts::Style::Angry)
+            }
+            AzureVoiceStyle::Sad => {
+                azure_tts::VoiceSegment::with_expression(text, azure_tts::Style::Sad)
+            }
+            AzureVoiceStyle::Cheerful => {
+                azure_tts::VoiceSegment::with_expression(text, azure_tts::Style::Cheerful)
+            }
+        };
+        segments.push(contents);
+
+        let data = self
+            .client
+            .lock()
+            .await
+            .synthesize_segments(segments, &self.voice, self.format)
+            .await?;
+        Ok(data)
+    }
+}
+
+/// ElevenLabs voices are looked up on first use so that boot works offline
+pub struct ElevenLabsTtsBackend {
+    client: ElevenLabsTtsClient,
+    voice_name: String,
+    voice_name_to_voice_id_table: TokioMutex<Option<HashMap<String, String>>>,
+}
+
+impl ElevenLabsTtsBackend {
+    pub fn new(api_key: String, voice_name: String) -> Self {
+        Self {
+            client: ElevenLabsTtsClient::new(api_key),
+            voice_name,
+            voice_name_to_voice_id_table: TokioMutex::new(None),
+        }
+    }
+
+    async fn voice_id(&self, voice_name: &str) -> anyhow::Result<String> {
+        let mut table = self.voice_name_to_voice_id_table.lock().await;
+        if table.is_none() {
+            let voices = self.client.voices().await?;
+            table.replace(voices.name_to_id_table());
+        }
+        let voice_id = table
+            .as_ref()
+            .and_then(|table| table.get(voice_name))
+            .context("Unknown voice")?
+            .clone();
+        info!("Using voice id {} for voice {}", voice_id, voice_name);
+        Ok(voice_id)
+    }
+
+    pub async fn start_streaming_session(
+        &self,
+    ) -> anyhow::Result<(StreamingSession, tokio::sync::mpsc::Receiver<Vec<u8>>)> {
+        let voice_id = self.voice_id(&self.voice_name).await?;
+        self.client.start_streaming_session(&voice_id).await
+    }
+}
+
+#[async_trait]
+impl TtsBackend for ElevenLabsTtsBackend {
+    fn name(&self) -> &'static str {
+        "eleven_labs"
+    }
+
+    fn cache_key(&self, text: &str, _style: AzureVoiceStyle) -> Option<String> {
+        Some(hash_eleven_labs_tts(text, &self.voice_name))
+    }
+
+    async fn synthesize(&self, text: &str, _style: AzureVoiceStyle) -> anyhow::Result<Vec<u8>> {
+        let voice_id = self.voice_id(&self.voice_name).await?;
+        let data = self.client.tts(text, &voice_id).await?;
+        Ok(data.to_vec())
+    }
+}
+
+/// Offline engine run as a subprocess
+///
+/// Text is written to stdin and a wav file is read from stdout.
+/// Works with `espeak-ng --stdout --stdin` or `piper --output_file -`
+pub struct LocalTtsBackend {
+    config: LocalTtsConfig,
+}
+
+impl LocalTtsBackend {
+    pub fn new(config: LocalTtsConfig) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl TtsBackend for LocalTtsBackend {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn cache_key(&self, _text: &str, _style: AzureVoiceStyle) -> Option<String> {
+        // fast enough to not need a cache
+        None
+    }
+
+    async fn synthesize(&self, text: &str, _style: AzureVoiceStyle) -> anyhow::Result<Vec<u8>> {
+        let mut child = Command::new(&self.config.command)
+            .args(&self.config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to start {}", self.config.command))?;
+        let mut stdin = child.stdin.take().context("Failed to open stdin")?;
+        // engine can start writing audio before it read all text
+        // so stdout has to be drained while text is still being written
+        let write_text = async move {
+            stdin.write_all(text.as_bytes()).await?;
+            // close stdin so that the engine knows text ended
+            drop(stdin);
+            Ok::<(), std::io::Error>(())
+        };
+
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        // child is killed on drop if it times out
+        let (write_result, output) = tokio::time::timeout(timeout, async {
+            tokio::join!(write_text, child.wait_with_output())
+        })
+        .await
+        .with_context(|| format!("{} timed out after {:?}", self.config.command, timeout))?;
+        let output = output?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} failed with {}: {}",
+                self.config.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        anyhow::ensure!(
+            !output.stdout.is_empty(),
+            "{} produced no audio",
+            self.config.command
+        );
+        write_result.with_context(|| format!("Failed to write text to {}", self.config.command))?;
+        Ok(output.stdout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn local_backend_reads_output_while_writing_text() {
+        let backend = LocalTtsBackend::new(LocalTtsConfig {
+            command: "cat".to_string(),
+            args: vec![],
+            ..Default::default()
+        });
+        // larger than pipe buffers so cat blocks unless stdout is drained
+        let text = "a".repeat(1024 * 1024);
+        let audio = backend
+            .synthesize(&text, AzureVoiceStyle::Plain)
+            .await
+            .unwrap();
+        assert_eq!(audio.len(), text.len());
+    }
+
+    #[tokio::test]
+    async fn local_backend_times_out() {
+        let backend = LocalTtsBackend::new(LocalTtsConfig {
+            command: "sleep".to_string(),
+            args: vec!["5".to_string()],
+            timeout_ms: 50,
+        });
+        let result = backend.synthesize("hello", AzureVoiceStyle::Plain).await;
+        assert!(result.is_err());
+    }
+}
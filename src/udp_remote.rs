@@ -1,6 +1,8 @@
 use crate::body_controller::{BodyController, BodyMotorPositions};
 use crate::error::HopperResult;
+use crate::hopper_body_config::HopperConfig;
 use crate::ik_controller::leg_positions::*;
+use crate::ik_controller::validation::check_motor_limits;
 use crate::ik_controller::IkControllable;
 use crate::motion_controller;
 use crate::motion_controller::walking::MoveCommand;
@@ -31,7 +33,11 @@ struct Message {
 }
 
 #[allow(dead_code)]
-pub async fn udp_motor_commander(mut controller: Box<dyn BodyController>) -> HopperResult<()> {
+/// Raw motor positions are checked against joint limits of `body_config`
+pub async fn udp_motor_commander(
+    mut controller: Box<dyn BodyController>,
+    body_config: HopperConfig,
+) -> HopperResult<()> {
     let socket = UdpSocket::bind("0.0.0.0:6666")?;
     let mut buffer = [0; 1024];
 
@@ -44,7 +50,10 @@ pub async fn udp_motor_commander(mut controller: Box<dyn BodyController>) -> Hop
                 match message.command {
                     Command::MoveMotorsTo(position) => {
                         trace!("Moving to pos");
-                        controller.move_motors_to(&position).await?;
+                        match check_motor_limits(&position, &body_config) {
+                            Ok(position) => controller.move_motors_to(&position).await?,
+                            Err(error) => warn!("Refusing motor positions {}", error),
+                        }
                     }
                     Command::SetSpeed(speed) => {
                         trace!("Setting speed");
@@ -111,7 +120,10 @@ pub async fn udp_ik_commander(mut controller: Box<dyn IkControllable>) -> Hopper
                     }
                     Command::MoveMotorsTo(position) => {
                         trace!("Moving to pos");
-                        controller.move_motors_to(&position).await?;
+                        // checked against joint limits by the ik controller
+                        if let Err(error) = controller.move_motors_to(&position).await {
+                            warn!("Failed writing motor positions {}", &error);
+                        }
                     }
                     Command::SetSpeed(speed) => {
                         trace!("Setting speed");
@@ -0,0 +1,49 @@
+use crate::error::HopperError;
+use crate::motion_controller::estop::EmergencyStop;
+use crate::zenoh_remotes::topic_consts::{HOPPER_ESTOP, HOPPER_ESTOP_CLEAR};
+use std::sync::Arc;
+use tokio::select;
+use tracing::*;
+use zenoh::prelude::r#async::*;
+use zenoh::Session;
+
+/// Any message on the estop topic latches the stop. Payload is ignored
+pub async fn start_estop_controller(
+    zenoh_session: Arc<Session>,
+    emergency_stop: EmergencyStop,
+) -> anyhow::Result<()> {
+    let estop_subscriber = zenoh_session
+        .declare_subscriber(HOPPER_ESTOP)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+
+    let clear_subscriber = zenoh_session
+        .declare_subscriber(HOPPER_ESTOP_CLEAR)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+
+    tokio::spawn(async move {
+        loop {
+            select! {
+                sample = estop_subscriber.recv_async() => {
+                    if sample.is_err() {
+                        break;
+                    }
+                    info!("Received emergency stop");
+                    emergency_stop.trigger();
+                }
+                sample = clear_subscriber.recv_async() => {
+                    if sample.is_err() {
+                        break;
+                    }
+                    info!("Received emergency stop clear");
+                    emergency_stop.clear();
+                }
+            }
+        }
+        error!("Emergency stop subscribers closed");
+    });
+    Ok(())
+}
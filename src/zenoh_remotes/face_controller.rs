@@ -44,15 +44,9 @@ pub async fn start_face_controller(
                         let color = color?;
                         let color: String = color.value.try_into()?;
                         info!("Received face color command {}", color);
-                        match color.to_lowercase().as_str() {
-                            "red" => selected_color = crate::face::driver::RED,
-                            "green" => selected_color = crate::face::driver::GREEN,
-                            "blue" => selected_color = crate::face::driver::BLUE,
-                            "yellow" => selected_color = crate::face::driver::YELLOW,
-                            "purple" => selected_color = crate::face::driver::PURPLE,
-                            "cyan" => selected_color = crate::face::driver::CYAN,
-                            "off" => selected_color = crate::face::driver::OFF,
-                            _ => error!("Unknown color {}", color),
+                        match color_from_name(&color) {
+                            Some(color) => selected_color = color,
+                            None => error!("Unknown color {}", color),
                         }
                         set_animation(&face_controller, &selected_animation, selected_color)?;
                     }
@@ -79,7 +73,20 @@ pub async fn start_face_controller(
     Ok(())
 }
 
-fn set_animation(
+pub(crate) fn color_from_name(color: &str) -> Option<crate::face::driver::RGB> {
+    match color.to_lowercase().as_str() {
+        "red" => Some(crate::face::driver::RED),
+        "green" => Some(crate::face::driver::GREEN),
+        "blue" => Some(crate::face::driver::BLUE),
+        "yellow" => Some(crate::face::driver::YELLOW),
+        "purple" => Some(crate::face::driver::PURPLE),
+        "cyan" => Some(crate::face::driver::CYAN),
+        "off" => Some(crate::face::driver::OFF),
+        _ => None,
+    }
+}
+
+pub(crate) fn set_animation(
     face_controller: &FaceController,
     animation: &str,
     color: crate::face::driver::RGB,
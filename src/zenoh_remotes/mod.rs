@@ -1,5 +1,8 @@
+pub mod estop_controller;
 pub mod face_controller;
 pub mod pose_publisher;
+pub mod proto_command;
 pub mod remote_controller;
 pub mod speech_controller;
+pub mod state_queryable;
 pub mod topic_consts;
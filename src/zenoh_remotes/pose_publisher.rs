@@ -1,22 +1,45 @@
 use std::sync::{Arc, Mutex};
 
-use crate::error::HopperResult;
-use crate::ik_controller::leg_positions::*;
+use crate::error::{HopperError, HopperResult};
+use crate::ik_controller::{leg_positions::*, odometry::Odometry};
+use crate::zenoh_remotes::topic_consts::{
+    HOPPER_ODOMETRY_FRAME, HOPPER_ODOMETRY_POSE, HOPPER_POSE_FRAMES,
+};
 use prost::Message;
 use tracing::*;
 use zenoh::prelude::r#async::*;
 
 pub struct ZenohPosePublisher {
     latest: Arc<Mutex<Option<LegPositions>>>,
+    odometry: Arc<Mutex<Odometry>>,
 }
 
 impl ZenohPosePublisher {
-    pub fn new(pose_publisher: zenoh::publication::Publisher<'static>) -> Self {
+    pub async fn new(
+        zenoh_session: Arc<Session>,
+        odometry: Arc<Mutex<Odometry>>,
+    ) -> HopperResult<Self> {
+        let pose_publisher = zenoh_session
+            .declare_publisher(HOPPER_POSE_FRAMES)
+            .res()
+            .await
+            .map_err(HopperError::ZenohError)?;
+        let odometry_pose_publisher = zenoh_session
+            .declare_publisher(HOPPER_ODOMETRY_POSE)
+            .res()
+            .await
+            .map_err(HopperError::ZenohError)?;
+        let odometry_frame_publisher = zenoh_session
+            .declare_publisher(HOPPER_ODOMETRY_FRAME)
+            .res()
+            .await
+            .map_err(HopperError::ZenohError)?;
+
         let latest = Arc::new(Mutex::new(None));
 
         tokio::spawn({
             let latest = latest.clone();
-            let pose_publisher = pose_publisher;
+            let odometry = odometry.clone();
             async move {
                 let mut interval = tokio::time::interval(std::time::Duration::from_millis(500));
                 interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
@@ -27,18 +50,35 @@ impl ZenohPosePublisher {
                             error!("Error publishing pose: {}", e);
                         }
                     }
+                    let odometry = odometry.lock().unwrap().clone();
+                    if let Err(e) = Self::publish_odometry(
+                        &odometry_pose_publisher,
+                        &odometry_frame_publisher,
+                        &odometry,
+                    )
+                    .await
+                    {
+                        error!("Error publishing odometry: {}", e);
+                    }
                     interval.tick().await;
                 }
             }
         });
 
-        Self { latest }
+        Ok(Self { latest, odometry })
     }
 
+    /// Set newly commanded pose
     pub fn set_pose(&self, pose: LegPositions) {
+        self.odometry.lock().unwrap().update(pose);
         self.latest.lock().unwrap().replace(pose);
     }
 
+    /// Motors were read back so commanded poses no longer follow each other
+    pub fn restart_odometry(&self) {
+        self.odometry.lock().unwrap().clear_reference();
+    }
+
     async fn publish_pose(
         pose_publisher: &zenoh::publication::Publisher<'static>,
         positions: LegPositions,
@@ -49,4 +89,20 @@ impl ZenohPosePublisher {
             .await?;
         Ok(())
     }
+
+    async fn publish_odometry(
+        odometry_pose_publisher: &zenoh::publication::Publisher<'static>,
+        odometry_frame_publisher: &zenoh::publication::Publisher<'static>,
+        odometry: &Odometry,
+    ) -> HopperResult<()> {
+        odometry_pose_publisher
+            .put(odometry.to_foxglove_pose_in_frame().encode_to_vec())
+            .res()
+            .await?;
+        odometry_frame_publisher
+            .put(odometry.to_foxglove_frame_transform().encode_to_vec())
+            .res()
+            .await?;
+        Ok(())
+    }
 }
@@ -0,0 +1,197 @@
+//! Typed commands from the versioned schema in `proto/hopper/command`
+//!
+//! Commands are protobuf encoded on [`HOPPER_COMMAND_V1`]. The same messages are
+//! transcoded from JSON on [`HOPPER_COMMAND_V1_JSON`] for manual use.
+//!
+//! [`HOPPER_COMMAND_V1`]: crate::zenoh_remotes::topic_consts::HOPPER_COMMAND_V1
+//! [`HOPPER_COMMAND_V1_JSON`]: crate::zenoh_remotes::topic_consts::HOPPER_COMMAND_V1_JSON
+
+use prost::Message;
+use prost_reflect::{DynamicMessage, ReflectMessage};
+use std::time::Duration;
+
+use crate::{
+    face::driver::{PURPLE, RGB},
+    hopper::command::v1 as proto,
+    motion_controller::{
+        gait::GaitType,
+        walking::{
+            MoveCommand, DEFAULT_MAX_YAW_RATE_DEG, DEFAULT_STEP_HEIGHT, DEFAULT_STEP_TIME,
+            FAST_STEP_DISTANCE, FAST_STEP_TIME,
+        },
+        BodyState, DanceMove,
+    },
+};
+
+pub fn decode_command(payload: &[u8]) -> anyhow::Result<proto::HopperCommand> {
+    Ok(proto::HopperCommand::decode(payload)?)
+}
+
+/// Parse command using the protobuf JSON mapping
+pub fn decode_json_command(json: &str) -> anyhow::Result<proto::HopperCommand> {
+    let descriptor = proto::HopperCommand::default().descriptor();
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+    let message = DynamicMessage::deserialize(descriptor, &mut deserializer)?;
+    deserializer.end()?;
+    Ok(message.transcode_to()?)
+}
+
+pub fn body_state_from_proto(body_state: proto::BodyState) -> Option<BodyState> {
+    match body_state {
+        proto::BodyState::Unspecified => None,
+        proto::BodyState::Standing => Some(BodyState::Standing),
+        proto::BodyState::Grounded => Some(BodyState::Grounded),
+        proto::BodyState::Folded => Some(BodyState::Folded),
+    }
+}
+
+/// Keeps value within `-limit..=limit`. Values that aren't finite become zero
+fn clamp_symmetric(value: f32, limit: f32) -> f32 {
+    if value.is_finite() {
+        value.clamp(-limit, limit)
+    } else {
+        0.0
+    }
+}
+
+/// Zero values are replaced by defaults
+///
+/// Values are clamped to the ranges the gamepad can produce
+pub fn move_command_from_proto(command: &proto::MoveCommand) -> MoveCommand {
+    let step_time = if command.step_time_ms == 0 {
+        DEFAULT_STEP_TIME
+    } else {
+        Duration::from_millis(command.step_time_ms as u64).clamp(FAST_STEP_TIME, DEFAULT_STEP_TIME)
+    };
+    let step_height = if command.step_height == 0.0 || !command.step_height.is_finite() {
+        DEFAULT_STEP_HEIGHT
+    } else {
+        command.step_height.clamp(0.0, DEFAULT_STEP_HEIGHT)
+    };
+    let direction = nalgebra::Vector2::new(
+        clamp_symmetric(command.direction_x, FAST_STEP_DISTANCE),
+        clamp_symmetric(command.direction_y, FAST_STEP_DISTANCE),
+    );
+    let rotation = clamp_symmetric(command.rotation, DEFAULT_MAX_YAW_RATE_DEG.to_radians());
+    let gait = match command.gait() {
+        proto::GaitType::Unspecified => GaitType::default(),
+        proto::GaitType::Tripod => GaitType::Tripod,
+        proto::GaitType::Ripple => GaitType::Ripple,
+        proto::GaitType::Wave => GaitType::Wave,
+    };
+    MoveCommand::with_optional_fields(
+        direction,
+        rotation,
+        step_time,
+        step_height,
+        command.aggressive_leg_lift,
+    )
+    .with_gait(gait)
+}
+
+pub fn dance_move_from_proto(dance_move: proto::DanceMove) -> Option<DanceMove> {
+    match dance_move {
+        proto::DanceMove::Unspecified => None,
+        proto::DanceMove::Random => Some(DanceMove::Random),
+        proto::DanceMove::HappyDance => Some(DanceMove::HappyDance),
+        proto::DanceMove::SadEmote => Some(DanceMove::SadEmote),
+        proto::DanceMove::WaveHi => Some(DanceMove::WaveHi),
+        proto::DanceMove::Roar => Some(DanceMove::Roar),
+        proto::DanceMove::CombatCry => Some(DanceMove::CombatCry),
+    }
+}
+
+/// Animation name as accepted on the face animation topic
+pub fn face_animation_name(animation: proto::FaceAnimation) -> Option<String> {
+    if animation == proto::FaceAnimation::Unspecified {
+        return None;
+    }
+    animation
+        .as_str_name()
+        .strip_prefix("FACE_ANIMATION_")
+        .map(str::to_lowercase)
+}
+
+/// Purple if color is unset
+pub fn color_from_proto(color: Option<&proto::Color>) -> RGB {
+    let channel = |value: u32| value.min(u8::MAX as u32) as u8;
+    color
+        .map(|color| {
+            RGB::new(
+                channel(color.red),
+                channel(color.green),
+                channel(color.blue),
+            )
+        })
+        .unwrap_or(PURPLE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn protobuf_roundtrip() {
+        let command = proto::HopperCommand {
+            command: Some(proto::hopper_command::Command::SetBodyState(
+                proto::SetBodyState {
+                    body_state: proto::BodyState::Standing as i32,
+                },
+            )),
+        };
+        assert_eq!(decode_command(&command.encode_to_vec()).unwrap(), command);
+    }
+
+    #[test]
+    fn json_move_command_uses_defaults() {
+        let command =
+            decode_json_command(r#"{"move": {"directionX": 0.02, "gait": "GAIT_TYPE_WAVE"}}"#)
+                .unwrap();
+        let move_command = match command.command {
+            Some(proto::hopper_command::Command::Move(move_command)) => {
+                move_command_from_proto(&move_command)
+            }
+            other => panic!("Unexpected command {:?}", other),
+        };
+        assert_relative_eq!(move_command.direction().x, 0.02);
+        assert_eq!(move_command.step_time(), DEFAULT_STEP_TIME);
+        assert_relative_eq!(move_command.step_height(), DEFAULT_STEP_HEIGHT);
+        assert_eq!(move_command.gait(), GaitType::Wave);
+    }
+
+    #[test]
+    fn move_command_is_clamped_to_gamepad_ranges() {
+        let command = proto::MoveCommand {
+            direction_x: 1.0,
+            direction_y: f32::NAN,
+            rotation: -10.0,
+            step_time_ms: 50,
+            step_height: 0.5,
+            ..Default::default()
+        };
+        let move_command = move_command_from_proto(&command);
+        assert_relative_eq!(move_command.direction().x, FAST_STEP_DISTANCE);
+        assert_relative_eq!(move_command.direction().y, 0.0);
+        assert_relative_eq!(
+            move_command.rotation(),
+            -DEFAULT_MAX_YAW_RATE_DEG.to_radians()
+        );
+        assert_eq!(move_command.step_time(), FAST_STEP_TIME);
+        assert_relative_eq!(move_command.step_height(), DEFAULT_STEP_HEIGHT);
+    }
+
+    #[test]
+    fn json_rejects_unknown_fields() {
+        assert!(decode_json_command(r#"{"jump": {}}"#).is_err());
+    }
+
+    #[test]
+    fn face_animation_names_match_topic() {
+        assert_eq!(
+            face_animation_name(proto::FaceAnimation::LarsonScanner).as_deref(),
+            Some("larson_scanner")
+        );
+        assert_eq!(face_animation_name(proto::FaceAnimation::Unspecified), None);
+    }
+}
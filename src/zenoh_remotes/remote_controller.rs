@@ -1,24 +1,40 @@
 use crate::body_controller::motor_controller::{HexapodCompliance, HexapodMotorSpeed};
+use crate::command_watchdog::{CommandSource, CommandWatchdog, CommandWatchdogStatus};
+use crate::configuration::{ChoreographyButton, CommandWatchdogConfig};
 use crate::error::HopperResult;
+use crate::face::FaceController;
 use crate::hexapod::LegFlags;
 use crate::high_five::HighFiveServiceController;
+use crate::hopper::command::v1::{self as proto, hopper_command::Command};
+use crate::input::{Button, ESTOP_BUTTONS, ESTOP_CLEAR_BUTTONS};
 use crate::ioc_container::IocContainer;
 use crate::lidar::LidarServiceController;
+use crate::motion_controller::choreography::ChoreographyLibrary;
+use crate::motion_controller::gait::GaitType;
 use crate::motion_controller::walking::{
-    DEFAULT_STEP_DISTANCE, DEFAULT_STEP_HEIGHT, DEFAULT_STEP_TIME,
+    DEFAULT_MAX_YAW_RATE_DEG, DEFAULT_STEP_DISTANCE, DEFAULT_STEP_HEIGHT, DEFAULT_STEP_TIME,
+    FAST_STEP_DISTANCE, FAST_STEP_TIME,
 };
 use crate::motion_controller::{self, SingleLegCommand};
+use crate::obstacle_guard::{ObstacleGuard, ObstacleGuardStatus};
 use crate::speech::SpeechService;
+use crate::zenoh_remotes::face_controller::set_animation;
+use crate::zenoh_remotes::proto_command::{
+    body_state_from_proto, color_from_proto, dance_move_from_proto, decode_command,
+    decode_json_command, face_animation_name, move_command_from_proto,
+};
 use crate::zenoh_remotes::topic_consts::{
-    BODY_MOTOR_SPEED_SUBSCRIBER, COMPLIANCE_SLOPE_SUBSCRIBER, HOPPER_WALKING_CONFIG_PUBLISHER,
-    REMOTE_CONTROL_SUBSCRIBER, STANCE_SUBSCRIBER, WALKING_CONFIG_SUBSCRIBER,
+    BODY_MOTOR_SPEED_SUBSCRIBER, COMPLIANCE_SLOPE_SUBSCRIBER, HOPPER_COMMAND_V1,
+    HOPPER_COMMAND_V1_JSON, HOPPER_COMMAND_WATCHDOG_STATUS, HOPPER_OBSTACLE_GUARD_STATUS,
+    HOPPER_STATE_WALKING_CONFIG, HOPPER_WALKING_CONFIG_PUBLISHER, REMOTE_CONTROL_SUBSCRIBER,
+    STANCE_SUBSCRIBER, WALKING_CONFIG_SUBSCRIBER,
 };
 use crate::{error::HopperError, motion_controller::walking::MoveCommand};
 use chrono::{DateTime, Utc};
 use gilrs::{GilrsBuilder, PowerInfo};
 use nalgebra::{UnitQuaternion, Vector2, Vector3};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{collections::BTreeMap, sync::Arc};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tracing::*;
@@ -29,6 +45,9 @@ pub enum ScheduledCommand {
     WaitCommand(Duration),
 }
 
+/// Scheduled moves are repeated this often so the command watchdog doesn't stop them
+const SCHEDULED_MOVE_REPEAT_PERIOD: Duration = Duration::from_millis(200);
+
 pub struct MoveService {
     sender: tokio::sync::mpsc::Sender<MoveCommand>,
     scheduled_sender: tokio::sync::mpsc::Sender<ScheduledCommand>,
@@ -42,15 +61,30 @@ impl MoveService {
         tokio::spawn({
             let command_sender = sender.clone();
             async move {
+                let mut last_move_command = None;
                 while let Some(command) = scheduled_receiver.recv().await {
                     match command {
                         ScheduledCommand::MoveCommand(move_command) => {
                             info!("Executing scheduled move command {:?}", move_command);
+                            last_move_command = Some(move_command);
                             command_sender.send(move_command).await.unwrap();
                         }
                         ScheduledCommand::WaitCommand(time) => {
                             info!("Executing scheduled sleep {:?}", time);
-                            tokio::time::sleep(time).await
+                            let sleep = tokio::time::sleep(time);
+                            tokio::pin!(sleep);
+                            let mut repeat_interval =
+                                tokio::time::interval(SCHEDULED_MOVE_REPEAT_PERIOD);
+                            loop {
+                                tokio::select! {
+                                    _ = &mut sleep => break,
+                                    _ = repeat_interval.tick() => {
+                                        if let Some(move_command) = last_move_command {
+                                            command_sender.send(move_command).await.unwrap();
+                                        }
+                                    }
+                                }
+                            }
                         }
                     }
                 }
@@ -81,6 +115,8 @@ pub async fn simple_zenoh_controller(
     motion_controller: &mut motion_controller::MotionController,
     zenoh_session: Arc<zenoh::Session>,
     mut move_command_receiver: tokio::sync::mpsc::Receiver<MoveCommand>,
+    choreography_buttons: Vec<ChoreographyButton>,
+    watchdog_config: CommandWatchdogConfig,
 ) -> anyhow::Result<()> {
     info!("Starting simple zenoh controller");
     let stance_subscriber = zenoh_session
@@ -113,11 +149,54 @@ pub async fn simple_zenoh_controller(
         .await
         .map_err(HopperError::ZenohError)?;
 
+    let command_subscriber = zenoh_session
+        .declare_subscriber(HOPPER_COMMAND_V1)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+
+    let json_command_subscriber = zenoh_session
+        .declare_subscriber(HOPPER_COMMAND_V1_JSON)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+
+    // walking config lives here so it's answered here instead of the state queryable
+    let walking_config_queryable = zenoh_session
+        .declare_queryable(HOPPER_STATE_WALKING_CONFIG)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+
+    let obstacle_guard_status_publisher = zenoh_session
+        .declare_publisher(HOPPER_OBSTACLE_GUARD_STATUS)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+
+    let watchdog_status_publisher = zenoh_session
+        .declare_publisher(HOPPER_COMMAND_WATCHDOG_STATUS)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+
+    let mut guarded_commander = GuardedCommander::new(
+        IocContainer::global_instance().service::<ObstacleGuard>()?,
+        obstacle_guard_status_publisher,
+        CommandWatchdog::new(watchdog_config),
+        watchdog_status_publisher,
+    );
+    let mut obstacle_check_interval = tokio::time::interval(OBSTACLE_CHECK_PERIOD);
+    obstacle_check_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
     let mut controller_reader = start_controller_reader();
 
     let mut last_gamepad_message: Option<InputMessage> = None;
 
-    let mut gamepad_controller = GamepadController::default();
+    let mut gamepad_controller = GamepadController {
+        choreography_buttons,
+        ..Default::default()
+    };
     gamepad_controller
         .publish_walking_config(&zenoh_session)
         .await?;
@@ -129,6 +208,21 @@ pub async fn simple_zenoh_controller(
                 let sample = sample?;
                 handle_stance_command(sample, motion_controller).await?;
             }
+            sample = command_subscriber.recv_async() => {
+                let sample = sample?;
+                match decode_command(&sample.value.payload.contiguous()) {
+                    Ok(command) => handle_proto_command(command, motion_controller, &mut guarded_commander).await?,
+                    Err(error) => error!("Failed to decode command: {}", error),
+                }
+            }
+            sample = json_command_subscriber.recv_async() => {
+                let sample = sample?;
+                let json: String = sample.value.try_into()?;
+                match decode_json_command(&json) {
+                    Ok(command) => handle_proto_command(command, motion_controller, &mut guarded_commander).await?,
+                    Err(error) => error!("Failed to decode json command: {}", error),
+                }
+            }
             sample = compliance_slope_subscriber.recv_async() => {
                 let sample = sample?;
                 handle_compliance_slope_command(sample, motion_controller).await?;
@@ -142,22 +236,35 @@ pub async fn simple_zenoh_controller(
                 gamepad_controller.handle_walking_config_update(sample).await?;
                 gamepad_controller.publish_walking_config(&zenoh_session).await?;
             }
+            query = walking_config_queryable.recv_async() => {
+                let query = query?;
+                let message = serde_json::to_string(&gamepad_controller.walking_config)?;
+                let key_expr = KeyExpr::try_from(HOPPER_STATE_WALKING_CONFIG)?;
+                query
+                    .reply(Ok(Sample::new(key_expr, message)))
+                    .res()
+                    .await
+                    .map_err(HopperError::ZenohError)?;
+            }
             gamepad_message = gamepad_subscriber.recv_async() => {
                 trace!("got new gamepad message");
                 let gamepad_message = gamepad_message?;
-                gamepad_controller.handle_gamepad_command_zenoh(gamepad_message, motion_controller, &mut last_gamepad_message).await?;
+                gamepad_controller.handle_gamepad_command_zenoh(gamepad_message, motion_controller, &mut guarded_commander, &mut last_gamepad_message).await?;
             }
             controller_message = controller_reader.recv() => {
                 trace!("got new controller message");
                 if let Some(controller_message) = controller_message {
-                    gamepad_controller.handle_gamepad_command(controller_message, motion_controller, &mut last_gamepad_message).await?;
+                    gamepad_controller.handle_gamepad_command(controller_message, CommandSource::LocalGamepad, motion_controller, &mut guarded_commander, &mut last_gamepad_message).await?;
                 }
             }
             move_command = move_command_receiver.recv() => {
                 if let Some(move_command) = move_command {
-                    motion_controller.set_command(move_command);
+                    guarded_commander.set_command(motion_controller, CommandSource::MoveService, move_command).await?;
                 }
             }
+            _ = obstacle_check_interval.tick() => {
+                guarded_commander.refresh(motion_controller).await?;
+            }
             _ = tokio::signal::ctrl_c() => {
                 info!("Got ctrl-c");
                 break;
@@ -168,6 +275,112 @@ pub async fn simple_zenoh_controller(
     Ok(())
 }
 
+/// Obstacle guard and command watchdog are checked this often
+const OBSTACLE_CHECK_PERIOD: Duration = Duration::from_millis(100);
+
+/// Passes walking commands through the command watchdog and obstacle guard
+/// before they reach the motion controller
+struct GuardedCommander {
+    obstacle_guard: Arc<ObstacleGuard>,
+    status_publisher: zenoh::publication::Publisher<'static>,
+    watchdog: CommandWatchdog,
+    watchdog_status_publisher: zenoh::publication::Publisher<'static>,
+    requested_command: MoveCommand,
+    last_status: Option<ObstacleGuardStatus>,
+    last_watchdog_status: Option<CommandWatchdogStatus>,
+}
+
+impl GuardedCommander {
+    fn new(
+        obstacle_guard: Arc<ObstacleGuard>,
+        status_publisher: zenoh::publication::Publisher<'static>,
+        watchdog: CommandWatchdog,
+        watchdog_status_publisher: zenoh::publication::Publisher<'static>,
+    ) -> Self {
+        Self {
+            obstacle_guard,
+            status_publisher,
+            watchdog,
+            watchdog_status_publisher,
+            requested_command: MoveCommand::default(),
+            last_status: None,
+            last_watchdog_status: None,
+        }
+    }
+
+    async fn set_command(
+        &mut self,
+        controller: &mut motion_controller::MotionController,
+        source: CommandSource,
+        command: MoveCommand,
+    ) -> anyhow::Result<()> {
+        self.watchdog.command_received(source, Instant::now());
+        self.requested_command = command;
+        let (watched_command, watchdog_status) = self.watchdog.limit(command, Instant::now());
+        let (limited_command, status) = self.obstacle_guard.limit(watched_command);
+        controller.set_command(limited_command);
+        self.publish_watchdog_status(watchdog_status).await?;
+        self.publish_status(status).await
+    }
+
+    /// Source is still connected even though it sent no new command
+    fn feed(&mut self, source: CommandSource) {
+        self.watchdog.feed(source, Instant::now());
+    }
+
+    /// Check the last requested command against new scans and the watchdog
+    async fn refresh(
+        &mut self,
+        controller: &mut motion_controller::MotionController,
+    ) -> anyhow::Result<()> {
+        let (watched_command, watchdog_status) =
+            self.watchdog.limit(self.requested_command, Instant::now());
+        let (limited_command, status) = self.obstacle_guard.limit(watched_command);
+        if controller.get_command() != limited_command {
+            controller.set_command(limited_command);
+        }
+        self.publish_watchdog_status(watchdog_status).await?;
+        self.publish_status(status).await
+    }
+
+    /// Publish watchdog status only when it changes
+    async fn publish_watchdog_status(
+        &mut self,
+        status: CommandWatchdogStatus,
+    ) -> anyhow::Result<()> {
+        if self.last_watchdog_status == Some(status) {
+            return Ok(());
+        }
+        if self.last_watchdog_status.map(|last| last.state) != Some(status.state) {
+            info!(?status, "Command watchdog state changed");
+        }
+        self.last_watchdog_status = Some(status);
+        self.watchdog_status_publisher
+            .put(serde_json::to_string(&status)?)
+            .res()
+            .await
+            .map_err(HopperError::ZenohError)?;
+        Ok(())
+    }
+
+    /// Publish status only when it changes
+    async fn publish_status(&mut self, status: ObstacleGuardStatus) -> anyhow::Result<()> {
+        if self.last_status == Some(status) {
+            return Ok(());
+        }
+        if status.requested_direction != status.limited_direction {
+            info!(?status, "Walking limited by obstacle guard");
+        }
+        self.last_status = Some(status);
+        self.status_publisher
+            .put(serde_json::to_string(&status)?)
+            .res()
+            .await
+            .map_err(HopperError::ZenohError)?;
+        Ok(())
+    }
+}
+
 async fn handle_stance_command(
     message: zenoh::sample::Sample,
     controller: &mut motion_controller::MotionController,
@@ -191,12 +404,100 @@ async fn handle_stance_command(
         controller.start_sequence(motion_controller::DanceMove::SadEmote);
     } else if &command.to_lowercase() == "combat_cry" {
         controller.start_sequence(motion_controller::DanceMove::CombatCry);
-    } else {
+    } else if !start_named_choreography(controller, &command)? {
         error!("Unknown command {}", command);
     }
     Ok(())
 }
 
+async fn handle_proto_command(
+    command: proto::HopperCommand,
+    controller: &mut motion_controller::MotionController,
+    guarded_commander: &mut GuardedCommander,
+) -> anyhow::Result<()> {
+    debug!(?command, "Received command");
+    match command.command {
+        Some(Command::SetBodyState(set_body_state)) => {
+            match body_state_from_proto(set_body_state.body_state()) {
+                Some(body_state) => controller.set_body_state(body_state),
+                None => error!("Body state is unspecified"),
+            }
+        }
+        Some(Command::Move(move_command)) => {
+            guarded_commander
+                .set_command(
+                    controller,
+                    CommandSource::TypedCommand,
+                    move_command_from_proto(&move_command),
+                )
+                .await?;
+        }
+        Some(Command::StartDance(start_dance)) => match start_dance.dance {
+            Some(proto::start_dance::Dance::DanceMove(dance_move)) => {
+                match proto::DanceMove::from_i32(dance_move).and_then(dance_move_from_proto) {
+                    Some(dance_move) => controller.start_sequence(dance_move),
+                    None => error!("Unknown dance move {}", dance_move),
+                }
+            }
+            Some(proto::start_dance::Dance::Choreography(name)) => {
+                if !start_named_choreography(controller, &name)? {
+                    error!("Unknown choreography {}", name);
+                }
+            }
+            None => error!("Dance is unspecified"),
+        },
+        Some(Command::SetFace(set_face)) => match face_animation_name(set_face.animation()) {
+            Some(animation) => set_animation(
+                &IocContainer::global_instance().service::<FaceController>()?,
+                &animation,
+                color_from_proto(set_face.color.as_ref()),
+            )?,
+            None => error!("Face animation is unspecified"),
+        },
+        Some(Command::Say(say)) => {
+            let speech_service = IocContainer::global_instance().service::<SpeechService>()?;
+            // speech takes a while so don't block commands
+            tokio::spawn(async move {
+                let result: anyhow::Result<()> = match say.speech {
+                    Some(proto::say::Speech::Text(text)) => speech_service.say_azure(&text).await,
+                    Some(proto::say::Speech::Astromech(text)) => {
+                        Ok(speech_service.say_astromech(&text).await?)
+                    }
+                    Some(proto::say::Speech::Sound(sound)) => {
+                        Ok(speech_service.play_sound(&sound).await?)
+                    }
+                    None => Err(anyhow::anyhow!("Speech is unspecified")),
+                };
+                if let Err(error) = result {
+                    error!("Failed to speak: {}", error);
+                }
+            });
+        }
+        None => error!("Command is empty"),
+    }
+    Ok(())
+}
+
+/// Start choreography from the loaded library
+///
+/// Returns false if no choreography with that name exists
+fn start_named_choreography(
+    controller: &mut motion_controller::MotionController,
+    name: &str,
+) -> anyhow::Result<bool> {
+    let choreography = IocContainer::global_instance()
+        .service::<ChoreographyLibrary>()?
+        .get(name);
+    match choreography {
+        Some(choreography) => {
+            info!("Starting choreography {}", name);
+            controller.start_choreography(choreography);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
 async fn handle_compliance_slope_command(
     message: zenoh::sample::Sample,
     controller: &mut motion_controller::MotionController,
@@ -224,6 +525,7 @@ struct GamepadController {
     feet_iterator: std::iter::Cycle<std::vec::IntoIter<LegFlags>>,
     height_offset: f32,
     last_gamepad_event_time: DateTime<Utc>,
+    choreography_buttons: Vec<ChoreographyButton>,
 }
 
 impl Default for GamepadController {
@@ -248,6 +550,7 @@ impl Default for GamepadController {
             feet_iterator,
             height_offset: 0.0,
             last_gamepad_event_time: Utc::now(),
+            choreography_buttons: vec![],
         }
     }
 }
@@ -278,18 +581,27 @@ impl GamepadController {
         &mut self,
         gamepad_message: zenoh::sample::Sample,
         controller: &mut motion_controller::MotionController,
+        guarded_commander: &mut GuardedCommander,
         last_gamepad_message: &mut Option<InputMessage>,
     ) -> anyhow::Result<()> {
         let gamepad_message: String = gamepad_message.value.try_into()?;
         let gamepad_message: InputMessage = serde_json::from_str(&gamepad_message)?;
-        self.handle_gamepad_command(gamepad_message, controller, last_gamepad_message)
-            .await
+        self.handle_gamepad_command(
+            gamepad_message,
+            CommandSource::RemoteGamepad,
+            controller,
+            guarded_commander,
+            last_gamepad_message,
+        )
+        .await
     }
 
     async fn handle_gamepad_command(
         &mut self,
         input_message: InputMessage,
+        source: CommandSource,
         controller: &mut motion_controller::MotionController,
+        guarded_commander: &mut GuardedCommander,
         last_input_message: &mut Option<InputMessage>,
     ) -> anyhow::Result<()> {
         let found = input_message
@@ -303,6 +615,11 @@ impl GamepadController {
             return Ok(());
         };
 
+        // gamepad keeps publishing without events while a stick is held
+        if gamepad_message.connected {
+            guarded_commander.feed(source);
+        }
+
         // skip outdated messages
         if gamepad_message.last_event_time <= self.last_gamepad_event_time {
             return Ok(());
@@ -313,6 +630,23 @@ impl GamepadController {
             .as_ref()
             .and_then(|input_message| input_message.gamepads.get(index));
 
+        // emergency stop combos skip every other action
+        let all_down = |buttons: &[Button]| {
+            buttons
+                .iter()
+                .all(|button| is_button_down(*button, gamepad_message))
+        };
+        if all_down(&ESTOP_BUTTONS) {
+            controller.emergency_stop().trigger();
+            *last_input_message = Some(input_message.clone());
+            return Ok(());
+        }
+        if all_down(&ESTOP_CLEAR_BUTTONS) {
+            controller.emergency_stop().clear();
+            *last_input_message = Some(input_message.clone());
+            return Ok(());
+        }
+
         let a_pressed = was_button_pressed_since_last_time(
             Button::South,
             gamepad_message,
@@ -391,9 +725,18 @@ impl GamepadController {
             let desired_state = !is_active;
             info!("Toggling high five controller to {}", desired_state);
             high_five_controller.set_active(desired_state);
-            IocContainer::global_instance()
+            let lidar_changed = IocContainer::global_instance()
                 .service::<LidarServiceController>()?
                 .set_active(desired_state);
+            if !lidar_changed {
+                tokio::spawn(async move {
+                    _ = IocContainer::global_instance()
+                        .service::<SpeechService>()
+                        .expect("Failed to get speech service")
+                        .say_eleven_with_default_voice("Lidar stays on for obstacle guard")
+                        .await;
+                });
+            }
         }
 
         // clamp
@@ -428,9 +771,22 @@ impl GamepadController {
             info!("Starting random dance");
             controller.start_sequence(motion_controller::DanceMove::Random);
         }
+        for choreography_button in &self.choreography_buttons {
+            if was_button_pressed_since_last_time(
+                choreography_button.button,
+                gamepad_message,
+                last_gamepad_message,
+            ) && !start_named_choreography(controller, &choreography_button.choreography)?
+            {
+                error!(
+                    "Unknown choreography {} mapped to {:?}",
+                    choreography_button.choreography, choreography_button.button
+                );
+            }
+        }
         if left_paddle_pressed {
-            self.walking_config.max_step_distance_m = 0.03;
-            self.walking_config.step_time = Duration::from_millis(400);
+            self.walking_config.max_step_distance_m = FAST_STEP_DISTANCE;
+            self.walking_config.step_time = FAST_STEP_TIME;
             tokio::spawn(async move {
                 _ = IocContainer::global_instance()
                     .service::<SpeechService>()
@@ -486,13 +842,16 @@ impl GamepadController {
                 self.walking_config.step_time,
                 self.walking_config.step_height_m,
                 self.walking_config.aggressive_leg_lift,
-            );
+            )
+            .with_gait(self.walking_config.gait);
 
             controller.set_transformation(
                 Vector3::new(0.0, 0.0, -self.height_offset),
                 Default::default(),
             );
-            controller.set_command(move_command);
+            guarded_commander
+                .set_command(controller, source, move_command)
+                .await?;
         } else if lt_down {
             let x = get_axis(Axis::LeftStickY, gamepad_message) * 0.07;
             let y = -get_axis(Axis::LeftStickX, gamepad_message) * 0.07;
@@ -508,13 +867,20 @@ impl GamepadController {
                 Vector3::new(0.0, 0.0, -self.height_offset),
                 Default::default(),
             );
-            controller.set_command(MoveCommand::with_optional_fields(
-                Vector2::zeros(),
-                0.0,
-                self.walking_config.step_time,
-                self.walking_config.step_height_m,
-                self.walking_config.aggressive_leg_lift,
-            ));
+            guarded_commander
+                .set_command(
+                    controller,
+                    source,
+                    MoveCommand::with_optional_fields(
+                        Vector2::zeros(),
+                        0.0,
+                        self.walking_config.step_time,
+                        self.walking_config.step_height_m,
+                        self.walking_config.aggressive_leg_lift,
+                    )
+                    .with_gait(self.walking_config.gait),
+                )
+                .await?;
         }
 
         *last_input_message = Some(input_message);
@@ -579,85 +945,6 @@ pub struct GamepadMessage {
     last_event_time: DateTime<Utc>,
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
-pub enum Button {
-    South,
-    East,
-    North,
-    West,
-    C,
-    Z,
-    LeftTrigger,
-    LeftTrigger2,
-    RightTrigger,
-    RightTrigger2,
-    Select,
-    Start,
-    Mode,
-    LeftThumb,
-    RightThumb,
-    DPadUp,
-    DPadDown,
-    DPadLeft,
-    DPadRight,
-    Unknown,
-    LeftPaddle,
-    RightPaddle,
-}
-
-impl Button {
-    pub fn all_gilrs_buttons() -> &'static [gilrs::ev::Button] {
-        &[
-            gilrs::ev::Button::South,
-            gilrs::ev::Button::East,
-            gilrs::ev::Button::North,
-            gilrs::ev::Button::West,
-            gilrs::ev::Button::C,
-            gilrs::ev::Button::Z,
-            gilrs::ev::Button::LeftTrigger,
-            gilrs::ev::Button::LeftTrigger2,
-            gilrs::ev::Button::RightTrigger,
-            gilrs::ev::Button::RightTrigger2,
-            gilrs::ev::Button::Select,
-            gilrs::ev::Button::Start,
-            gilrs::ev::Button::Mode,
-            gilrs::ev::Button::LeftThumb,
-            gilrs::ev::Button::RightThumb,
-            gilrs::ev::Button::DPadUp,
-            gilrs::ev::Button::DPadDown,
-            gilrs::ev::Button::DPadLeft,
-            gilrs::ev::Button::DPadRight,
-        ]
-    }
-}
-
-impl From<gilrs::ev::Button> for Button {
-    fn from(value: gilrs::ev::Button) -> Self {
-        match value {
-            gilrs::ev::Button::South => Button::South,
-            gilrs::ev::Button::East => Button::East,
-            gilrs::ev::Button::North => Button::North,
-            gilrs::ev::Button::West => Button::West,
-            gilrs::ev::Button::C => Button::C,
-            gilrs::ev::Button::Z => Button::Z,
-            gilrs::ev::Button::LeftTrigger => Button::LeftTrigger,
-            gilrs::ev::Button::LeftTrigger2 => Button::LeftTrigger2,
-            gilrs::ev::Button::RightTrigger => Button::RightTrigger,
-            gilrs::ev::Button::RightTrigger2 => Button::RightTrigger2,
-            gilrs::ev::Button::Select => Button::Select,
-            gilrs::ev::Button::Start => Button::Start,
-            gilrs::ev::Button::Mode => Button::Mode,
-            gilrs::ev::Button::LeftThumb => Button::LeftThumb,
-            gilrs::ev::Button::RightThumb => Button::RightThumb,
-            gilrs::ev::Button::DPadUp => Button::DPadUp,
-            gilrs::ev::Button::DPadDown => Button::DPadDown,
-            gilrs::ev::Button::DPadLeft => Button::DPadLeft,
-            gilrs::ev::Button::DPadRight => Button::DPadRight,
-            gilrs::ev::Button::Unknown => Button::Unknown,
-        }
-    }
-}
-
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
 pub enum Axis {
     LeftStickX,
@@ -694,6 +981,7 @@ pub struct WalkingConfig {
     step_height_m: f32,
     max_yaw_rate_deg: f32,
     aggressive_leg_lift: bool,
+    gait: GaitType,
 }
 
 impl Default for WalkingConfig {
@@ -702,8 +990,9 @@ impl Default for WalkingConfig {
             max_step_distance_m: DEFAULT_STEP_DISTANCE,
             step_time: DEFAULT_STEP_TIME,
             step_height_m: DEFAULT_STEP_HEIGHT,
-            max_yaw_rate_deg: 15.0,
+            max_yaw_rate_deg: DEFAULT_MAX_YAW_RATE_DEG,
             aggressive_leg_lift: false,
+            gait: GaitType::Tripod,
         }
     }
 }
@@ -725,6 +1014,9 @@ impl WalkingConfig {
         if let Some(aggressive_leg_lift) = msg.aggressive_leg_lift {
             self.aggressive_leg_lift = aggressive_leg_lift;
         }
+        if let Some(gait) = msg.gait {
+            self.gait = gait;
+        }
     }
 }
 
@@ -740,6 +1032,8 @@ pub struct WalkingConfigMsg {
     max_yaw_rate_deg: Option<f32>,
     #[serde(default)]
     aggressive_leg_lift: Option<bool>,
+    #[serde(default)]
+    gait: Option<GaitType>,
 }
 
 fn start_controller_reader() -> Receiver<InputMessage> {
@@ -0,0 +1,137 @@
+//! Current state answered on request
+//!
+//! Publishers only send state on change so new clients can query it instead.
+//! `z_get -s 'hopper/state/**'` returns every value as JSON at once.
+//! Walking config is owned by the remote controller which answers it on its own.
+
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tracing::*;
+use zenoh::{prelude::r#async::*, queryable::Query};
+
+use crate::{
+    error::HopperError,
+    face::{animations::Animation, FaceController},
+    high_five::HighFiveServiceController,
+    ioc_container::IocContainer,
+    lidar::LidarServiceController,
+    monitoring::DiagnosticsService,
+    motion_controller::{MotionControllerService, MotionControllerStatus},
+    zenoh_remotes::topic_consts::{
+        HOPPER_STATE_BODY_PHASE, HOPPER_STATE_BODY_STATE, HOPPER_STATE_DIAGNOSTICS,
+        HOPPER_STATE_FACE_ANIMATION, HOPPER_STATE_HIGH_FIVE_ACTIVE, HOPPER_STATE_LIDAR_ACTIVE,
+        HOPPER_STATE_MOVE_COMMAND, HOPPER_STATE_QUERYABLE,
+    },
+};
+
+const STATE_KEYS: [&str; 7] = [
+    HOPPER_STATE_BODY_STATE,
+    HOPPER_STATE_BODY_PHASE,
+    HOPPER_STATE_MOVE_COMMAND,
+    HOPPER_STATE_FACE_ANIMATION,
+    HOPPER_STATE_LIDAR_ACTIVE,
+    HOPPER_STATE_HIGH_FIVE_ACTIVE,
+    HOPPER_STATE_DIAGNOSTICS,
+];
+
+/// Services are looked up in the IoC container on every query
+pub async fn start_state_queryable(zenoh_session: Arc<Session>) -> anyhow::Result<()> {
+    let queryable = zenoh_session
+        .declare_queryable(HOPPER_STATE_QUERYABLE)
+        .res()
+        .await
+        .map_err(HopperError::ZenohError)?;
+
+    tokio::spawn(async move {
+        while let Ok(query) = queryable.recv_async().await {
+            if let Err(error) = reply_state(&query).await {
+                error!(
+                    "Failed to answer state query {}: {}",
+                    query.selector(),
+                    error
+                );
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn reply_state(query: &Query) -> anyhow::Result<()> {
+    // read once so that body state and move command are consistent
+    let status = IocContainer::global_instance()
+        .service::<MotionControllerService>()?
+        .status();
+    for key in STATE_KEYS {
+        let key_expr = KeyExpr::try_from(key)?;
+        if !query.key_expr().intersects(&key_expr) {
+            continue;
+        }
+        match state_value(key, &status) {
+            Ok(value) => {
+                query
+                    .reply(Ok(Sample::new(key_expr, serde_json::to_string(&value)?)))
+                    .res()
+                    .await
+                    .map_err(HopperError::ZenohError)?;
+            }
+            Err(error) => warn!("Failed to read state {}: {}", key, error),
+        }
+    }
+    Ok(())
+}
+
+fn state_value(key: &str, status: &MotionControllerStatus) -> anyhow::Result<Value> {
+    let ioc_container = IocContainer::global_instance();
+    let value = match key {
+        HOPPER_STATE_BODY_STATE => serde_json::to_value(status.body_state)?,
+        HOPPER_STATE_BODY_PHASE => serde_json::to_value(status.body_phase)?,
+        HOPPER_STATE_MOVE_COMMAND => serde_json::to_value(status.move_command)?,
+        HOPPER_STATE_FACE_ANIMATION => face_animation_state(
+            ioc_container
+                .service::<FaceController>()?
+                .get_last_animation()
+                .as_ref(),
+        ),
+        HOPPER_STATE_LIDAR_ACTIVE => {
+            json!(ioc_container
+                .service::<LidarServiceController>()?
+                .is_active())
+        }
+        HOPPER_STATE_HIGH_FIVE_ACTIVE => {
+            json!(ioc_container
+                .service::<HighFiveServiceController>()?
+                .is_active())
+        }
+        HOPPER_STATE_DIAGNOSTICS => ioc_container
+            .service::<DiagnosticsService>()?
+            .latest_json()?
+            .unwrap_or(Value::Null),
+        _ => anyhow::bail!("Unknown state key {}", key),
+    };
+    Ok(value)
+}
+
+fn face_animation_state(animation: Option<&Animation>) -> Value {
+    match animation {
+        Some(animation) => json!({
+            "animation": animation.name(),
+            "color": animation.color(),
+        }),
+        None => json!({ "animation": null, "color": null }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::face::driver::RED;
+
+    #[test]
+    fn face_animation_state_has_name_and_color() {
+        let state = face_animation_state(Some(&Animation::Breathing(RED)));
+        assert_eq!(state["animation"], "breathing");
+        assert!(state["color"]["red"].as_u64().unwrap() > 0);
+        let state = face_animation_state(Some(&Animation::CycleAllColors));
+        assert!(state["color"].is_null());
+    }
+}
@@ -9,8 +9,29 @@ pub const REMOTE_CONTROL_SUBSCRIBER: &str = "remote-control/gamepad";
 pub const WALKING_CONFIG_SUBSCRIBER: &str = "hopper/command/simple/walking_config";
 pub const COMPLIANCE_SLOPE_SUBSCRIBER: &str = "hopper/command/config/compliance_slope";
 pub const BODY_MOTOR_SPEED_SUBSCRIBER: &str = "hopper/command/config/motor_speed";
+/// body config as TOML
+pub const BODY_CONFIG_SUBSCRIBER: &str = "hopper/command/config/body";
+/// stance as TOML. Last segment is the stance name such as `relaxed`
+pub const STANCE_CONFIG_SUBSCRIBER: &str = "hopper/command/config/stance/*";
+
+/// hopper.command.v1.HopperCommand as protobuf
+pub const HOPPER_COMMAND_V1: &str = "hopper/command/v1";
+/// hopper.command.v1.HopperCommand as JSON
+pub const HOPPER_COMMAND_V1_JSON: &str = "hopper/command/v1/json";
+
+/// latches the emergency stop. Payload is ignored
+pub const HOPPER_ESTOP: &str = "hopper/command/estop";
+pub const HOPPER_ESTOP_CLEAR: &str = "hopper/command/estop/clear";
 
 pub const HOPPER_WALKING_CONFIG_PUBLISHER: &str = "hopper/status/simple/walking_config";
+pub const HOPPER_OBSTACLE_GUARD_STATUS: &str = "hopper/status/obstacle_guard";
+pub const HOPPER_COMMAND_WATCHDOG_STATUS: &str = "hopper/status/command_watchdog";
+pub const HOPPER_BODY_PHASE_STATUS: &str = "hopper/status/body_phase";
+
+// navigation
+pub const NAVIGATION_GOAL_SUBSCRIBER: &str = "hopper/command/navigation/goal";
+pub const NAVIGATION_CANCEL_SUBSCRIBER: &str = "hopper/command/navigation/cancel";
+pub const NAVIGATION_STATUS: &str = "hopper/status/navigation";
 
 // speech
 pub const SPEECH_SAY_SUBSCRIBER: &str = "hopper/command/speech/say";
@@ -22,10 +43,25 @@ pub const SPEECH_PLAY_SOUND_RANDOM_SUBSCRIBER: &str = "hopper/command/speech/pla
 // telemetry
 pub const DIAGNOSTIC_METRICS: &str = "hopper/metrics/diagnostic";
 pub const DIAGNOSTIC_METRICS_JSON: &str = "hopper/metrics/diagnostic/json";
+pub const SERVO_TELEMETRY: &str = "hopper/metrics/servo_telemetry";
+pub const SERVO_TELEMETRY_JSON: &str = "hopper/metrics/servo_telemetry/json";
 pub const HOPPER_MOTOR_RATE: &str = "hopper/metrics/motor/rate";
 pub const HOPPER_POSE_FRAMES: &str = "hopper/pose/frames";
+pub const HOPPER_ODOMETRY_POSE: &str = "hopper/odometry/pose";
+pub const HOPPER_ODOMETRY_FRAME: &str = "hopper/odometry/frame";
 pub const HOPPER_CONTROL_LOOP_RATE: &str = "hopper/metrics/control_loop/rate";
 
+// state queryables answered with JSON
+pub const HOPPER_STATE_QUERYABLE: &str = "hopper/state/*";
+pub const HOPPER_STATE_BODY_STATE: &str = "hopper/state/body_state";
+pub const HOPPER_STATE_BODY_PHASE: &str = "hopper/state/body_phase";
+pub const HOPPER_STATE_MOVE_COMMAND: &str = "hopper/state/move_command";
+pub const HOPPER_STATE_WALKING_CONFIG: &str = "hopper/state/walking_config";
+pub const HOPPER_STATE_FACE_ANIMATION: &str = "hopper/state/face_animation";
+pub const HOPPER_STATE_LIDAR_ACTIVE: &str = "hopper/state/lidar_active";
+pub const HOPPER_STATE_HIGH_FIVE_ACTIVE: &str = "hopper/state/high_five_active";
+pub const HOPPER_STATE_DIAGNOSTICS: &str = "hopper/state/diagnostics";
+
 // tracing
 pub const HOPPER_TRACING_FULL: &str = "hopper/tracing/full";
 pub const HOPPER_TRACING_JSON: &str = "hopper/tracing/json";
@@ -35,3 +71,5 @@ pub const HOPPER_OPENAI_COMMAND_SUBSCRIBER: &str = "hopper/openai/simple/text/co
 pub const HOPPER_OPENAI_VOICE_COMMAND_SUBSCRIBER: &str = "audio_to_mqtt/windows/simple";
 pub const OPENAI_DIAGNOSTICS_HISTORY: &str = "hopper/openai/diagnostics/history";
 pub const OPENAI_DIAGNOSTICS_TRANSCRIPT: &str = "hopper/openai/diagnostics/transcript";
+/// accepts `reset` or `inspect`
+pub const OPENAI_MEMORY_COMMAND_SUBSCRIBER: &str = "hopper/openai/memory/command";